@@ -60,3 +60,23 @@ Create the name of the service account to use
 {{- default "default" .Values.serviceAccount.name }}
 {{- end }}
 {{- end }}
+
+{{/*
+Whether the main server terminates TLS
+*/}}
+{{- define "otaflux.tlsEnabled" -}}
+{{- with .Values.otaflux.tls }}
+{{- if and .cert .key }}true{{ end }}
+{{- end }}
+{{- end }}
+
+{{/*
+Probe of the main server, over HTTPS when it terminates TLS
+*/}}
+{{- define "otaflux.probe" -}}
+{{- $probe := deepCopy .probe }}
+{{- if and .tls $probe.httpGet }}
+{{- $_ := set $probe.httpGet "scheme" "HTTPS" }}
+{{- end }}
+{{- toYaml $probe }}
+{{- end }}
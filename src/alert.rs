@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{info, instrument};
+
+/// Timeout of alert webhook deliveries.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Alert raised when a release is halted on its failure rate.
+#[derive(Clone, Debug, Serialize)]
+pub struct HaltAlert {
+    /// Always `release_halted`.
+    pub event: &'static str,
+    pub device: String,
+    pub version: String,
+    /// Release of the default channel the device model fell back to, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+    pub devices: usize,
+    pub failed: usize,
+    /// Percentage of devices that failed to install the release.
+    pub failure_rate: f64,
+}
+
+/// Delivers alerts as JSON `POST` requests to an HTTP endpoint.
+#[derive(Clone, Debug)]
+pub struct AlertWebhook {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl AlertWebhook {
+    /// Creates a webhook delivering alerts to `url`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP client fails to initialize.
+    pub fn new(url: String) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .context("Failed to create alert webhook client")?;
+
+        Ok(Self { client, url })
+    }
+
+    /// Posts an alert to the webhook.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the endpoint doesn't answer
+    /// with a success status.
+    #[instrument(skip(self, alert), fields(url = %self.url))]
+    pub async fn send(&self, alert: &HaltAlert) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(alert)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("Failed to deliver alert to {}", self.url))?;
+
+        info!(device_id = %alert.device, "Delivered alert webhook");
+        Ok(())
+    }
+}
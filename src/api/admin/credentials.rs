@@ -0,0 +1,105 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::time::SystemTime;
+use tracing::{info, instrument};
+
+use crate::api::admin::devices::with_store;
+use crate::api::router::AppState;
+use crate::credential::Credential;
+
+/// A device credential, as returned by the admin API. Token hashes aren't
+/// exposed.
+#[derive(Serialize)]
+pub struct CredentialResponse {
+    /// ID of the credential, or serial of the device it was issued to by
+    /// enrollment.
+    pub id: String,
+    pub devices: Vec<String>,
+    pub serial: Option<String>,
+    /// Whether the credential was issued by enrollment.
+    pub enrolled: bool,
+}
+
+/// Lists the device credentials, loaded from the credentials file, then those
+/// issued by enrollment.
+pub async fn list_credentials_handler(
+    State(app): State<AppState>,
+) -> Json<Vec<CredentialResponse>> {
+    let response = |enrolled: bool| {
+        move |(id, credential): (String, Credential)| CredentialResponse {
+            id,
+            devices: credential.devices,
+            serial: credential.serial,
+            enrolled,
+        }
+    };
+
+    Json(
+        app.credentials
+            .iter()
+            .flat_map(|credentials| {
+                let configured = credentials.list().into_iter().map(response(false));
+                let enrolled = credentials.list_enrolled().into_iter().map(response(true));
+                configured.chain(enrolled).collect::<Vec<_>>()
+            })
+            .collect(),
+    )
+}
+
+/// Revokes a device credential, by ID, else by the serial of an enrolled
+/// device.
+///
+/// Revoking the credential of a device, or the serial of an enrolled device,
+/// also revokes the credential and certificates issued to its serial by
+/// enrollment until it enrolls again with a new enrollment code.
+#[instrument(skip(app))]
+pub async fn revoke_credential_handler(
+    State(app): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(credentials) = &app.credentials else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Credential '{id}' not found"),
+        )
+            .into_response();
+    };
+
+    let removed = credentials.remove(&id);
+    let serial = removed
+        .as_ref()
+        .and_then(|credential| credential.serial.clone())
+        .unwrap_or_else(|| id.clone());
+
+    let revoked_serial = serial.clone();
+    let enrolled = match with_store(app.devices.clone(), move |store| {
+        store.revoke(&revoked_serial, SystemTime::now())
+    })
+    .await
+    {
+        Ok(enrolled) => enrolled,
+        Err(response) => return response,
+    };
+
+    if removed.is_none() && !enrolled {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Credential '{id}' not found"),
+        )
+            .into_response();
+    }
+
+    let device_bound = removed.is_some_and(|credential| credential.serial.is_some());
+    if enrolled || device_bound {
+        credentials.remove_enrolled(&serial);
+        credentials.revoke_serial(&serial);
+    }
+
+    info!(id = %id, serial = %serial, "Device credential revoked");
+    StatusCode::NO_CONTENT.into_response()
+}
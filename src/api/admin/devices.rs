@@ -0,0 +1,182 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, instrument};
+
+use crate::inventory::{
+    run_blocking, DeviceFilter, DeviceRecord, DeviceStore, UpdateReport, UpdateStatus,
+};
+
+/// Filters of the device inventory listing.
+#[derive(Deserialize)]
+pub struct DeviceListParams {
+    pub model: Option<String>,
+    /// Firmware version reported by the devices.
+    pub version: Option<String>,
+}
+
+/// A device of the inventory, as returned by the admin API.
+#[derive(Serialize)]
+pub struct DeviceResponse {
+    pub serial: String,
+    pub model: String,
+    /// Unix timestamp of the last check-in.
+    pub last_seen: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reported_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub served_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Outcome of the last update reported by the device.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_report: Option<ReportResponse>,
+}
+
+/// An update report, as returned by the admin API.
+#[derive(Serialize)]
+pub struct ReportResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_version: Option<String>,
+    pub to_version: String,
+    pub status: UpdateStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    /// Unix timestamp of the report.
+    pub reported_at: u64,
+}
+
+impl From<UpdateReport> for ReportResponse {
+    fn from(report: UpdateReport) -> Self {
+        Self {
+            from_version: report.from_version.map(|v| v.to_string()),
+            to_version: report.to_version.to_string(),
+            status: report.status,
+            error_code: report.error_code,
+            reported_at: unix_secs(report.reported_at),
+        }
+    }
+}
+
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl From<DeviceRecord> for DeviceResponse {
+    fn from(device: DeviceRecord) -> Self {
+        Self {
+            serial: device.serial,
+            model: device.model,
+            last_seen: unix_secs(device.last_seen),
+            reported_version: device.reported_version.map(|v| v.to_string()),
+            served_version: device.served_version.map(|v| v.to_string()),
+            ip: device.ip.map(|ip| ip.to_string()),
+            user_agent: device.user_agent,
+            last_report: device.last_report.map(ReportResponse::from),
+        }
+    }
+}
+
+/// Runs a blocking store operation, turning failures into
+/// `500 Internal Server Error` responses.
+pub(crate) async fn with_store<T, F>(
+    devices: Arc<dyn DeviceStore>,
+    operation: F,
+) -> Result<T, Response>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn DeviceStore) -> anyhow::Result<T> + Send + 'static,
+{
+    run_blocking(devices, operation).await.map_err(|e| {
+        error!(error = ?e, "Device store operation failed");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Device store unavailable",
+        )
+            .into_response()
+    })
+}
+
+/// Lists the devices that checked in, optionally filtered by model and
+/// reported version.
+#[instrument(skip(devices, params))]
+pub async fn list_devices_handler(
+    State(devices): State<Arc<dyn DeviceStore>>,
+    Query(params): Query<DeviceListParams>,
+) -> Response {
+    let version = match params.version.as_deref().map(str::trim) {
+        Some(version) => match Version::parse(version.strip_prefix('v').unwrap_or(version)) {
+            Ok(version) => Some(version),
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid version '{version}': {e}"),
+                )
+                    .into_response();
+            }
+        },
+        None => None,
+    };
+    let filter = DeviceFilter {
+        model: params.model,
+        version,
+    };
+
+    match with_store(devices, move |store| store.list(&filter)).await {
+        Ok(devices) => Json(
+            devices
+                .into_iter()
+                .map(DeviceResponse::from)
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Returns a device of the inventory by serial.
+#[instrument(skip(devices))]
+pub async fn get_device_handler(
+    State(devices): State<Arc<dyn DeviceStore>>,
+    Path(serial): Path<String>,
+) -> Response {
+    let lookup = serial.clone();
+    match with_store(devices, move |store| store.get(&lookup)).await {
+        Ok(Some(device)) => Json(DeviceResponse::from(device)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            format!("No device with serial '{serial}'"),
+        )
+            .into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Returns the update reports of a device, most recent first.
+#[instrument(skip(devices))]
+pub async fn list_device_reports_handler(
+    State(devices): State<Arc<dyn DeviceStore>>,
+    Path(serial): Path<String>,
+) -> Response {
+    match with_store(devices, move |store| store.reports(&serial)).await {
+        Ok(reports) => Json(
+            reports
+                .into_iter()
+                .map(ReportResponse::from)
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(response) => response,
+    }
+}
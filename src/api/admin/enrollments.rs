@@ -0,0 +1,169 @@
+use axum::{
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use tracing::instrument;
+
+use crate::api::admin::devices::{unix_secs, with_store};
+use crate::api::router::AppState;
+use crate::enrollment::{generate_secret, EnrollmentCode, DEFAULT_CODE_TTL};
+use crate::inventory::EnrollmentRecord;
+use crate::rollout::parse_duration;
+
+/// Body of an enrollment code creation request.
+#[derive(Deserialize)]
+pub struct EnrollmentCodeRequest {
+    /// Device IDs the device may enroll as, as globs.
+    pub devices: Vec<String>,
+    /// Lifetime of the code, e.g. `24h`, defaults to 7 days.
+    pub ttl: Option<String>,
+}
+
+/// A pending enrollment code, as returned by the admin API.
+#[derive(Serialize)]
+pub struct EnrollmentCodeResponse {
+    pub serial: String,
+    pub devices: Vec<String>,
+    /// Expiry as a Unix timestamp in seconds.
+    pub expires_at: u64,
+    /// The code itself, only returned when it's created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+/// An enrollment recorded in the device store, as returned by the admin API.
+#[derive(Serialize)]
+pub struct EnrollmentResponse {
+    pub serial: String,
+    pub device: String,
+    /// `token` or `certificate`.
+    pub credential: &'static str,
+    pub certificate_serial: Option<String>,
+    pub enrolled_at: u64,
+    pub revoked_at: Option<u64>,
+}
+
+impl From<EnrollmentRecord> for EnrollmentResponse {
+    fn from(enrollment: EnrollmentRecord) -> Self {
+        Self {
+            credential: if enrollment.certificate_serial.is_some() {
+                "certificate"
+            } else {
+                "token"
+            },
+            serial: enrollment.serial,
+            device: enrollment.model,
+            certificate_serial: enrollment.certificate_serial,
+            enrolled_at: unix_secs(enrollment.enrolled_at),
+            revoked_at: enrollment.revoked_at.map(unix_secs),
+        }
+    }
+}
+
+fn enrollment_disabled() -> Response {
+    (StatusCode::NOT_FOUND, "Enrollment is not enabled").into_response()
+}
+
+/// Lists the devices that enrolled, including revoked ones.
+#[instrument(skip(app))]
+pub async fn list_enrollments_handler(State(app): State<AppState>) -> Response {
+    match with_store(app.devices.clone(), |store| store.enrollments()).await {
+        Ok(enrollments) => Json(
+            enrollments
+                .into_iter()
+                .map(EnrollmentResponse::from)
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Lists the pending enrollment codes, without the codes themselves.
+pub async fn list_enrollment_codes_handler(State(app): State<AppState>) -> Response {
+    let Some(enrollment) = &app.enrollment else {
+        return enrollment_disabled();
+    };
+
+    Json(
+        enrollment
+            .codes
+            .list()
+            .into_iter()
+            .map(|(serial, code)| EnrollmentCodeResponse {
+                serial,
+                devices: code.devices,
+                expires_at: unix_secs(code.expires_at),
+                code: None,
+            })
+            .collect::<Vec<_>>(),
+    )
+    .into_response()
+}
+
+/// Creates a one-time enrollment code for a device serial, replacing any
+/// pending one. The generated code is only returned in this response.
+#[instrument(skip(app, request))]
+pub async fn put_enrollment_code_handler(
+    State(app): State<AppState>,
+    Path(serial): Path<String>,
+    request: Result<Json<EnrollmentCodeRequest>, JsonRejection>,
+) -> Response {
+    let Some(enrollment) = &app.enrollment else {
+        return enrollment_disabled();
+    };
+
+    let request = match request {
+        Ok(Json(request)) => request,
+        Err(rejection) => {
+            return (StatusCode::BAD_REQUEST, rejection.body_text()).into_response();
+        }
+    };
+    if request.devices.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Enrollment codes must allow at least one device",
+        )
+            .into_response();
+    }
+    let ttl = match request.ttl.as_deref().map(parse_duration).transpose() {
+        Ok(ttl) => ttl.unwrap_or(DEFAULT_CODE_TTL),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let code = generate_secret();
+    let pending = EnrollmentCode::new(&code, request.devices, SystemTime::now(), ttl);
+    let response = EnrollmentCodeResponse {
+        serial: serial.clone(),
+        devices: pending.devices.clone(),
+        expires_at: unix_secs(pending.expires_at),
+        code: Some(code),
+    };
+    enrollment.codes.set(&serial, pending);
+
+    (StatusCode::CREATED, Json(response)).into_response()
+}
+
+/// Deletes the pending enrollment code of a device serial.
+#[instrument(skip(app))]
+pub async fn delete_enrollment_code_handler(
+    State(app): State<AppState>,
+    Path(serial): Path<String>,
+) -> Response {
+    let Some(enrollment) = &app.enrollment else {
+        return enrollment_disabled();
+    };
+
+    if enrollment.codes.remove(&serial).is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("No enrollment code for serial '{serial}'"),
+        )
+            .into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
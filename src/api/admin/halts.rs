@@ -0,0 +1,87 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tracing::instrument;
+
+use crate::api::notify::renotify_device;
+use crate::api::router::AppState;
+use crate::firmware_manager::FirmwareManager;
+use crate::halt::Halt;
+
+/// A release halted on its failure rate, as returned by the admin API.
+#[derive(Serialize)]
+pub struct HaltResponse {
+    pub device: String,
+    pub version: String,
+    /// Release of the default channel the device model fell back to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+    pub devices: usize,
+    pub failed: usize,
+    pub failure_rate: f64,
+    /// Unix timestamp of the halt.
+    pub halted_at: u64,
+}
+
+impl HaltResponse {
+    fn new(device: String, halt: &Halt) -> Self {
+        Self {
+            device,
+            version: halt.version.to_string(),
+            previous: halt.previous.as_ref().map(ToString::to_string),
+            devices: halt.devices,
+            failed: halt.failed,
+            failure_rate: halt.failure_rate(),
+            halted_at: halt
+                .halted_at
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Lists the releases halted on their failure rate.
+pub async fn list_halts_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+) -> Json<Vec<HaltResponse>> {
+    Json(
+        manager
+            .halts()
+            .into_iter()
+            .map(|(device, halt)| HaltResponse::new(device, &halt))
+            .collect(),
+    )
+}
+
+/// Lifts the halts of a device, serving its latest release again, and
+/// notifies devices over MQTT. Returns the lifted halts.
+#[instrument(skip(app))]
+pub async fn resume_halt_handler(
+    State(app): State<AppState>,
+    Path(device): Path<String>,
+) -> Response {
+    let halts = app.firmware_manager.resume_release(&device).await;
+    let Some(lowest) = halts.first() else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("No halted release for device '{device}'"),
+        )
+            .into_response();
+    };
+
+    renotify_device(&app, &device, &lowest.version.to_string());
+    Json(
+        halts
+            .iter()
+            .map(|halt| HaltResponse::new(device.clone(), halt))
+            .collect::<Vec<_>>(),
+    )
+    .into_response()
+}
@@ -0,0 +1,49 @@
+pub mod credentials;
+pub mod devices;
+pub mod enrollments;
+pub mod halts;
+pub mod pins;
+pub mod rollbacks;
+pub mod rollouts;
+pub mod yanks;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+/// Rejects admin requests that don't carry the configured bearer token.
+pub async fn require_admin_token(
+    State(token): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|provided| constant_time_eq(provided.trim(), &token));
+
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Compares two secrets without short-circuiting on the first difference.
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
@@ -0,0 +1,74 @@
+use axum::{
+    extract::{rejection::JsonRejection, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::instrument;
+
+use crate::firmware_manager::FirmwareManager;
+use crate::pin::PinRule;
+
+/// A pin rule, as returned by the admin API.
+#[derive(Serialize)]
+pub struct PinResponse {
+    pub id: String,
+    #[serde(flatten)]
+    pub rule: PinRule,
+}
+
+/// Lists all pin rules.
+pub async fn list_pins_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+) -> Json<Vec<PinResponse>> {
+    Json(
+        manager
+            .pins()
+            .into_iter()
+            .map(|(id, rule)| PinResponse { id, rule })
+            .collect(),
+    )
+}
+
+/// Creates or replaces a pin rule.
+#[instrument(skip(manager, rule))]
+pub async fn put_pin_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+    Path(id): Path<String>,
+    rule: Result<Json<PinRule>, JsonRejection>,
+) -> Response {
+    let rule = match rule {
+        Ok(Json(rule)) => rule,
+        Err(rejection) => {
+            return (StatusCode::BAD_REQUEST, rejection.body_text()).into_response();
+        }
+    };
+
+    if let Some(yank) = manager.yanked_pin_target(&rule) {
+        return (
+            StatusCode::CONFLICT,
+            format!(
+                "Pinned release {} of '{}' is yanked",
+                yank.release, yank.device
+            ),
+        )
+            .into_response();
+    }
+
+    manager.set_pin(&id, rule.clone());
+    Json(PinResponse { id, rule }).into_response()
+}
+
+/// Removes a pin rule.
+#[instrument(skip(manager))]
+pub async fn delete_pin_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+    Path(id): Path<String>,
+) -> Response {
+    match manager.remove_pin(&id) {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => (StatusCode::NOT_FOUND, format!("No pin rule '{id}'")).into_response(),
+    }
+}
@@ -0,0 +1,136 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::api::notify::renotify_device;
+use crate::api::router::AppState;
+use crate::rollback::RollbackTarget;
+
+/// Body of a rollback target creation or update.
+#[derive(Deserialize)]
+pub struct RollbackRequest {
+    pub version: String,
+}
+
+/// A rollback target, as returned by the admin API.
+#[derive(Serialize)]
+pub struct RollbackResponse {
+    pub device: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    pub version: String,
+}
+
+impl From<RollbackTarget> for RollbackResponse {
+    fn from(target: RollbackTarget) -> Self {
+        Self {
+            device: target.device,
+            serial: target.serial,
+            version: target.version.to_string(),
+        }
+    }
+}
+
+/// Lists the rollback targets of all devices.
+pub async fn list_rollbacks_handler(State(app): State<AppState>) -> Json<Vec<RollbackResponse>> {
+    Json(
+        app.firmware_manager
+            .rollbacks()
+            .into_iter()
+            .map(RollbackResponse::from)
+            .collect(),
+    )
+}
+
+/// Rolls every device of a model back to an older version.
+#[instrument(skip(app, request))]
+pub async fn put_rollback_handler(
+    State(app): State<AppState>,
+    Path(device): Path<String>,
+    Json(request): Json<RollbackRequest>,
+) -> Response {
+    set_rollback(&app, device, None, &request)
+}
+
+/// Rolls a single device, identified by its serial, back to an older version.
+#[instrument(skip(app, request))]
+pub async fn put_serial_rollback_handler(
+    State(app): State<AppState>,
+    Path((device, serial)): Path<(String, String)>,
+    Json(request): Json<RollbackRequest>,
+) -> Response {
+    set_rollback(&app, device, Some(serial), &request)
+}
+
+/// Removes the rollback target of a model.
+#[instrument(skip(app))]
+pub async fn delete_rollback_handler(
+    State(app): State<AppState>,
+    Path(device): Path<String>,
+) -> Response {
+    remove_rollback(&app, &device, None)
+}
+
+/// Removes the rollback target of a single device.
+#[instrument(skip(app))]
+pub async fn delete_serial_rollback_handler(
+    State(app): State<AppState>,
+    Path((device, serial)): Path<(String, String)>,
+) -> Response {
+    remove_rollback(&app, &device, Some(&serial))
+}
+
+fn set_rollback(
+    app: &AppState,
+    device: String,
+    serial: Option<String>,
+    request: &RollbackRequest,
+) -> Response {
+    let version = request.version.trim();
+    let version = match Version::parse(version.strip_prefix('v').unwrap_or(version)) {
+        Ok(version) => version,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid rollback version '{}': {e}", request.version),
+            )
+                .into_response();
+        }
+    };
+
+    let target = RollbackTarget {
+        device,
+        serial,
+        version,
+    };
+    app.firmware_manager.set_rollback(target.clone());
+
+    // Only model-wide rollbacks have an MQTT topic to announce them on
+    if target.serial.is_none() {
+        renotify_device(app, &target.device, &target.version.to_string());
+    }
+
+    Json(RollbackResponse::from(target)).into_response()
+}
+
+fn remove_rollback(app: &AppState, device: &str, serial: Option<&str>) -> Response {
+    let Some(version) = app.firmware_manager.remove_rollback(device, serial) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("No rollback target for device '{device}'"),
+        )
+            .into_response();
+    };
+
+    if serial.is_none() {
+        renotify_device(app, device, &version.to_string());
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
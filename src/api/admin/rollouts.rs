@@ -0,0 +1,189 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tracing::instrument;
+
+use crate::firmware_manager::FirmwareManager;
+use crate::rollout::Rollout;
+
+/// Body of a rollout creation or update.
+#[derive(Deserialize)]
+pub struct RolloutRequest {
+    pub version: String,
+    pub percentage: u8,
+}
+
+/// A staged rollout, as returned by the admin API.
+#[derive(Serialize)]
+pub struct RolloutResponse {
+    pub device: String,
+    pub version: String,
+    pub percentage: u8,
+    pub halted: bool,
+    /// Ramp step of scheduled rollouts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<ScheduleResponse>,
+}
+
+/// Progress of a scheduled rollout.
+#[derive(Serialize)]
+pub struct ScheduleResponse {
+    pub steps: Vec<u8>,
+    pub interval_secs: u64,
+    /// Index of the current step in `steps`.
+    pub step: usize,
+    /// Unix timestamp of the next step, unless halted or complete.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_step_at: Option<u64>,
+}
+
+impl RolloutResponse {
+    fn new(device: String, rollout: &Rollout) -> Self {
+        let schedule = rollout.progression.as_ref().map(|p| {
+            let has_next = !rollout.halted && p.step + 1 < p.schedule.steps.len();
+            ScheduleResponse {
+                steps: p.schedule.steps.clone(),
+                interval_secs: p.schedule.interval.as_secs(),
+                step: p.step,
+                next_step_at: if has_next {
+                    (p.step_started_at + p.schedule.interval)
+                        .duration_since(UNIX_EPOCH)
+                        .ok()
+                        .map(|at| at.as_secs())
+                } else {
+                    None
+                },
+            }
+        });
+
+        Self {
+            device,
+            version: rollout.version.to_string(),
+            percentage: rollout.percentage,
+            halted: rollout.halted,
+            schedule,
+        }
+    }
+}
+
+fn rollout_not_found(device: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        format!("No rollout for device '{device}'"),
+    )
+        .into_response()
+}
+
+/// Lists the staged rollouts of all devices.
+pub async fn list_rollouts_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+) -> Json<Vec<RolloutResponse>> {
+    Json(
+        manager
+            .rollouts()
+            .into_iter()
+            .map(|(device, rollout)| RolloutResponse::new(device, &rollout))
+            .collect(),
+    )
+}
+
+/// Returns the staged rollout of a device.
+pub async fn get_rollout_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+    Path(device): Path<String>,
+) -> Response {
+    match manager.rollout(&device) {
+        Some(rollout) => Json(RolloutResponse::new(device, &rollout)).into_response(),
+        None => rollout_not_found(&device),
+    }
+}
+
+/// Creates or updates the staged rollout of a device.
+#[instrument(skip(manager, request))]
+pub async fn put_rollout_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+    Path(device): Path<String>,
+    Json(request): Json<RolloutRequest>,
+) -> Response {
+    let version = request.version.trim();
+    let version = match Version::parse(version.strip_prefix('v').unwrap_or(version)) {
+        Ok(version) => version,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid rollout version '{}': {e}", request.version),
+            )
+                .into_response();
+        }
+    };
+
+    if !version.pre.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Invalid rollout version '{}': only stable releases are rolled out",
+                request.version
+            ),
+        )
+            .into_response();
+    }
+
+    if request.percentage > 100 {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Invalid rollout percentage {}: must be between 0 and 100",
+                request.percentage
+            ),
+        )
+            .into_response();
+    }
+
+    let rollout = Rollout::new(version, request.percentage);
+    manager.set_rollout(&device, rollout.clone()).await;
+
+    Json(RolloutResponse::new(device, &rollout)).into_response()
+}
+
+/// Removes the staged rollout of a device, serving it the latest version again.
+#[instrument(skip(manager))]
+pub async fn delete_rollout_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+    Path(device): Path<String>,
+) -> Response {
+    match manager.remove_rollout(&device).await {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => rollout_not_found(&device),
+    }
+}
+
+/// Halts the staged rollout of a device at its current percentage.
+#[instrument(skip(manager))]
+pub async fn halt_rollout_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+    Path(device): Path<String>,
+) -> Response {
+    match manager.halt_rollout(&device).await {
+        Some(rollout) => Json(RolloutResponse::new(device, &rollout)).into_response(),
+        None => rollout_not_found(&device),
+    }
+}
+
+/// Resumes the halted rollout of a device.
+#[instrument(skip(manager))]
+pub async fn resume_rollout_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+    Path(device): Path<String>,
+) -> Response {
+    match manager.resume_rollout(&device).await {
+        Some(rollout) => Json(RolloutResponse::new(device, &rollout)).into_response(),
+        None => rollout_not_found(&device),
+    }
+}
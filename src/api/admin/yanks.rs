@@ -0,0 +1,83 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::api::notify::renotify_device;
+use crate::api::router::AppState;
+use crate::yank::{Yank, YankedRelease};
+
+/// A yanked release, as returned by the admin API.
+#[derive(Serialize)]
+pub struct YankResponse {
+    pub device: String,
+    /// Yanked version or manifest digest.
+    pub release: String,
+}
+
+impl From<Yank> for YankResponse {
+    fn from(yank: Yank) -> Self {
+        Self {
+            device: yank.device,
+            release: yank.release.to_string(),
+        }
+    }
+}
+
+/// Lists the yanked releases of all devices.
+pub async fn list_yanks_handler(State(app): State<AppState>) -> Json<Vec<YankResponse>> {
+    Json(
+        app.firmware_manager
+            .yanks()
+            .into_iter()
+            .map(YankResponse::from)
+            .collect(),
+    )
+}
+
+/// Yanks a version or manifest digest of a device, so that devices fall back
+/// to the previous release, and notifies them over MQTT.
+#[instrument(skip(app))]
+pub async fn put_yank_handler(
+    State(app): State<AppState>,
+    Path((device, release)): Path<(String, String)>,
+) -> Response {
+    let release = match release.parse::<YankedRelease>() {
+        Ok(release) => release,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    if !app.firmware_manager.yank(&device, &release) {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    renotify_device(&app, &device, &release.to_string());
+    StatusCode::CREATED.into_response()
+}
+
+/// Restores a yanked release of a device, and notifies devices over MQTT.
+#[instrument(skip(app))]
+pub async fn delete_yank_handler(
+    State(app): State<AppState>,
+    Path((device, release)): Path<(String, String)>,
+) -> Response {
+    let release = match release.parse::<YankedRelease>() {
+        Ok(release) => release,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    if !app.firmware_manager.unyank(&device, &release) {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Release '{release}' of device '{device}' is not yanked"),
+        )
+            .into_response();
+    }
+
+    renotify_device(&app, &device, &release.to_string());
+    StatusCode::NO_CONTENT.into_response()
+}
@@ -0,0 +1,256 @@
+use axum::{
+    extract::{Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::api::identity::ClientCertificate;
+use crate::channel::Channel;
+use crate::credential::{Credential, Credentials};
+use crate::jwt::{is_jwt, DeviceClaims, JwtVerifier};
+use crate::pin::glob_matches;
+
+/// Request header carrying a device API key, as an alternative to an
+/// `Authorization: Bearer` token.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Verifiers of the credentials required on the device endpoints.
+#[derive(Clone, Debug)]
+pub struct DeviceAuth {
+    /// Per-device API tokens.
+    pub credentials: Option<Arc<Credentials>>,
+    /// Verifier of JWT device tokens.
+    pub jwt: Option<Arc<JwtVerifier>>,
+    /// Release channel served to requests that don't name one.
+    pub default_channel: Channel,
+    /// Device IDs, as globs, client certificates naming none may access.
+    pub certificate_devices: Vec<String>,
+    /// Whether requests must come with a client certificate, whatever other
+    /// credential they carry.
+    pub require_certificate: bool,
+}
+
+/// Device credential a request was authenticated with, inserted as a request
+/// extension by [`require_device_credential`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedCredential {
+    /// ID of the API token, or subject of the JWT.
+    pub id: String,
+    /// Device IDs the request may access, as globs.
+    pub devices: Vec<String>,
+    /// Serial of the device owning the credential.
+    pub serial: Option<String>,
+    /// Most permissive release channel the request may access, unrestricted
+    /// when `None`.
+    pub channel: Option<Channel>,
+    /// Whether requests may claim a serial, as fleet API tokens shared by
+    /// devices do. JWTs without a subject identify no device.
+    pub may_claim_serial: bool,
+}
+
+impl AuthenticatedCredential {
+    fn from_credential(id: String, credential: Credential) -> Self {
+        Self {
+            id,
+            devices: credential.devices,
+            serial: credential.serial,
+            channel: None,
+            may_claim_serial: true,
+        }
+    }
+
+    /// Certificates are bound to the device IDs they name, else to
+    /// `default_devices`.
+    fn from_certificate(certificate: &ClientCertificate, default_devices: &[String]) -> Self {
+        Self {
+            id: certificate.common_name.clone(),
+            devices: certificate.devices_or(default_devices).to_vec(),
+            serial: Some(certificate.common_name.clone()),
+            channel: None,
+            may_claim_serial: false,
+        }
+    }
+
+    fn from_claims(claims: DeviceClaims) -> Self {
+        Self {
+            id: claims.serial.clone().unwrap_or_else(|| "jwt".to_string()),
+            devices: claims.models,
+            serial: claims.serial,
+            channel: claims.channel,
+            may_claim_serial: false,
+        }
+    }
+
+    /// Rejects requests for device IDs the credential isn't bound to.
+    ///
+    /// # Errors
+    ///
+    /// Returns the message of the `403 Forbidden` response when the device ID
+    /// isn't allowed.
+    pub fn authorize(&self, device_id: &str) -> Result<(), String> {
+        let allowed = self
+            .devices
+            .iter()
+            .any(|pattern| glob_matches(pattern, device_id));
+        if allowed {
+            return Ok(());
+        }
+
+        warn!(credential = %self.id, device_id = %device_id, "Device not allowed by credential");
+        Err(format!(
+            "Credential not allowed to access device '{device_id}'"
+        ))
+    }
+
+    /// Rejects requests for release channels more permissive than the
+    /// credential allows.
+    ///
+    /// # Errors
+    ///
+    /// Returns the message of the `403 Forbidden` response when the channel
+    /// isn't allowed.
+    pub fn authorize_channel(&self, channel: Channel) -> Result<(), String> {
+        if self.channel.is_none_or(|allowed| channel <= allowed) {
+            return Ok(());
+        }
+
+        warn!(credential = %self.id, channel = %channel, "Channel not allowed by credential");
+        Err(format!(
+            "Credential not allowed to access channel '{channel}'"
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+struct DeviceQuery {
+    device: Option<String>,
+    channel: Option<Channel>,
+}
+
+/// Returns the bearer token of a request.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+/// Returns the API key of a request.
+fn api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+impl DeviceAuth {
+    /// Authenticates a request with its bearer token, verified as a JWT when
+    /// it has the form of one, with its API key, or else with its client
+    /// certificate.
+    fn authenticate(
+        &self,
+        headers: &HeaderMap,
+        certificate: Option<&ClientCertificate>,
+    ) -> Option<AuthenticatedCredential> {
+        let credential = |token: &str| {
+            let (id, credential) = self.credentials.as_ref()?.authenticate(token)?;
+            Some(AuthenticatedCredential::from_credential(id, credential))
+        };
+
+        match (bearer_token(headers), &self.jwt) {
+            (Some(token), Some(jwt)) if is_jwt(token) => match jwt.verify(token) {
+                Ok(claims) => Some(AuthenticatedCredential::from_claims(claims)),
+                Err(e) => {
+                    debug!(error = %e, "Invalid device token");
+                    None
+                }
+            },
+            (Some(token), _) => credential(token),
+            (None, _) => api_key(headers).and_then(credential).or_else(|| {
+                certificate.map(|certificate| {
+                    AuthenticatedCredential::from_certificate(
+                        certificate,
+                        &self.certificate_devices,
+                    )
+                })
+            }),
+        }
+    }
+}
+
+/// Rejects device requests without a valid credential with
+/// `401 Unauthorized`, requests for a `device` or `channel` the credential
+/// isn't bound to, from a revoked device, or whose credential is bound to
+/// another serial than their client certificate, with `403 Forbidden`, and
+/// requests whose query fails to parse with `400 Bad Request`.
+///
+/// Client certificates verified by the TLS listener authenticate devices too,
+/// restricted to the device IDs they name, see [`ClientCertificate`], and are
+/// required when the listener verifies them.
+pub async fn require_device_credential(
+    State(auth): State<DeviceAuth>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let certificate = request.extensions().get::<ClientCertificate>();
+    if auth.require_certificate && certificate.is_none() {
+        debug!("Missing client certificate");
+        return (StatusCode::UNAUTHORIZED, "Client certificate required").into_response();
+    }
+    if let (Some(certificate), Some(credentials)) = (certificate, &auth.credentials) {
+        if credentials.is_revoked(&certificate.common_name) {
+            warn!(serial = %certificate.common_name, "Revoked device certificate");
+            return (StatusCode::FORBIDDEN, "Device revoked").into_response();
+        }
+    }
+
+    let Some(authenticated) = auth.authenticate(request.headers(), certificate) else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response();
+    };
+
+    // A device can't borrow the credential of another one
+    if let (Some(certificate), Some(serial)) = (certificate, &authenticated.serial) {
+        if *serial != certificate.common_name {
+            warn!(
+                credential = %authenticated.id,
+                serial = %serial,
+                common_name = %certificate.common_name,
+                "Credential serial doesn't match client certificate"
+            );
+            return (
+                StatusCode::FORBIDDEN,
+                "Credential serial doesn't match client certificate",
+            )
+                .into_response();
+        }
+    }
+
+    // Requests the device endpoints would reject can't skip authorization
+    let query = match Query::<DeviceQuery>::try_from_uri(request.uri()) {
+        Ok(Query(query)) => query,
+        Err(rejection) => return rejection.into_response(),
+    };
+    if let Some(device) = &query.device {
+        if let Err(message) = authenticated.authorize(device) {
+            return (StatusCode::FORBIDDEN, message).into_response();
+        }
+    }
+    let channel = query.channel.unwrap_or(auth.default_channel);
+    if let Err(message) = authenticated.authorize_channel(channel) {
+        return (StatusCode::FORBIDDEN, message).into_response();
+    }
+
+    request.extensions_mut().insert(authenticated);
+    next.run(request).await
+}
@@ -0,0 +1,23 @@
+use axum::http::{header, HeaderMap};
+
+/// Returns `true` when the `If-None-Match` request header matches `etag`.
+///
+/// Uses the weak comparison function from RFC 9110: `W/` prefixes are ignored
+/// and `*` matches any current representation. Devices and intermediate caches
+/// can use this to poll cheaply and receive `304 Not Modified`.
+#[must_use]
+pub fn is_not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = strip_weak(etag);
+
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || strip_weak(candidate) == etag)
+}
+
+fn strip_weak(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
@@ -1,85 +1,752 @@
 use axum::{
+    body::Body,
     extract::{Query, State},
-    http::{HeaderMap, HeaderValue, StatusCode},
-    response::IntoResponse,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
-use serde::Deserialize;
+use semver::Version;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tracing::instrument;
+use std::time::SystemTime;
+use tracing::{debug, instrument, warn};
 
-use crate::firmware_manager::FirmwareManager;
+use crate::api::conditional::is_not_modified;
+use crate::api::identity::{ClientIp, DeviceIdentity};
+use crate::api::range::{requested_range, ByteRange};
+use crate::channel::Channel;
+use crate::compression::{ContentEncoding, HEATSHRINK_LOOKAHEAD_BITS, HEATSHRINK_WINDOW_BITS};
+use crate::firmware_manager::{
+    FirmwareBody, FirmwareInfo, FirmwareManager, FirmwareQuery, FirmwareStream,
+};
+use crate::halt::ReleaseHalted;
+use crate::inventory::{run_blocking, DeviceRecord, DeviceStore};
+use crate::registry::VariantNotFound;
+
+/// Request header devices can use to report their running firmware version,
+/// as an alternative to the `current` query parameter.
+pub const FIRMWARE_VERSION_HEADER: &str = "x-firmware-version";
+
+/// Response formats supported by the `/version` endpoint.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    /// Newline-separated `version`, `crc` and `size`, easy to parse on
+    /// constrained devices.
+    #[default]
+    Text,
+    /// Structured JSON document, see [`VersionResponse`].
+    Json,
+}
+
+impl ResponseFormat {
+    /// Negotiates the response format from the `format` query parameter,
+    /// falling back to the `Accept` header and then to plain text.
+    fn negotiate(requested: Option<Self>, headers: &HeaderMap) -> Self {
+        if let Some(format) = requested {
+            return format;
+        }
+
+        let accepts_json = headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| {
+                accept
+                    .split(',')
+                    .filter_map(|media_range| media_range.split(';').next())
+                    .any(|media_type| media_type.trim() == "application/json")
+            });
+
+        if accepts_json {
+            Self::Json
+        } else {
+            Self::Text
+        }
+    }
+}
+
+/// Response header flagging firmware served as a rollback target, older than
+/// the version the device may run.
+pub const FIRMWARE_ROLLBACK_HEADER: &str = "x-firmware-rollback";
+/// Response header carrying the size of the uncompressed firmware.
+pub const FIRMWARE_SIZE_HEADER: &str = "x-firmware-size";
+/// Response header carrying the CRC32 of the uncompressed firmware.
+pub const FIRMWARE_CRC32_HEADER: &str = "x-firmware-crc32";
+/// Response header carrying the SHA-256 of the uncompressed firmware.
+pub const FIRMWARE_SHA256_HEADER: &str = "x-firmware-sha256";
+/// Response header carrying the size of the compressed firmware.
+pub const COMPRESSED_SIZE_HEADER: &str = "x-compressed-size";
+/// Response header carrying the CRC32 of the compressed firmware.
+pub const COMPRESSED_CRC32_HEADER: &str = "x-compressed-crc32";
+/// Response header carrying the SHA-256 of the compressed firmware.
+pub const COMPRESSED_SHA256_HEADER: &str = "x-compressed-sha256";
+/// Response header carrying the window size, as a power of two, of heatshrink
+/// compressed firmware.
+pub const HEATSHRINK_WINDOW_HEADER: &str = "x-heatshrink-window";
+/// Response header carrying the lookahead size, as a power of two, of
+/// heatshrink compressed firmware.
+pub const HEATSHRINK_LOOKAHEAD_HEADER: &str = "x-heatshrink-lookahead";
 
 #[derive(Deserialize)]
 pub struct DeviceParams {
     device: Option<String>,
+    /// Firmware version currently running on the device.
+    current: Option<String>,
+    /// Explicit response format, takes precedence over the `Accept` header.
+    format: Option<ResponseFormat>,
+    /// Explicit firmware encoding, takes precedence over `Accept-Encoding`.
+    encoding: Option<ContentEncoding>,
+    /// Component of a multi-layer artifact, by name or media type. Defaults
+    /// to the primary firmware binary (first layer).
+    component: Option<String>,
+    /// Hardware variant selecting a manifest from a multi-platform release.
+    hw: Option<String>,
+    /// Release channel, defaults to the server default channel.
+    channel: Option<Channel>,
+    /// Serial number identifying a single device of the model, see
+    /// [`DeviceIdentity::serial`].
+    serial: Option<String>,
+    /// Group of the device (site, rack, ...), matched by pin rules.
+    group: Option<String>,
+}
+
+impl DeviceParams {
+    /// Builds the firmware query for the given device model, identifying the
+    /// device itself from its credentials, query or headers.
+    fn query(&self, device: &str, identity: &DeviceIdentity) -> FirmwareQuery {
+        FirmwareQuery::new(device)
+            .with_hw(self.hw.clone())
+            .with_channel(self.channel)
+            .with_serial(identity.serial(self.serial.as_deref()))
+            .with_group(self.group.clone())
+    }
+}
+
+/// Builds the `404 Not Found` message for a firmware lookup failure,
+/// distinguishing releases with no manifest for the requested hardware.
+fn not_found_message(query: &FirmwareQuery, error: &anyhow::Error) -> String {
+    match error.downcast_ref::<VariantNotFound>() {
+        Some(VariantNotFound { hw }) => format!(
+            "No firmware for hardware variant '{hw}' of device '{}'",
+            query.device_id
+        ),
+        None => format!("No firmware for device '{}'", query.device_id),
+    }
+}
+
+/// Records the check-in of an identified device in the inventory. Devices are
+/// served even when it fails.
+async fn record_check_in(devices: Arc<dyn DeviceStore>, check_in: DeviceRecord) {
+    let serial = check_in.serial.clone();
+    if let Err(e) = run_blocking(devices, move |store| store.check_in(check_in)).await {
+        warn!(%serial, error = ?e, "Failed to record device check-in");
+    }
+}
+
+/// Inserts firmware metadata headers, skipping values that aren't valid
+/// header values.
+fn insert_metadata<const N: usize>(headers: &mut HeaderMap, entries: [(&'static str, String); N]) {
+    for (name, value) in entries {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// JSON document returned by `/version` when JSON is negotiated.
+#[derive(Serialize)]
+pub struct VersionResponse {
+    pub version: String,
+    pub crc32: u32,
+    pub sha256: String,
+    pub size: usize,
+    pub manifest_digest: String,
+    /// Path to download the firmware binary from.
+    pub url: String,
+    /// Every component of the artifact, starting with the primary firmware.
+    pub components: Vec<ComponentResponse>,
+    /// Set when the firmware is a rollback target, to be installed even if
+    /// older than the running version.
+    pub rollback: bool,
+}
+
+/// A component of the artifact listed in [`VersionResponse`].
+#[derive(Serialize)]
+pub struct ComponentResponse {
+    pub name: String,
+    pub media_type: String,
+    pub crc32: u32,
+    pub sha256: String,
+    pub size: usize,
+    /// Path to download the component binary from.
+    pub url: String,
+}
+
+/// Percent-encodes a query parameter value, e.g. a component media type.
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(b).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+impl VersionResponse {
+    fn new(query: &FirmwareQuery, fw: &FirmwareInfo, rollback: bool) -> Self {
+        let mut url = format!("/firmware?device={}", encode_query_value(&query.device_id));
+        if let Some(hw) = &query.hw {
+            url = format!("{url}&hw={}", encode_query_value(hw));
+        }
+        if let Some(channel) = query.channel {
+            url = format!("{url}&channel={channel}");
+        }
+        if let Some(serial) = &query.serial {
+            url = format!("{url}&serial={}", encode_query_value(serial));
+        }
+        if let Some(group) = &query.group {
+            url = format!("{url}&group={}", encode_query_value(group));
+        }
+        if let Some(current) = &query.current {
+            url = format!("{url}&current={}", encode_query_value(&current.to_string()));
+        }
+        let components = fw
+            .components
+            .iter()
+            .map(|c| ComponentResponse {
+                name: c.name.clone(),
+                media_type: c.media_type.clone(),
+                crc32: c.crc,
+                sha256: c.sha256.clone(),
+                size: c.size,
+                url: format!("{url}&component={}", encode_query_value(&c.name)),
+            })
+            .collect();
+
+        Self {
+            version: fw.version.to_string(),
+            crc32: fw.crc,
+            sha256: fw.sha256.clone(),
+            size: fw.size,
+            manifest_digest: fw.manifest_digest.clone(),
+            url,
+            components,
+            rollback,
+        }
+    }
+}
+
+/// Resolves the firmware version reported by the device, either from the
+/// `current` query parameter or the `X-Firmware-Version` header.
+///
+/// Returns `Ok(None)` when the device didn't report a version, and an error
+/// message when the reported value is not a valid semantic version.
+fn reported_version(params: &DeviceParams, headers: &HeaderMap) -> Result<Option<Version>, String> {
+    let reported = params
+        .current
+        .as_deref()
+        .or_else(|| {
+            headers
+                .get(FIRMWARE_VERSION_HEADER)
+                .and_then(|v| v.to_str().ok())
+        })
+        .map(str::trim)
+        .filter(|v| !v.is_empty());
+
+    let Some(reported) = reported else {
+        return Ok(None);
+    };
+
+    Version::parse(reported.strip_prefix('v').unwrap_or(reported))
+        .map(Some)
+        .map_err(|e| format!("Invalid current firmware version '{reported}': {e}"))
 }
 
 /// Returns the firmware version, CRC32, and size for the specified device.
-#[instrument(skip(manager, params))]
+///
+/// When the device reports its running version (`current` query parameter or
+/// `X-Firmware-Version` header), it is compared with the latest firmware using
+/// semver rules and `204 No Content` is returned if no update applies.
+///
+/// The plain-text format is the default, with one extra `name crc size` line
+/// per additional component of multi-layer artifacts. A JSON document with
+/// additional metadata and every component is returned when `format=json` is
+/// passed or the `Accept` header asks for `application/json`.
+///
+/// The release channel (`stable`, `beta` or `nightly`) is selected with the
+/// `channel` query parameter, falling back to the server default channel.
+///
+/// Devices passing their `serial` are bucketed into staged rollouts, see
+/// [`Rollout`](crate::rollout::Rollout).
+///
+/// Pin rules matching the device ID, `serial` or `group` hold devices on a
+/// release, see [`PinRule`](crate::pin::PinRule).
+///
+/// Devices reporting their running version get the next hop of their upgrade
+/// path, see [`UpgradePaths`](crate::upgrade_path::UpgradePaths).
+///
+/// Devices are never offered a version older than the one they run, unless an
+/// operator set a rollback target for their model or serial. Rollback targets
+/// are flagged with the `X-Firmware-Rollback` header and the `rollback` JSON
+/// field.
+///
+/// Each request of an identified device is recorded as a check-in in the
+/// device inventory, see [`DeviceStore`].
+///
+/// Releases published as an image index with one manifest per board revision
+/// or chip are resolved with the `hw` query parameter, and `404 Not Found` is
+/// returned when no manifest matches it.
+///
+/// The response carries an `ETag` derived from the manifest digest, and
+/// `If-None-Match` requests for an unchanged artifact get `304 Not Modified`.
+#[instrument(skip(manager, devices, params, identity, client_ip, request_headers))]
 pub async fn version_handler(
     State(manager): State<Arc<FirmwareManager>>,
+    State(devices): State<Arc<dyn DeviceStore>>,
     Query(params): Query<DeviceParams>,
-) -> impl IntoResponse {
+    identity: DeviceIdentity,
+    client_ip: Option<Extension<ClientIp>>,
+    request_headers: HeaderMap,
+) -> Response {
     let mut headers = HeaderMap::new();
     headers.insert(
-        axum::http::header::CONTENT_TYPE,
+        header::CONTENT_TYPE,
         HeaderValue::from_static("text/plain; charset=utf-8"),
     );
 
-    let Some(device) = params.device.filter(|d| !d.is_empty()) else {
+    let Some(device) = params.device.clone().filter(|d| !d.is_empty()) else {
         return (
             StatusCode::BAD_REQUEST,
             headers,
             "Missing required query parameter: 'device'".to_string(),
-        );
+        )
+            .into_response();
     };
 
-    if let Ok(fw) = manager.get_firmware(&device).await {
-        let body = format!("{}\n{}\n{}", fw.version, fw.crc, fw.size);
-        (StatusCode::OK, headers, body)
-    } else {
-        let body = format!("No firmware for device '{device}'");
-        (StatusCode::NOT_FOUND, headers, body)
+    let current = match reported_version(&params, &request_headers) {
+        Ok(current) => current,
+        Err(message) => return (StatusCode::BAD_REQUEST, headers, message).into_response(),
+    };
+
+    let query = params
+        .query(&device, &identity)
+        .with_current(current.clone());
+    let result = manager.get_firmware(&query).await;
+
+    if let Some(serial) = query.serial.clone() {
+        let check_in = DeviceRecord {
+            serial,
+            model: device.clone(),
+            last_seen: SystemTime::now(),
+            reported_version: current.clone(),
+            served_version: result.as_ref().ok().map(|fw| fw.version.clone()),
+            ip: client_ip.map(|Extension(ClientIp(ip))| ip),
+            user_agent: request_headers
+                .get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            last_report: None,
+        };
+        record_check_in(devices, check_in).await;
+    }
+
+    let fw = match result {
+        Ok(fw) => fw,
+        // Devices whose releases are all halted keep the version they run
+        Err(e) if e.is::<ReleaseHalted>() => {
+            debug!(error = %e, "No release to offer");
+            return (StatusCode::NO_CONTENT, headers).into_response();
+        }
+        Err(e) => {
+            let body = not_found_message(&query, &e);
+            return (StatusCode::NOT_FOUND, headers, body).into_response();
+        }
+    };
+
+    let etag = fw.etag();
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        headers.insert(header::ETAG, value);
+    }
+
+    // Devices are never offered a version older than the one they run,
+    // unless it is their rollback target
+    let rollback = manager.is_rollback(&query, &fw.version);
+    let up_to_date = |current: Version| {
+        if rollback {
+            fw.version == current
+        } else {
+            !fw.is_update_for(&current)
+        }
+    };
+    if current.is_some_and(up_to_date) {
+        debug!(version = %fw.version, "Device firmware is up to date");
+        return (StatusCode::NO_CONTENT, headers).into_response();
+    }
+
+    if rollback {
+        headers.insert(FIRMWARE_ROLLBACK_HEADER, HeaderValue::from_static("true"));
+    }
+
+    if is_not_modified(&request_headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    match ResponseFormat::negotiate(params.format, &request_headers) {
+        ResponseFormat::Text => {
+            let body = std::iter::once(format!("{}\n{}\n{}", fw.version, fw.crc, fw.size))
+                .chain(
+                    fw.components
+                        .iter()
+                        .skip(1)
+                        .map(|c| format!("{} {} {}", c.name, c.crc, c.size)),
+                )
+                .collect::<Vec<_>>()
+                .join("\n");
+            (StatusCode::OK, headers, body).into_response()
+        }
+        ResponseFormat::Json => {
+            headers.remove(header::CONTENT_TYPE);
+            let body = VersionResponse::new(&query, &fw, rollback);
+            (StatusCode::OK, headers, Json(body)).into_response()
+        }
     }
 }
 
 /// Returns the firmware binary for the specified device.
-#[instrument(skip(manager, params))]
+///
+/// Supports resumable downloads through single `Range` requests (answered with
+/// `206 Partial Content`), guarded by `If-Range` against the firmware `ETag`.
+/// `If-None-Match` requests for the firmware already held by the device get
+/// `304 Not Modified`. `HEAD` requests are served by the same handler without
+/// a body.
+///
+/// Firmware can be served gzip, zstd or heatshrink compressed, negotiated
+/// through the `encoding` query parameter or the `Accept-Encoding` header.
+/// Size and checksums of both the raw and compressed firmware are returned in
+/// `X-Firmware-*` and `X-Compressed-*` headers.
+///
+/// Components of multi-layer artifacts (bootloader, partition table,
+/// filesystem, ...) are selected with the `component` query parameter, by
+/// layer title or media type. The hardware variant is selected with the `hw`
+/// query parameter, and the running version with `current` or the
+/// `X-Firmware-Version` header, see [`version_handler`]. Firmware older than
+/// the running version is only served when it is the rollback target of the
+/// device, and `204 No Content` is returned otherwise.
+#[allow(clippy::too_many_lines)]
+#[instrument(skip(manager, params, identity, request_headers))]
 pub async fn firmware_handler(
     State(manager): State<Arc<FirmwareManager>>,
     Query(params): Query<DeviceParams>,
-) -> impl IntoResponse {
+    identity: DeviceIdentity,
+    request_headers: HeaderMap,
+) -> Response {
     let mut headers = HeaderMap::new();
 
-    let Some(device) = params.device.filter(|d| !d.is_empty()) else {
+    let Some(device) = params.device.clone().filter(|d| !d.is_empty()) else {
         headers.insert(
-            axum::http::header::CONTENT_TYPE,
+            header::CONTENT_TYPE,
             HeaderValue::from_static("text/plain; charset=utf-8"),
         );
         return (
             StatusCode::BAD_REQUEST,
             headers,
-            "Missing required query parameter: 'device'".into(),
-        );
+            "Missing required query parameter: 'device'",
+        )
+            .into_response();
+    };
+
+    let current = match reported_version(&params, &request_headers) {
+        Ok(current) => current,
+        Err(message) => {
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+            return (StatusCode::BAD_REQUEST, headers, message).into_response();
+        }
     };
 
-    if let Ok(fw) = manager.get_firmware(&device).await {
+    let encoding = params.encoding.unwrap_or_else(|| {
+        request_headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .map_or(ContentEncoding::Identity, ContentEncoding::negotiate)
+    });
+
+    // Raw full downloads of the primary firmware can start before the
+    // artifact is fully fetched from the registry; ranges, compressed
+    // variants and other components need the complete artifact.
+    let streamable = encoding == ContentEncoding::Identity
+        && params.component.is_none()
+        && !request_headers.contains_key(header::RANGE);
+    let query = params
+        .query(&device, &identity)
+        .with_current(current.clone());
+    let firmware = if streamable {
+        manager.get_firmware_stream(&query).await
+    } else {
+        manager
+            .get_firmware(&query)
+            .await
+            .map(FirmwareBody::Buffered)
+    };
+
+    // Devices are never served a version older than the one they run, unless
+    // it is their rollback target
+    if let (Some(current), Ok(body)) = (&current, &firmware) {
+        let version = body.version();
+        if version < current && !manager.is_rollback(&query, version) {
+            debug!(version = %version, current = %current, "Refusing firmware downgrade");
+            return StatusCode::NO_CONTENT.into_response();
+        }
+    }
+
+    let fw = match firmware {
+        Ok(FirmwareBody::Buffered(fw)) => fw,
+        Ok(FirmwareBody::Streaming(stream)) => {
+            return streaming_response(stream, &request_headers);
+        }
+        Err(e) if e.is::<ReleaseHalted>() => {
+            debug!(error = %e, "No release to serve");
+            return StatusCode::NO_CONTENT.into_response();
+        }
+        Err(e) => {
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+            return (
+                StatusCode::NOT_FOUND,
+                headers,
+                not_found_message(&query, &e),
+            )
+                .into_response();
+        }
+    };
+
+    let component = match params.component.as_deref() {
+        Some(selector) => fw.component(selector),
+        None => fw.components.first(),
+    };
+    let Some(component) = component else {
         headers.insert(
-            axum::http::header::CONTENT_TYPE,
-            HeaderValue::from_static("application/octet-stream"),
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
         );
-        (StatusCode::OK, headers, fw.binary.clone())
+        let body = format!(
+            "No component '{}' in firmware for device '{device}'",
+            params.component.unwrap_or_default()
+        );
+        return (StatusCode::NOT_FOUND, headers, body).into_response();
+    };
+
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    insert_metadata(
+        &mut headers,
+        [
+            (FIRMWARE_SIZE_HEADER, component.size.to_string()),
+            (FIRMWARE_CRC32_HEADER, component.crc.to_string()),
+            (FIRMWARE_SHA256_HEADER, component.sha256.clone()),
+        ],
+    );
+
+    let body = if encoding == ContentEncoding::Identity {
+        component.binary.clone()
     } else {
+        let compressed = match manager.get_compressed(component, encoding).await {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                warn!(error = ?e, "Failed to compress firmware");
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to compress firmware for device '{device}'"),
+                )
+                    .into_response();
+            }
+        };
         headers.insert(
-            axum::http::header::CONTENT_TYPE,
-            HeaderValue::from_static("text/plain; charset=utf-8"),
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+        insert_metadata(
+            &mut headers,
+            [
+                (COMPRESSED_SIZE_HEADER, compressed.data.len().to_string()),
+                (COMPRESSED_CRC32_HEADER, compressed.crc.to_string()),
+                (COMPRESSED_SHA256_HEADER, compressed.sha256.clone()),
+            ],
         );
-        (
-            StatusCode::NOT_FOUND,
+        if encoding == ContentEncoding::Heatshrink {
+            insert_metadata(
+                &mut headers,
+                [
+                    (HEATSHRINK_WINDOW_HEADER, HEATSHRINK_WINDOW_BITS.to_string()),
+                    (
+                        HEATSHRINK_LOOKAHEAD_HEADER,
+                        HEATSHRINK_LOOKAHEAD_BITS.to_string(),
+                    ),
+                ],
+            );
+        }
+        compressed.data.clone()
+    };
+
+    let etag = fw.component_etag(component, encoding);
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        headers.insert(header::ETAG, value);
+    }
+
+    if is_not_modified(&request_headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    match requested_range(&request_headers, &etag, body.len()) {
+        ByteRange::Full => {
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            );
+            (StatusCode::OK, headers, body).into_response()
+        }
+        ByteRange::Partial(range) => {
+            let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, body.len());
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            );
+            if let Ok(value) = HeaderValue::from_str(&content_range) {
+                headers.insert(header::CONTENT_RANGE, value);
+            }
+            (StatusCode::PARTIAL_CONTENT, headers, body.slice(range)).into_response()
+        }
+        ByteRange::Unsatisfiable => {
+            let content_range = format!("bytes */{}", body.len());
+            if let Ok(value) = HeaderValue::from_str(&content_range) {
+                headers.insert(header::CONTENT_RANGE, value);
+            }
+            (StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response()
+        }
+    }
+}
+
+/// Builds the response for firmware streamed from the registry while it is
+/// being downloaded.
+///
+/// Checksums are only known once the download completes, so only the size is
+/// advertised. Subsequent requests are served from the cache with all headers.
+fn streaming_response(firmware: FirmwareStream, request_headers: &HeaderMap) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+
+    let etag = firmware.etag();
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        headers.insert(header::ETAG, value);
+    }
+
+    if is_not_modified(request_headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(firmware.size));
+    insert_metadata(
+        &mut headers,
+        [(FIRMWARE_SIZE_HEADER, firmware.size.to_string())],
+    );
+
+    (StatusCode::OK, headers, Body::from_stream(firmware.stream)).into_response()
+}
+
+/// Response header carrying the version of the reconstructed firmware.
+pub const TARGET_VERSION_HEADER: &str = "x-target-version";
+/// Response header carrying the CRC32 of the reconstructed firmware.
+pub const TARGET_CRC32_HEADER: &str = "x-target-crc32";
+/// Response header carrying the SHA-256 of the reconstructed firmware.
+pub const TARGET_SHA256_HEADER: &str = "x-target-sha256";
+/// Response header carrying the size of the reconstructed firmware.
+pub const TARGET_SIZE_HEADER: &str = "x-target-size";
+
+/// Returns a binary patch (bsdiff) from the device's current firmware to the
+/// latest version.
+///
+/// The device must report its running version, see [`version_handler`]. The
+/// version, CRC32, SHA-256 and size of the reconstructed image are returned in
+/// `X-Target-*` headers so the device can verify the patched firmware before
+/// booting it. `204 No Content` is returned if no update applies.
+#[instrument(skip(manager, params, identity, request_headers))]
+pub async fn delta_handler(
+    State(manager): State<Arc<FirmwareManager>>,
+    Query(params): Query<DeviceParams>,
+    identity: DeviceIdentity,
+    request_headers: HeaderMap,
+) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+
+    let Some(device) = params.device.clone().filter(|d| !d.is_empty()) else {
+        return (
+            StatusCode::BAD_REQUEST,
             headers,
-            format!("No firmware for device '{device}'").into(),
+            "Missing required query parameter: 'device'".to_string(),
         )
-    }
+            .into_response();
+    };
+
+    let current = match reported_version(&params, &request_headers) {
+        Ok(Some(current)) => current,
+        Ok(None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                headers,
+                "Missing current firmware version: set the 'current' query parameter or the \
+                 'X-Firmware-Version' header"
+                    .to_string(),
+            )
+                .into_response();
+        }
+        Err(message) => return (StatusCode::BAD_REQUEST, headers, message).into_response(),
+    };
+
+    let delta = match manager
+        .get_delta(&params.query(&device, &identity), &current)
+        .await
+    {
+        Ok(Some(delta)) => delta,
+        Ok(None) => return (StatusCode::NO_CONTENT, headers).into_response(),
+        Err(e) => {
+            warn!(error = ?e, "Failed to get firmware delta");
+            let body = format!("No firmware delta for device '{device}' from version {current}");
+            return (StatusCode::NOT_FOUND, headers, body).into_response();
+        }
+    };
+
+    let target = &delta.target;
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    insert_metadata(
+        &mut headers,
+        [
+            (TARGET_VERSION_HEADER, target.version.to_string()),
+            (TARGET_CRC32_HEADER, target.crc.to_string()),
+            (TARGET_SHA256_HEADER, target.sha256.clone()),
+            (TARGET_SIZE_HEADER, target.size.to_string()),
+        ],
+    );
+
+    (StatusCode::OK, headers, delta.patch.clone()).into_response()
 }
 
 /// Returns 200 OK if the server is healthy.
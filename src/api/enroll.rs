@@ -0,0 +1,218 @@
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use tracing::{info, instrument, warn};
+
+use crate::api::admin::devices::with_store;
+use crate::api::identity::{ClientCertificate, ClientIp};
+use crate::api::router::AppState;
+use crate::credential::{hash_token, Credential};
+use crate::enrollment::generate_secret;
+use crate::inventory::{DeviceRecord, EnrollmentRecord};
+use crate::pin::glob_matches;
+
+/// Body of an enrollment request.
+#[derive(Deserialize)]
+pub struct EnrollRequest {
+    /// Device model (repository name) the device enrolls as.
+    pub device: String,
+    /// Serial of the device, required with an enrollment code.
+    pub serial: Option<String>,
+    /// One-time enrollment code, unless the device presents a factory
+    /// certificate.
+    pub code: Option<String>,
+    /// PEM certificate signing request, to be issued a certificate rather
+    /// than a token.
+    pub csr: Option<String>,
+}
+
+/// Credentials issued to an enrolled device.
+#[derive(Serialize)]
+pub struct EnrollResponse {
+    pub serial: String,
+    pub device: String,
+    /// API token to send in the `Authorization: Bearer` header.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// PEM certificate signed by the enrollment CA.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<String>,
+    /// PEM certificate of the enrollment CA.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_certificate: Option<String>,
+}
+
+/// Enrolls a device, issuing it a per-device API token, or a certificate
+/// when it sends a certificate signing request.
+///
+/// Devices prove their identity with a one-time enrollment code created
+/// through the admin API for their serial, or with a factory certificate
+/// verified by the TLS listener, whose common name is their serial and which
+/// must allow the device ID, see [`ClientCertificate::devices_or`].
+/// Re-enrolling replaces the credentials previously issued to the device,
+/// and enrolling with a code lifts a revocation. The device is recorded in
+/// the device inventory.
+#[instrument(skip(app, certificate, client_ip, headers, request))]
+#[allow(clippy::too_many_lines)]
+pub async fn enroll_handler(
+    State(app): State<AppState>,
+    certificate: Option<Extension<ClientCertificate>>,
+    client_ip: Option<Extension<ClientIp>>,
+    headers: HeaderMap,
+    request: Result<Json<EnrollRequest>, JsonRejection>,
+) -> Response {
+    let (Some(enrollment), Some(credentials)) = (&app.enrollment, &app.credentials) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let request = match request {
+        Ok(Json(request)) => request,
+        Err(rejection) => {
+            return (StatusCode::BAD_REQUEST, rejection.body_text()).into_response();
+        }
+    };
+
+    let device = request.device.trim().to_string();
+    if device.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Missing required field: 'device'").into_response();
+    }
+    if request.csr.is_some() && enrollment.authority.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Certificate enrollment is not configured",
+        )
+            .into_response();
+    }
+
+    let now = SystemTime::now();
+    let (serial, method) = match (request.code.as_deref(), certificate) {
+        (Some(code), _) => {
+            let Some(serial) = request
+                .serial
+                .as_deref()
+                .map(str::trim)
+                .filter(|serial| !serial.is_empty())
+            else {
+                return (StatusCode::BAD_REQUEST, "Missing required field: 'serial'")
+                    .into_response();
+            };
+            if !enrollment.codes.redeem(serial, code, &device, now) {
+                warn!(serial = %serial, device_id = %device, "Invalid enrollment code");
+                return (StatusCode::FORBIDDEN, "Invalid enrollment code").into_response();
+            }
+            (serial.to_string(), "code")
+        }
+        (None, Some(Extension(certificate))) => {
+            if credentials.is_revoked(&certificate.common_name) {
+                warn!(serial = %certificate.common_name, "Revoked device enrolling");
+                return (StatusCode::FORBIDDEN, "Device revoked").into_response();
+            }
+            let allowed = certificate
+                .devices_or(&app.certificate_devices)
+                .iter()
+                .any(|pattern| glob_matches(pattern, &device));
+            if !allowed {
+                warn!(
+                    serial = %certificate.common_name,
+                    device_id = %device,
+                    "Device not allowed by factory certificate"
+                );
+                return (
+                    StatusCode::FORBIDDEN,
+                    format!("Certificate not allowed to enroll as device '{device}'"),
+                )
+                    .into_response();
+            }
+            (certificate.common_name, "certificate")
+        }
+        (None, None) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                "Missing enrollment code or client certificate",
+            )
+                .into_response();
+        }
+    };
+
+    let mut response = EnrollResponse {
+        serial: serial.clone(),
+        device: device.clone(),
+        token: None,
+        certificate: None,
+        ca_certificate: None,
+    };
+    let mut record = EnrollmentRecord {
+        serial: serial.clone(),
+        model: device.clone(),
+        token_sha256: None,
+        certificate_serial: None,
+        enrolled_at: now,
+        revoked_at: None,
+    };
+
+    if let (Some(csr), Some(authority)) = (request.csr.as_deref(), &enrollment.authority) {
+        match authority.sign(csr, &serial, &device, now) {
+            Ok(issued) => {
+                record.certificate_serial = Some(issued.serial_number);
+                response.certificate = Some(issued.pem);
+                response.ca_certificate = Some(authority.certificate_pem().to_string());
+            }
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        }
+    } else {
+        let token = generate_secret();
+        record.token_sha256 = Some(hash_token(&token));
+        response.token = Some(token);
+    }
+
+    let check_in = DeviceRecord {
+        serial: serial.clone(),
+        model: device.clone(),
+        last_seen: now,
+        reported_version: None,
+        served_version: None,
+        ip: client_ip.map(|Extension(ClientIp(ip))| ip),
+        user_agent: headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+        last_report: None,
+    };
+    let stored = record.clone();
+    if let Err(response) = with_store(app.devices.clone(), move |store| {
+        store.enroll(stored)?;
+        store.check_in(check_in)
+    })
+    .await
+    {
+        return response;
+    }
+
+    // Credentials of a previous enrollment are replaced
+    credentials.remove_enrolled(&serial);
+    if let Some(token_sha256) = record.token_sha256 {
+        credentials.set_enrolled(
+            &serial,
+            Credential {
+                token_sha256,
+                devices: vec![device.clone()],
+                serial: Some(serial.clone()),
+            },
+        );
+    }
+    credentials.restore_serial(&serial);
+
+    info!(serial = %serial, device_id = %device, method = %method, "Device enrolled");
+    metrics::counter!(
+        "device_enrollments_total",
+        &[("device_id", device), ("method", method.to_string())]
+    )
+    .increment(1);
+
+    (StatusCode::CREATED, Json(response)).into_response()
+}
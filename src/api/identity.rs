@@ -0,0 +1,262 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{request::Parts, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::api::auth::AuthenticatedCredential;
+
+/// Request header devices can use to report their serial, as an alternative to
+/// the `serial` query parameter.
+pub const DEVICE_SERIAL_HEADER: &str = "x-device-serial";
+
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// Verified client certificate of the connection a request arrived on.
+///
+/// Listeners terminating mutual TLS insert it as a request extension, so that
+/// devices are identified by their certificate rather than by the serial they
+/// claim.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// Common name (CN) of the certificate subject, the device serial.
+    pub common_name: String,
+    /// Device IDs the device may access, as globs, from the organizational
+    /// units (OU) of the certificate subject.
+    pub devices: Vec<String>,
+}
+
+impl ClientCertificate {
+    /// Returns the device IDs the device may access, as globs: those named by
+    /// the certificate, else `default_devices`.
+    #[must_use]
+    pub fn devices_or<'a>(&'a self, default_devices: &'a [String]) -> &'a [String] {
+        if self.devices.is_empty() {
+            default_devices
+        } else {
+            &self.devices
+        }
+    }
+}
+
+/// Identity of the device sending a request, extracted from its client
+/// certificate, device credential and headers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    /// Serial the request was authenticated with.
+    authenticated: Option<String>,
+    /// Serial claimed in the `X-Device-Serial` header.
+    header: Option<String>,
+    /// Whether the request may claim a serial, when it has no credential or
+    /// a credential shared by devices.
+    may_claim: bool,
+}
+
+impl Default for DeviceIdentity {
+    fn default() -> Self {
+        Self {
+            authenticated: None,
+            header: None,
+            may_claim: true,
+        }
+    }
+}
+
+impl DeviceIdentity {
+    #[must_use]
+    pub fn new(
+        headers: &HeaderMap,
+        certificate: Option<&ClientCertificate>,
+        credential: Option<&AuthenticatedCredential>,
+    ) -> Self {
+        let authenticated = certificate
+            .map(|certificate| certificate.common_name.clone())
+            .or_else(|| credential.and_then(|c| c.serial.clone()));
+
+        Self {
+            authenticated,
+            header: headers
+                .get(DEVICE_SERIAL_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            may_claim: credential.is_none_or(|c| c.may_claim_serial),
+        }
+    }
+
+    /// Returns `true` if the request was authenticated with the serial of the
+    /// device, from its client certificate or credential.
+    #[must_use]
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated.is_some()
+    }
+
+    /// Returns the serial the request was authenticated with, ignoring the
+    /// serial it claims.
+    #[must_use]
+    pub fn authenticated_serial(&self) -> Option<&str> {
+        self.authenticated.as_deref()
+    }
+
+    /// Resolves the serial identifying a single device of a model: the serial
+    /// it authenticated with, else the serial it claims, else the
+    /// `X-Device-Serial` header. Requests authenticated with a credential
+    /// bound to no serial that can't claim one, such as a JWT without a
+    /// subject, identify no device.
+    #[must_use]
+    pub fn serial(&self, claimed: Option<&str>) -> Option<String> {
+        if let Some(serial) = &self.authenticated {
+            return Some(serial.clone());
+        }
+        if !self.may_claim {
+            return None;
+        }
+
+        claimed
+            .or(self.header.as_deref())
+            .map(str::trim)
+            .filter(|serial| !serial.is_empty())
+            .map(str::to_string)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for DeviceIdentity {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::new(
+            &parts.headers,
+            parts.extensions.get(),
+            parts.extensions.get(),
+        ))
+    }
+}
+
+/// Resolves the serial identifying a single device of a model: the common
+/// name of its client certificate, else the `serial` query parameter, else the
+/// `X-Device-Serial` header.
+#[must_use]
+pub fn device_serial(
+    query_serial: Option<&str>,
+    headers: &HeaderMap,
+    certificate: Option<&ClientCertificate>,
+) -> Option<String> {
+    DeviceIdentity::new(headers, certificate, None).serial(query_serial)
+}
+
+/// Proxy trusted to set the `X-Forwarded-For` header, by address or network,
+/// e.g. `10.0.0.1` or `10.0.0.0/8`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl TrustedProxy {
+    /// Returns `true` if the address belongs to the proxy network.
+    #[must_use]
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| format!("Invalid trusted proxy '{value}': {reason}");
+
+        let (address, prefix) = match value.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value.trim(), None),
+        };
+        let network = address
+            .parse::<IpAddr>()
+            .map_err(|e| invalid(&e.to_string()))?
+            .to_canonical();
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .ok_or_else(|| invalid(&format!("prefix must be at most {bits}")))?,
+            None => bits,
+        };
+
+        Ok(Self { network, prefix })
+    }
+}
+
+/// IP address of the client sending a request, inserted as a request
+/// extension by [`resolve_client_ip`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Resolves the IP address of the client: the address of the peer, unless it
+/// is a trusted proxy, in which case the `X-Forwarded-For` header is walked
+/// from the closest hop until an address that isn't a trusted proxy.
+///
+/// Returns `None` when the peer address is unknown.
+#[must_use]
+pub fn client_ip(
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+    trusted_proxies: &[TrustedProxy],
+) -> Option<IpAddr> {
+    let trusted = |ip: IpAddr| trusted_proxies.iter().any(|proxy| proxy.contains(ip));
+    let forwarded: Vec<&str> = headers
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+
+    let mut ip = peer?.ip().to_canonical();
+    for hop in forwarded.iter().rev() {
+        if !trusted(ip) {
+            break;
+        }
+        match hop.parse::<IpAddr>() {
+            Ok(hop) => ip = hop.to_canonical(),
+            Err(_) => break,
+        }
+    }
+    Some(ip)
+}
+
+/// Inserts the [`ClientIp`] of requests whose peer address is known,
+/// resolved with [`client_ip`].
+pub async fn resolve_client_ip(
+    State(trusted_proxies): State<Arc<[TrustedProxy]>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    if let Some(ip) = client_ip(request.headers(), peer, &trusted_proxies) {
+        request.extensions_mut().insert(ClientIp(ip));
+    }
+    next.run(request).await
+}
@@ -1,3 +1,12 @@
+pub mod admin;
+pub mod auth;
+pub mod conditional;
 pub mod endpoints;
+pub mod enroll;
+pub mod identity;
+pub mod notify;
+pub mod range;
+pub mod report;
 pub mod router;
+pub mod throttle;
 pub mod webhooks;
@@ -0,0 +1,133 @@
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::alert::HaltAlert;
+use crate::api::router::AppState;
+use crate::channel::Channel;
+use crate::firmware_manager::FirmwareQuery;
+use crate::halt::Halt;
+
+#[derive(Serialize)]
+pub struct FirmwarePayload {
+    version: String,
+    size: usize,
+}
+
+/// Refreshes the cached firmware of a device on a release channel and
+/// publishes it on the channel MQTT topic.
+pub(crate) async fn notify_channel(
+    app: &AppState,
+    device_id: &str,
+    reference: &str,
+    channel: Channel,
+) {
+    let query = FirmwareQuery::new(device_id).with_channel(Some(channel));
+
+    match app.firmware_manager.get_firmware(&query).await {
+        Ok(fw) => {
+            let payload_data = FirmwarePayload {
+                version: fw.version.to_string(),
+                size: fw.size,
+            };
+
+            match serde_json::to_vec(&payload_data) {
+                Ok(payload_bytes) => {
+                    if let Some(notifier) = &app.notifier {
+                        match notifier
+                            .publish(device_id.to_string(), channel, payload_bytes)
+                            .await
+                        {
+                            Ok(()) => {
+                                info!(
+                                    device_id = %device_id,
+                                    reference = %reference,
+                                    channel = %channel,
+                                    "Published firmware notification"
+                                );
+                            }
+                            Err(e) => {
+                                warn!(
+                                    device_id = %device_id,
+                                    reference = %reference,
+                                    channel = %channel,
+                                    error = ?e,
+                                    "Failed to publish MQTT notification"
+                                );
+                            }
+                        }
+                    } else {
+                        warn!("No notifier configured, skipping MQTT notification");
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        device_id = %device_id,
+                        reference = %reference,
+                        channel = %channel,
+                        error = ?e,
+                        "Failed to serialize firmware payload"
+                    );
+                }
+            }
+        }
+        Err(e) => {
+            warn!(
+                device_id = %device_id,
+                reference = %reference,
+                channel = %channel,
+                error = ?e,
+                "Failed to get firmware"
+            );
+        }
+    }
+}
+
+/// Republishes the firmware of a device on every release channel in the
+/// background, after the releases served to it changed.
+pub(crate) fn renotify_device(app: &AppState, device_id: &str, reference: &str) {
+    if app.notifier.is_none() {
+        return;
+    }
+
+    let app = app.clone();
+    let device_id = device_id.to_string();
+    let reference = reference.to_string();
+    tokio::spawn(async move {
+        for channel in Channel::ALL {
+            notify_channel(&app, &device_id, &reference, channel).await;
+        }
+    });
+}
+
+/// Raises the alert of a halted release on the MQTT alert topic of the device
+/// and the alert webhook, in the background.
+pub(crate) fn alert_halt(app: &AppState, device_id: &str, halt: &Halt) {
+    let alert = HaltAlert {
+        event: "release_halted",
+        device: device_id.to_string(),
+        version: halt.version.to_string(),
+        previous: halt.previous.as_ref().map(ToString::to_string),
+        devices: halt.devices,
+        failed: halt.failed,
+        failure_rate: halt.failure_rate(),
+    };
+
+    let app = app.clone();
+    tokio::spawn(async move {
+        if let Some(notifier) = &app.notifier {
+            let published = match serde_json::to_vec(&alert) {
+                Ok(payload) => notifier.publish_alert(&alert.device, payload).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = published {
+                warn!(device_id = %alert.device, error = ?e, "Failed to publish halt alert");
+            }
+        }
+
+        if let Some(webhook) = &app.alert_webhook {
+            if let Err(e) = webhook.send(&alert).await {
+                warn!(device_id = %alert.device, error = ?e, "Failed to deliver halt alert");
+            }
+        }
+    });
+}
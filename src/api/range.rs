@@ -0,0 +1,88 @@
+use axum::http::{header, HeaderMap};
+use std::ops::Range;
+
+/// Outcome of evaluating a `Range` request header against a body of known length.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No usable range was requested, the full body should be served.
+    Full,
+    /// A single satisfiable byte range, as a half-open interval.
+    Partial(Range<usize>),
+    /// The requested range lies entirely outside of the body.
+    Unsatisfiable,
+}
+
+/// Evaluates the `Range` and `If-Range` request headers for a body of `len` bytes.
+///
+/// A range is only honoured when `If-Range` is absent or carries the exact
+/// (strong) `etag` of the current body. This guarantees that a resumed
+/// download never mixes bytes from two different builds: if the artifact
+/// changed in between, the full body is served instead.
+#[must_use]
+pub fn requested_range(headers: &HeaderMap, etag: &str, len: usize) -> ByteRange {
+    let Some(range) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+        return ByteRange::Full;
+    };
+
+    if let Some(if_range) = headers.get(header::IF_RANGE) {
+        let matches = if_range.to_str().is_ok_and(|v| v.trim() == etag);
+        if !matches {
+            return ByteRange::Full;
+        }
+    }
+
+    parse_range(range, len)
+}
+
+/// Parses a `Range` header value (e.g. `bytes=0-1023`, `bytes=512-`, `bytes=-256`).
+///
+/// Only single byte ranges are supported. Malformed headers and multi-range
+/// requests are ignored, as permitted by RFC 9110, and result in the full body.
+#[must_use]
+pub fn parse_range(value: &str, len: usize) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // Suffix range: the last `n` bytes
+        let Ok(suffix) = end.parse::<usize>() else {
+            return ByteRange::Full;
+        };
+        if suffix == 0 || len == 0 {
+            return ByteRange::Unsatisfiable;
+        }
+        return ByteRange::Partial(len.saturating_sub(suffix)..len);
+    }
+
+    let Ok(start) = start.parse::<usize>() else {
+        return ByteRange::Full;
+    };
+
+    let last = if end.is_empty() {
+        len.saturating_sub(1)
+    } else {
+        let Ok(end) = end.parse::<usize>() else {
+            return ByteRange::Full;
+        };
+        if end < start {
+            return ByteRange::Full;
+        }
+        end.min(len.saturating_sub(1))
+    };
+
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Partial(start..last + 1)
+}
@@ -0,0 +1,156 @@
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use semver::Version;
+use serde::Deserialize;
+use std::time::SystemTime;
+use tracing::{debug, info, instrument};
+
+use crate::api::admin::devices::with_store;
+use crate::api::auth::AuthenticatedCredential;
+use crate::api::identity::DeviceIdentity;
+use crate::api::notify::alert_halt;
+use crate::api::router::AppState;
+use crate::inventory::{UpdateReport, UpdateStatus};
+
+/// Body of an update outcome report.
+#[derive(Deserialize)]
+pub struct ReportRequest {
+    /// Device model (repository name).
+    pub device: String,
+    /// Serial of the device, see [`DeviceIdentity::serial`].
+    pub serial: Option<String>,
+    /// Firmware version running before the update.
+    pub from_version: Option<String>,
+    /// Firmware version being installed.
+    pub to_version: String,
+    pub status: UpdateStatus,
+    pub error_code: Option<ErrorCode>,
+}
+
+/// Error code of a failed update, as a number or a string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ErrorCode {
+    Number(i64),
+    Text(String),
+}
+
+impl From<ErrorCode> for String {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Number(code) => code.to_string(),
+            ErrorCode::Text(code) => code,
+        }
+    }
+}
+
+fn parse_version(field: &str, version: &str) -> Result<Version, String> {
+    let version = version.trim();
+    Version::parse(version.strip_prefix('v').unwrap_or(version))
+        .map_err(|e| format!("Invalid {field} '{version}': {e}"))
+}
+
+/// Records the outcome of a firmware update reported by a device, as it goes
+/// through the `downloaded`, `installed` and `booted` stages or `failed`.
+///
+/// Reports are stored in the device inventory and counted per version in the
+/// `firmware_update_reports_total` metric. Devices are identified like on the
+/// other device endpoints, see [`DeviceIdentity::serial`], and device
+/// credentials must be bound to the reported device.
+///
+/// Install outcomes count towards the failure policy of the repository, see
+/// [`Halts`](crate::halt::Halts), when the device authenticated with its
+/// serial. Releases tripping it are halted and an alert is raised over MQTT
+/// and the alert webhook.
+#[instrument(skip(app, identity, credential, request))]
+pub async fn report_handler(
+    State(app): State<AppState>,
+    identity: DeviceIdentity,
+    credential: Option<Extension<AuthenticatedCredential>>,
+    request: Result<Json<ReportRequest>, JsonRejection>,
+) -> Response {
+    let request = match request {
+        Ok(Json(request)) => request,
+        Err(rejection) => {
+            return (StatusCode::BAD_REQUEST, rejection.body_text()).into_response();
+        }
+    };
+
+    let device = request.device.trim();
+    if device.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Missing required field: 'device'").into_response();
+    }
+
+    if let Some(Extension(credential)) = &credential {
+        if let Err(message) = credential.authorize(device) {
+            return (StatusCode::FORBIDDEN, message).into_response();
+        }
+    }
+
+    let Some(serial) = identity.serial(request.serial.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Missing device serial").into_response();
+    };
+
+    let from_version = match request
+        .from_version
+        .as_deref()
+        .map(|v| parse_version("from_version", v))
+        .transpose()
+    {
+        Ok(version) => version,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let to_version = match parse_version("to_version", &request.to_version) {
+        Ok(version) => version,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let report = UpdateReport {
+        serial,
+        model: device.to_string(),
+        from_version,
+        to_version,
+        status: request.status,
+        error_code: request.error_code.map(String::from),
+        reported_at: SystemTime::now(),
+    };
+
+    info!(
+        device_id = %report.model,
+        serial = %report.serial,
+        to_version = %report.to_version,
+        status = %report.status,
+        error_code = ?report.error_code,
+        "Update outcome reported"
+    );
+
+    let labels = [
+        ("device_id", report.model.clone()),
+        ("version", report.to_version.to_string()),
+        ("status", report.status.as_str().to_string()),
+    ];
+
+    let stored = report.clone();
+    if let Err(response) = with_store(app.devices.clone(), move |store| store.report(stored)).await
+    {
+        return response;
+    }
+    metrics::counter!("firmware_update_reports_total", &labels).increment(1);
+
+    // Reports under made-up serials must not halt releases
+    if !identity.is_authenticated() {
+        debug!(
+            serial = %report.serial,
+            to_version = %report.to_version,
+            "Not counting outcome of unauthenticated device"
+        );
+    } else if let Some(halt) = app.firmware_manager.record_outcome(&report).await {
+        alert_halt(&app, &report.model, &halt);
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
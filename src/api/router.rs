@@ -1,15 +1,48 @@
 use axum::{
     extract::FromRef,
     middleware,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use std::sync::Arc;
+use tokio::sync::Semaphore;
 use tower_http::trace::TraceLayer;
 
-use crate::api::endpoints::{firmware_handler, health_handler, version_handler};
+use crate::alert::AlertWebhook;
+use crate::api::admin::credentials::{list_credentials_handler, revoke_credential_handler};
+use crate::api::admin::devices::{
+    get_device_handler, list_device_reports_handler, list_devices_handler,
+};
+use crate::api::admin::enrollments::{
+    delete_enrollment_code_handler, list_enrollment_codes_handler, list_enrollments_handler,
+    put_enrollment_code_handler,
+};
+use crate::api::admin::halts::{list_halts_handler, resume_halt_handler};
+use crate::api::admin::pins::{delete_pin_handler, list_pins_handler, put_pin_handler};
+use crate::api::admin::require_admin_token;
+use crate::api::admin::rollbacks::{
+    delete_rollback_handler, delete_serial_rollback_handler, list_rollbacks_handler,
+    put_rollback_handler, put_serial_rollback_handler,
+};
+use crate::api::admin::rollouts::{
+    delete_rollout_handler, get_rollout_handler, halt_rollout_handler, list_rollouts_handler,
+    put_rollout_handler, resume_rollout_handler,
+};
+use crate::api::admin::yanks::{delete_yank_handler, list_yanks_handler, put_yank_handler};
+use crate::api::auth::{require_device_credential, DeviceAuth};
+use crate::api::endpoints::{delta_handler, firmware_handler, health_handler, version_handler};
+use crate::api::enroll::enroll_handler;
+use crate::api::identity::{resolve_client_ip, TrustedProxy};
+use crate::api::report::report_handler;
+use crate::api::throttle::{
+    limit_concurrent_downloads, throttle_client_ips, throttle_devices, Throttle,
+};
 use crate::api::webhooks::harbor::harbor_webhook_handler;
+use crate::credential::Credentials;
+use crate::enrollment::Enrollment;
 use crate::firmware_manager::FirmwareManager;
+use crate::inventory::{DeviceStore, MemoryStore};
+use crate::jwt::JwtVerifier;
 use crate::metrics::middleware::track_metrics;
 use crate::notifier::Notifier;
 
@@ -17,6 +50,12 @@ use crate::notifier::Notifier;
 pub struct AppState {
     pub firmware_manager: Arc<FirmwareManager>,
     pub notifier: Option<Notifier>,
+    pub devices: Arc<dyn DeviceStore>,
+    pub alert_webhook: Option<AlertWebhook>,
+    pub credentials: Option<Arc<Credentials>>,
+    pub enrollment: Option<Arc<Enrollment>>,
+    /// Device IDs, as globs, of client certificates naming none.
+    pub certificate_devices: Arc<[String]>,
 }
 
 impl FromRef<AppState> for Arc<FirmwareManager> {
@@ -25,18 +64,192 @@ impl FromRef<AppState> for Arc<FirmwareManager> {
     }
 }
 
+impl FromRef<AppState> for Arc<dyn DeviceStore> {
+    fn from_ref(app_state: &AppState) -> Self {
+        app_state.devices.clone()
+    }
+}
+
+/// Optional features of the main API.
+#[derive(Clone, Debug, Default)]
+pub struct ApiConfig {
+    /// Bearer token protecting the `/admin` API, which is disabled when not set.
+    pub admin_token: Option<String>,
+    /// Store of device check-ins, kept in memory when not set.
+    pub device_store: Option<Arc<dyn DeviceStore>>,
+    /// Webhook receiving alerts, such as halted releases.
+    pub alert_webhook: Option<AlertWebhook>,
+    /// Per-device API tokens accepted on the device endpoints.
+    pub device_credentials: Option<Arc<Credentials>>,
+    /// Verifier of JWT device tokens accepted on the device endpoints.
+    pub jwt_verifier: Option<Arc<JwtVerifier>>,
+    /// Device IDs, as globs, client certificates that don't name their device
+    /// ID may access.
+    pub certificate_devices: Vec<String>,
+    /// Requires a client certificate verified by the TLS listener on the
+    /// device endpoints, rejecting requests without one with
+    /// `401 Unauthorized`.
+    pub require_client_certificate: bool,
+    /// Device enrollment, issuing device credentials on `/enroll`, which is
+    /// disabled when not set.
+    pub enrollment: Option<Arc<Enrollment>>,
+    /// Rate limits of the device endpoints, per device and client IP.
+    pub throttle: Throttle,
+    /// Proxies trusted to set the `X-Forwarded-For` header, which is ignored
+    /// otherwise.
+    pub trusted_proxies: Vec<TrustedProxy>,
+    /// Firmware downloads served at once, unlimited when not set.
+    pub max_concurrent_downloads: Option<usize>,
+}
+
 pub fn api_router(firmware_manager: Arc<FirmwareManager>, notifier: Option<Notifier>) -> Router {
+    api_router_with_config(firmware_manager, notifier, ApiConfig::default())
+}
+
+pub fn api_router_with_config(
+    firmware_manager: Arc<FirmwareManager>,
+    notifier: Option<Notifier>,
+    config: ApiConfig,
+) -> Router {
+    // Enrolled devices authenticate with the credentials issued to them
+    let credentials = config.device_credentials.or_else(|| {
+        config
+            .enrollment
+            .as_ref()
+            .map(|_| Arc::new(Credentials::default()))
+    });
+
+    // Device endpoints are open unless a kind of credential is configured
+    let device_auth = (credentials.is_some()
+        || config.jwt_verifier.is_some()
+        || config.require_client_certificate)
+        .then(|| DeviceAuth {
+            credentials: credentials.clone(),
+            jwt: config.jwt_verifier,
+            default_channel: firmware_manager.default_channel(),
+            certificate_devices: config.certificate_devices.clone(),
+            require_certificate: config.require_client_certificate,
+        });
+
     let app_state = AppState {
         firmware_manager,
         notifier,
+        devices: config
+            .device_store
+            .unwrap_or_else(|| Arc::new(MemoryStore::new())),
+        alert_webhook: config.alert_webhook,
+        credentials,
+        enrollment: config.enrollment,
+        certificate_devices: config.certificate_devices.into(),
     };
 
-    Router::new()
-        .route("/version", get(version_handler))
+    let mut firmware_router = Router::new()
         .route("/firmware", get(firmware_handler))
+        .route("/firmware/delta", get(delta_handler));
+
+    if let Some(max_downloads) = config.max_concurrent_downloads {
+        firmware_router = firmware_router.route_layer(middleware::from_fn_with_state(
+            Arc::new(Semaphore::new(max_downloads)),
+            limit_concurrent_downloads,
+        ));
+    }
+
+    let mut device_router = Router::new()
+        .route("/version", get(version_handler))
+        .route("/report", post(report_handler))
+        .merge(firmware_router);
+
+    // Devices are throttled once authenticated, by the serial they
+    // authenticated with, else by the serial they claim
+    if let Some(devices) = config.throttle.devices {
+        device_router =
+            device_router.route_layer(middleware::from_fn_with_state(devices, throttle_devices));
+    }
+
+    if let Some(device_auth) = device_auth {
+        device_router = device_router.route_layer(middleware::from_fn_with_state(
+            device_auth,
+            require_device_credential,
+        ));
+    }
+
+    // Client IPs are throttled ahead of authentication
+    if let Some(ips) = config.throttle.ips {
+        device_router =
+            device_router.route_layer(middleware::from_fn_with_state(ips, throttle_client_ips));
+    }
+
+    let mut router = device_router
         .route("/health", get(health_handler))
-        .route("/webhooks/harbor", post(harbor_webhook_handler))
+        .route("/webhooks/harbor", post(harbor_webhook_handler));
+
+    if app_state.enrollment.is_some() {
+        router = router.route("/enroll", post(enroll_handler));
+    }
+
+    if let Some(admin_token) = config.admin_token.filter(|t| !t.is_empty()) {
+        router = router.nest("/admin", admin_router(admin_token));
+    }
+
+    router
         .layer(middleware::from_fn(track_metrics))
+        .layer(middleware::from_fn_with_state(
+            Arc::<[TrustedProxy]>::from(config.trusted_proxies),
+            resolve_client_ip,
+        ))
         .with_state(app_state)
         .layer(TraceLayer::new_for_http())
 }
+
+/// Routes of the admin API, all requiring the admin bearer token.
+fn admin_router(admin_token: String) -> Router<AppState> {
+    Router::new()
+        .route("/devices", get(list_devices_handler))
+        .route("/devices/{serial}", get(get_device_handler))
+        .route(
+            "/devices/{serial}/reports",
+            get(list_device_reports_handler),
+        )
+        .route("/rollouts", get(list_rollouts_handler))
+        .route(
+            "/rollouts/{device}",
+            get(get_rollout_handler)
+                .put(put_rollout_handler)
+                .delete(delete_rollout_handler),
+        )
+        .route("/rollouts/{device}/halt", post(halt_rollout_handler))
+        .route("/rollouts/{device}/resume", post(resume_rollout_handler))
+        .route("/credentials", get(list_credentials_handler))
+        .route("/credentials/{id}", delete(revoke_credential_handler))
+        .route("/enrollments", get(list_enrollments_handler))
+        .route("/enrollment-codes", get(list_enrollment_codes_handler))
+        .route(
+            "/enrollment-codes/{serial}",
+            put(put_enrollment_code_handler).delete(delete_enrollment_code_handler),
+        )
+        .route("/halts", get(list_halts_handler))
+        .route("/halts/{device}/resume", post(resume_halt_handler))
+        .route("/pins", get(list_pins_handler))
+        .route(
+            "/pins/{id}",
+            put(put_pin_handler).delete(delete_pin_handler),
+        )
+        .route("/rollbacks", get(list_rollbacks_handler))
+        .route(
+            "/rollbacks/{device}",
+            put(put_rollback_handler).delete(delete_rollback_handler),
+        )
+        .route(
+            "/rollbacks/{device}/{serial}",
+            put(put_serial_rollback_handler).delete(delete_serial_rollback_handler),
+        )
+        .route("/yanks", get(list_yanks_handler))
+        .route(
+            "/yanks/{device}/{release}",
+            put(put_yank_handler).delete(delete_yank_handler),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            Arc::<str>::from(admin_token),
+            require_admin_token,
+        ))
+}
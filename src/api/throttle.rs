@@ -0,0 +1,163 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::{MatchedPath, Query, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::stream::{self, StreamExt};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+use crate::api::identity::{ClientIp, DeviceIdentity};
+use crate::rate_limit::RateLimiter;
+
+/// Delay advertised to devices turned away because every download slot is
+/// taken.
+pub const DOWNLOAD_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Rate limits of the device endpoints.
+#[derive(Clone, Debug, Default)]
+pub struct Throttle {
+    /// Limit per device, by authenticated serial, else by device ID and
+    /// claimed serial, else by device ID.
+    pub devices: Option<Arc<RateLimiter>>,
+    /// Limit per client IP address.
+    pub ips: Option<Arc<RateLimiter>>,
+}
+
+#[derive(Deserialize)]
+struct ThrottleQuery {
+    device: Option<String>,
+    serial: Option<String>,
+}
+
+/// Builds a `429 Too Many Requests` response and counts the rejection.
+fn too_many_requests(limit: &'static str, path: String, retry_after: Duration) -> Response {
+    metrics::counter!(
+        "http_requests_throttled_total",
+        &[("limit", limit.to_string()), ("path", path)]
+    )
+    .increment(1);
+
+    // Round up, so that devices don't retry before their allowance is back
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, HeaderValue::from(seconds.max(1)))],
+        "Too many requests",
+    )
+        .into_response()
+}
+
+fn matched_path(request: &Request) -> String {
+    request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| request.uri().path(), MatchedPath::as_str)
+        .to_string()
+}
+
+/// Rejects requests exceeding the rate limit of their client IP with
+/// `429 Too Many Requests`.
+///
+/// Applied ahead of authentication, so that requests with invalid credentials
+/// count towards the limit too. Requests whose client IP is unknown aren't
+/// limited.
+pub async fn throttle_client_ips(
+    State(ips): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(ClientIp(ip)) = request.extensions().get::<ClientIp>() {
+        if let Err(retry_after) = ips.check(&ip.to_string(), Instant::now()) {
+            debug!(ip = %ip, "Client IP rate limited");
+            return too_many_requests("ip", matched_path(&request), retry_after);
+        }
+    }
+
+    next.run(request).await
+}
+
+/// Rejects device requests exceeding the rate limit of the device with
+/// `429 Too Many Requests`.
+///
+/// Devices are told apart by the serial they authenticated with, else by the
+/// serial they claim within their device ID, so that devices behind one
+/// address don't share a limit. Claimed serials can be rotated at will, which
+/// the client IP limit of [`throttle_client_ips`] caps. Requests claiming no
+/// serial share the limit of their device ID.
+pub async fn throttle_devices(
+    State(devices): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let identity = DeviceIdentity::new(
+        request.headers(),
+        request.extensions().get(),
+        request.extensions().get(),
+    );
+    let key = if let Some(serial) = identity.authenticated_serial() {
+        Some(format!("serial:{serial}"))
+    } else {
+        Query::<ThrottleQuery>::try_from_uri(request.uri())
+            .ok()
+            .and_then(|Query(query)| {
+                let device = query.device?;
+                Some(match identity.serial(query.serial.as_deref()) {
+                    Some(serial) => format!("device:{device}:{serial}"),
+                    None => format!("device:{device}"),
+                })
+            })
+    };
+
+    if let Some(key) = key {
+        if let Err(retry_after) = devices.check(&key, Instant::now()) {
+            debug!(device = %key, "Device rate limited");
+            return too_many_requests("device", matched_path(&request), retry_after);
+        }
+    }
+
+    next.run(request).await
+}
+
+/// Rejects firmware downloads with `429 Too Many Requests` while every
+/// download slot is taken.
+///
+/// A slot is held until the response body is fully sent, or the device
+/// disconnects.
+pub async fn limit_concurrent_downloads(
+    State(slots): State<Arc<Semaphore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(permit) = slots.try_acquire_owned() else {
+        warn!("Download slots exhausted");
+        return too_many_requests("downloads", matched_path(&request), DOWNLOAD_RETRY_AFTER);
+    };
+
+    let mut response = next.run(request).await;
+    if !response.status().is_success() {
+        return response;
+    }
+
+    // Streaming the body drops its size, which is kept as its content length
+    if let Some(size) = response.body().size_hint().exact() {
+        response
+            .headers_mut()
+            .entry(header::CONTENT_LENGTH)
+            .or_insert_with(|| HeaderValue::from(size));
+    }
+    response.map(|body| {
+        Body::from_stream(stream::unfold(
+            (body.into_data_stream(), permit),
+            |(mut body, permit)| async move {
+                let chunk = body.next().await?;
+                Some((chunk, (body, permit)))
+            },
+        ))
+    })
+}
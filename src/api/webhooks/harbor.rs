@@ -3,11 +3,13 @@ use axum::{
     http::StatusCode,
     response::IntoResponse,
 };
+use semver::Version;
 use serde::Deserialize;
-use serde::Serialize;
 use tracing::{info, instrument, warn};
 
+use crate::api::notify::notify_channel;
 use crate::api::router::AppState;
+use crate::channel::Channel;
 
 #[derive(Debug, Deserialize)]
 pub struct HarborWebhookPayload {
@@ -40,12 +42,6 @@ pub struct HarborRepository {
     pub repo_type: String,
 }
 
-#[derive(Serialize)]
-pub struct FirmwarePayload {
-    version: String,
-    size: usize,
-}
-
 #[instrument(skip(app, payload), fields(event_type = %payload.event_type, operator = %payload.operator))]
 pub async fn harbor_webhook_handler(
     State(app): State<AppState>,
@@ -67,55 +63,17 @@ pub async fn harbor_webhook_handler(
             "Processing PUSH_ARTIFACT event"
         );
 
-        match app.firmware_manager.get_firmware(device_id).await {
-            Ok(fw) => {
-                let payload_data = FirmwarePayload {
-                    version: fw.version.to_string(),
-                    size: fw.size,
-                };
+        // Notify every channel the pushed version is released on. Tags that
+        // aren't semver are treated as stable, as before channels existed.
+        let version = Version::parse(&resource.tag).ok();
+        let channels = Channel::ALL.into_iter().filter(|c| {
+            version
+                .as_ref()
+                .map_or(*c == Channel::Stable, |v| c.includes(v))
+        });
 
-                match serde_json::to_vec(&payload_data) {
-                    Ok(payload_bytes) => {
-                        if let Some(notifier) = &app.notifier {
-                            match notifier.publish(device_id.clone(), payload_bytes).await {
-                                Ok(()) => {
-                                    info!(
-                                        device_id = %device_id,
-                                        tag = %resource.tag,
-                                        "Published firmware notification"
-                                    );
-                                }
-                                Err(e) => {
-                                    warn!(
-                                        device_id = %device_id,
-                                        tag = %resource.tag,
-                                        error = ?e,
-                                        "Failed to publish MQTT notification"
-                                    );
-                                }
-                            }
-                        } else {
-                            warn!("No notifier configured, skipping MQTT notification");
-                        }
-                    }
-                    Err(e) => {
-                        warn!(
-                            device_id = %device_id,
-                            tag = %resource.tag,
-                            error = ?e,
-                            "Failed to serialize firmware payload"
-                        );
-                    }
-                }
-            }
-            Err(e) => {
-                warn!(
-                    device_id = %device_id,
-                    tag = %resource.tag,
-                    error = ?e,
-                    "Failed to get firmware"
-                );
-            }
+        for channel in channels {
+            notify_channel(&app, device_id, &resource.tag, channel).await;
         }
     }
 
@@ -0,0 +1,61 @@
+use semver::Version;
+use serde::Deserialize;
+use std::fmt;
+
+/// Release channels, selecting firmware versions by their semver pre-release
+/// identifiers, ordered from the most to the least conservative.
+#[derive(
+    Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    /// Releases without pre-release identifier, e.g. `1.2.0`.
+    #[default]
+    Stable,
+    /// Stable releases, betas and release candidates, e.g. `1.3.0-beta.1` or
+    /// `1.3.0-rc.2`.
+    Beta,
+    /// Every release, including nightly or development builds.
+    Nightly,
+}
+
+impl Channel {
+    /// All channels, from the most to the least conservative.
+    pub const ALL: [Self; 3] = [Self::Stable, Self::Beta, Self::Nightly];
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Beta => "beta",
+            Self::Nightly => "nightly",
+        }
+    }
+
+    /// Returns `true` if `version` is released on this channel.
+    #[must_use]
+    pub fn includes(self, version: &Version) -> bool {
+        let pre = version.pre.as_str();
+        match self {
+            Self::Stable => pre.is_empty(),
+            Self::Beta => pre.is_empty() || pre.starts_with("beta") || pre.starts_with("rc"),
+            Self::Nightly => true,
+        }
+    }
+
+    /// Returns the suffix appended to the MQTT topic of a device for this
+    /// channel. Stable notifications keep the plain device topic.
+    #[must_use]
+    pub fn topic_suffix(self) -> Option<&'static str> {
+        match self {
+            Self::Stable => None,
+            _ => Some(self.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
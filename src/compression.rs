@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+
+/// Compression level used for zstd. Firmware is compressed once per build and
+/// served many times, so favour ratio over speed.
+const ZSTD_LEVEL: i32 = 19;
+
+/// Window size of heatshrink streams, as a power of two. Matches the default
+/// of the heatshrink library, which decoders must be configured with.
+pub const HEATSHRINK_WINDOW_BITS: u8 = 8;
+/// Lookahead size of heatshrink streams, as a power of two.
+pub const HEATSHRINK_LOOKAHEAD_BITS: u8 = 4;
+
+/// Content encodings supported for firmware delivery.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+    /// Raw firmware bytes.
+    #[default]
+    Identity,
+    Gzip,
+    Zstd,
+    /// LZSS stream for microcontrollers decompressing with a few hundred
+    /// bytes of RAM, see <https://github.com/atomicobject/heatshrink>.
+    Heatshrink,
+}
+
+impl ContentEncoding {
+    /// Returns the HTTP `Content-Encoding` token for this encoding.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Heatshrink => "heatshrink",
+        }
+    }
+
+    /// Preference between encodings of equal quality, by compression ratio.
+    fn rank(self) -> u8 {
+        match self {
+            Self::Identity => 0,
+            Self::Heatshrink => 1,
+            Self::Gzip => 2,
+            Self::Zstd => 3,
+        }
+    }
+
+    /// Selects the preferred supported encoding from an `Accept-Encoding` header.
+    ///
+    /// Codings with the highest quality value win, with zstd preferred over
+    /// gzip, and gzip over heatshrink, on ties. Wildcards are ignored so that devices only receive
+    /// compressed firmware when they explicitly ask for it.
+    #[must_use]
+    pub fn negotiate(accept_encoding: &str) -> Self {
+        accept_encoding
+            .split(',')
+            .filter_map(|coding| {
+                let mut parts = coding.split(';');
+                let encoding = match parts.next()?.trim().to_ascii_lowercase().as_str() {
+                    "gzip" | "x-gzip" => Self::Gzip,
+                    "zstd" => Self::Zstd,
+                    "heatshrink" => Self::Heatshrink,
+                    _ => return None,
+                };
+                let quality = parts
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (quality > 0.0).then_some((encoding, quality))
+            })
+            .max_by(|(a, qa), (b, qb)| qa.total_cmp(qb).then_with(|| a.rank().cmp(&b.rank())))
+            .map_or(Self::Identity, |(encoding, _)| encoding)
+    }
+
+    /// Compresses `data` with this encoding.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the encoder fails.
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Identity => Ok(data.to_vec()),
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Self::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)
+                .map_err(|e| anyhow!("Failed to compress firmware with zstd: {e}")),
+            Self::Heatshrink => Ok(heatshrink_compress(data)),
+        }
+    }
+}
+
+/// Writes values most significant bit first, as read by heatshrink decoders.
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    byte: u8,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: usize, count: u8) {
+        for bit in (0..count).rev() {
+            self.byte = (self.byte << 1) | u8::from((value >> bit) & 1 == 1);
+            self.bits += 1;
+            if self.bits == 8 {
+                self.data.push(self.byte);
+                self.byte = 0;
+                self.bits = 0;
+            }
+        }
+    }
+
+    /// Pads the last byte with zeroes, which decoders ignore as an incomplete
+    /// back-reference.
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.data.push(self.byte << (8 - self.bits));
+        }
+        self.data
+    }
+}
+
+/// Compresses `data` into a heatshrink stream, with the window and lookahead
+/// sizes of [`HEATSHRINK_WINDOW_BITS`] and [`HEATSHRINK_LOOKAHEAD_BITS`].
+///
+/// Each byte is either emitted as a literal (a `1` bit and the byte), or as
+/// part of a back-reference to a previous occurrence within the window (a `0`
+/// bit, the offset minus one and the length minus one).
+fn heatshrink_compress(data: &[u8]) -> Vec<u8> {
+    const NONE: usize = usize::MAX;
+
+    let window = 1 << HEATSHRINK_WINDOW_BITS;
+    let max_length = 1 << HEATSHRINK_LOOKAHEAD_BITS;
+    // Back-references only pay off once shorter than the literals they replace
+    let reference_bits = 1 + usize::from(HEATSHRINK_WINDOW_BITS + HEATSHRINK_LOOKAHEAD_BITS);
+    let min_length = reference_bits / 9 + 1;
+
+    // Previous positions starting with the same two bytes, most recent first
+    let prefix = |pos: usize| (usize::from(data[pos]) << 8) | usize::from(data[pos + 1]);
+    let mut head = vec![NONE; 1 << 16];
+    let mut previous = vec![NONE; data.len()];
+
+    let mut output = BitWriter::default();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut length, mut offset) = (0, 0);
+        if pos + 1 < data.len() {
+            let mut candidate = head[prefix(pos)];
+            while candidate != NONE && pos - candidate <= window {
+                let candidate_length = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..])
+                    .take(max_length)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if candidate_length > length {
+                    length = candidate_length;
+                    offset = pos - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+            }
+        }
+
+        let step = if length >= min_length {
+            output.push(0, 1);
+            output.push(offset - 1, HEATSHRINK_WINDOW_BITS);
+            output.push(length - 1, HEATSHRINK_LOOKAHEAD_BITS);
+            length
+        } else {
+            output.push(1, 1);
+            output.push(usize::from(data[pos]), 8);
+            1
+        };
+
+        for indexed in pos..(pos + step).min(data.len() - 1) {
+            previous[indexed] = head[prefix(indexed)];
+            head[prefix(indexed)] = indexed;
+        }
+        pos += step;
+    }
+
+    output.finish()
+}
+
+/// A compressed variant of a firmware binary, with checksums of the
+/// compressed bytes so devices can verify the transfer before decompressing.
+#[derive(Clone, Debug)]
+pub struct CompressedFirmware {
+    pub encoding: ContentEncoding,
+    pub data: Bytes,
+    pub crc: u32,
+    /// Hex-encoded SHA-256 digest of the compressed data.
+    pub sha256: String,
+}
+
+impl CompressedFirmware {
+    /// Compresses `binary` with the given encoding and computes its checksums.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the encoder fails.
+    pub fn new(encoding: ContentEncoding, binary: &[u8]) -> Result<Self> {
+        let data = Bytes::from(encoding.compress(binary)?);
+        let crc = crc32fast::hash(&data);
+        let sha256 = format!("{:x}", Sha256::digest(&data));
+
+        Ok(Self {
+            encoding,
+            data,
+            crc,
+            sha256,
+        })
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::pin::glob_matches;
+
+/// Credential authenticating devices on the firmware endpoints.
+///
+/// Only the SHA-256 of the token is stored. Credentials are bound to the
+/// device IDs they may request, as globs (`*` and `?` wildcards), and
+/// optionally to the serial of a single device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "CredentialSpec", into = "CredentialSpec")]
+pub struct Credential {
+    /// Hex-encoded SHA-256 of the token.
+    pub token_sha256: String,
+    pub devices: Vec<String>,
+    /// Serial of the device authenticated by the credential, overriding the
+    /// serial it claims.
+    pub serial: Option<String>,
+}
+
+/// Serialized form of a [`Credential`].
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CredentialSpec {
+    token_sha256: String,
+    devices: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    serial: Option<String>,
+}
+
+impl TryFrom<CredentialSpec> for Credential {
+    type Error = String;
+
+    fn try_from(spec: CredentialSpec) -> Result<Self, Self::Error> {
+        let token_sha256 = spec.token_sha256.trim().to_ascii_lowercase();
+        if token_sha256.len() != 64 || !token_sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err("'token_sha256' must be a hex-encoded SHA-256".to_string());
+        }
+        if spec.devices.is_empty() {
+            return Err("Credentials must allow at least one device".to_string());
+        }
+
+        Ok(Self {
+            token_sha256,
+            devices: spec.devices,
+            serial: spec.serial,
+        })
+    }
+}
+
+impl From<Credential> for CredentialSpec {
+    fn from(credential: Credential) -> Self {
+        Self {
+            token_sha256: credential.token_sha256,
+            devices: credential.devices,
+            serial: credential.serial,
+        }
+    }
+}
+
+impl Credential {
+    /// Creates a credential for a token.
+    #[must_use]
+    pub fn new(token: &str, devices: Vec<String>, serial: Option<String>) -> Self {
+        Self {
+            token_sha256: hash_token(token),
+            devices,
+            serial,
+        }
+    }
+
+    /// Returns `true` if the credential may request firmware of the device ID.
+    #[must_use]
+    pub fn allows(&self, device_id: &str) -> bool {
+        self.devices
+            .iter()
+            .any(|pattern| glob_matches(pattern, device_id))
+    }
+}
+
+/// Returns the hex-encoded SHA-256 of a token, as stored in credentials.
+#[must_use]
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Loads device credentials from a JSON file mapping credential IDs to
+/// credentials.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or contains invalid credentials.
+pub fn load_credentials(path: &Path) -> Result<BTreeMap<String, Credential>> {
+    let content = std::fs::read(path)
+        .with_context(|| format!("Failed to read credentials file {}", path.display()))?;
+    serde_json::from_slice(&content)
+        .with_context(|| format!("Invalid credentials file {}", path.display()))
+}
+
+/// Device credentials by ID, adjustable at runtime, along with the serials of
+/// revoked devices.
+///
+/// Credentials issued by enrollment are kept by serial apart from the
+/// configured credentials, so that a device can't replace a configured
+/// credential by enrolling under its ID.
+#[derive(Debug, Default)]
+pub struct Credentials {
+    configured: Mutex<BTreeMap<String, Credential>>,
+    enrolled: Mutex<BTreeMap<String, Credential>>,
+    revoked: Mutex<BTreeSet<String>>,
+}
+
+impl Credentials {
+    #[must_use]
+    pub fn new(credentials: BTreeMap<String, Credential>) -> Self {
+        Self {
+            configured: Mutex::new(credentials),
+            enrolled: Mutex::default(),
+            revoked: Mutex::default(),
+        }
+    }
+
+    /// Returns all credentials, sorted by ID.
+    #[must_use]
+    pub fn list(&self) -> Vec<(String, Credential)> {
+        self.configured
+            .lock()
+            .iter()
+            .map(|(id, credential)| (id.clone(), credential.clone()))
+            .collect()
+    }
+
+    /// Creates or replaces a credential.
+    pub fn set(&self, id: &str, credential: Credential) {
+        self.configured.lock().insert(id.to_string(), credential);
+    }
+
+    /// Removes a credential, returning it if it existed.
+    pub fn remove(&self, id: &str) -> Option<Credential> {
+        self.configured.lock().remove(id)
+    }
+
+    /// Returns the credentials issued by enrollment, sorted by serial.
+    #[must_use]
+    pub fn list_enrolled(&self) -> Vec<(String, Credential)> {
+        self.enrolled
+            .lock()
+            .iter()
+            .map(|(serial, credential)| (serial.clone(), credential.clone()))
+            .collect()
+    }
+
+    /// Creates or replaces the credential issued to an enrolled device.
+    pub fn set_enrolled(&self, serial: &str, credential: Credential) {
+        self.enrolled.lock().insert(serial.to_string(), credential);
+    }
+
+    /// Removes the credential issued to an enrolled device, returning it if it
+    /// existed.
+    pub fn remove_enrolled(&self, serial: &str) -> Option<Credential> {
+        self.enrolled.lock().remove(serial)
+    }
+
+    /// Returns the ID and credential a token belongs to, if any. Credentials
+    /// issued by enrollment are identified by the serial of their device.
+    ///
+    /// Tokens are compared by hash, so comparisons don't leak the stored
+    /// tokens through timing.
+    #[must_use]
+    pub fn authenticate(&self, token: &str) -> Option<(String, Credential)> {
+        let token_sha256 = hash_token(token);
+        let find = |credentials: &BTreeMap<String, Credential>| {
+            credentials
+                .iter()
+                .find(|(_, credential)| credential.token_sha256 == token_sha256)
+                .map(|(id, credential)| (id.clone(), credential.clone()))
+        };
+        find(&self.configured.lock()).or_else(|| find(&self.enrolled.lock()))
+    }
+
+    /// Revokes a device, so that certificates issued to its serial are no
+    /// longer accepted.
+    pub fn revoke_serial(&self, serial: &str) {
+        self.revoked.lock().insert(serial.to_string());
+    }
+
+    /// Accepts a revoked device again, once it enrolled anew.
+    pub fn restore_serial(&self, serial: &str) {
+        self.revoked.lock().remove(serial);
+    }
+
+    /// Returns `true` if the device of the serial was revoked.
+    #[must_use]
+    pub fn is_revoked(&self, serial: &str) -> bool {
+        self.revoked.lock().contains(serial)
+    }
+}
@@ -0,0 +1,263 @@
+use bytes::{Bytes, BytesMut};
+use futures::stream::{self, BoxStream, StreamExt};
+use parking_lot::Mutex;
+use semver::Version;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use crate::firmware_manager::{FirmwareComponent, FirmwareInfo};
+use crate::in_flight::Abort;
+use crate::registry::{BlobDigest, FetchBlobStream};
+
+/// State of a firmware download shared between the request that started it
+/// and the requests attached to it.
+#[derive(Clone, Debug)]
+pub enum DownloadStatus {
+    /// Resolving the manifest and verifying the artifact signature.
+    Resolving,
+    /// Receiving blob data, with the expected size when announced by the registry.
+    Receiving(Option<u64>),
+    /// The firmware binary is fully received and matches its digest, with its
+    /// size. The remaining components of the artifact are being fetched.
+    Received(u64),
+    /// The firmware is fully downloaded and cached.
+    Complete(Arc<FirmwareInfo>),
+    /// The download failed, with the error message.
+    Failed(String),
+}
+
+/// An in-flight firmware download from the registry.
+///
+/// Chunks of the primary firmware binary are kept as they arrive so that any
+/// number of subscribers can stream it from the first byte while the download
+/// is still running, and CRC32, SHA-256 and the blob digest are computed
+/// incrementally. The last chunk is held back from subscribers until the blob
+/// matches its digest, so that a corrupted blob is never fully served. Once
+/// the download completes, subscribers switch to the assembled binary and the
+/// chunks are released.
+pub struct Download {
+    pub version: Version,
+    pub manifest_digest: String,
+    chunks: Mutex<Vec<Bytes>>,
+    status: watch::Sender<DownloadStatus>,
+}
+
+impl Download {
+    #[must_use]
+    pub fn new(version: Version, manifest_digest: String) -> Self {
+        let (status, _) = watch::channel(DownloadStatus::Resolving);
+        Self {
+            version,
+            manifest_digest,
+            chunks: Mutex::new(Vec::new()),
+            status,
+        }
+    }
+
+    /// Receives the blob stream until completion, publishing every chunk to
+    /// subscribers, and returns the assembled primary firmware component.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream fails, the blob is empty or doesn't
+    /// match its digest.
+    pub async fn receive(&self, blob: FetchBlobStream) -> anyhow::Result<FirmwareComponent> {
+        let FetchBlobStream {
+            mut stream,
+            size,
+            primary,
+            ..
+        } = blob;
+
+        self.status.send_replace(DownloadStatus::Receiving(size));
+
+        let mut digest = BlobDigest::new(primary.digest())?;
+        let mut crc = crc32fast::Hasher::new();
+        let mut sha256 = Sha256::new();
+        let mut len = 0;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            digest.update(&chunk);
+            crc.update(&chunk);
+            sha256.update(&chunk);
+            len += chunk.len();
+            self.chunks.lock().push(chunk);
+            // Wake up subscribers waiting for the next chunk
+            self.status.send_modify(|_| {});
+        }
+
+        if len == 0 {
+            return Err(anyhow::anyhow!("Fetched artifact blob is empty"));
+        }
+        digest.verify()?;
+        self.status
+            .send_replace(DownloadStatus::Received(u64::try_from(len)?));
+
+        let binary = {
+            let chunks = self.chunks.lock();
+            let mut binary = BytesMut::with_capacity(len);
+            for chunk in chunks.iter() {
+                binary.extend_from_slice(chunk);
+            }
+            binary.freeze()
+        };
+
+        Ok(FirmwareComponent {
+            name: primary.name,
+            media_type: primary.media_type,
+            binary,
+            crc: crc.finalize(),
+            sha256: format!("{:x}", sha256.finalize()),
+            size: len,
+        })
+    }
+
+    /// Marks the download as complete, releasing the buffered chunks.
+    pub fn complete(&self, firmware: Arc<FirmwareInfo>) {
+        self.status.send_replace(DownloadStatus::Complete(firmware));
+        self.chunks.lock().clear();
+    }
+
+    /// Marks the download as failed.
+    pub fn fail(&self, error: &anyhow::Error) {
+        self.status
+            .send_replace(DownloadStatus::Failed(format!("{error:#}")));
+        self.chunks.lock().clear();
+    }
+
+    /// Waits until `done` returns `true` for the download status and returns it.
+    async fn wait_until(&self, done: impl FnMut(&DownloadStatus) -> bool) -> DownloadStatus {
+        let mut rx = self.status.subscribe();
+        let status = match rx.wait_for(done).await {
+            Ok(status) => status.clone(),
+            Err(_) => DownloadStatus::Failed("Download was aborted".to_string()),
+        };
+        status
+    }
+
+    /// Waits for the download to finish and returns the firmware.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the download failed.
+    pub async fn wait(&self) -> anyhow::Result<Arc<FirmwareInfo>> {
+        let status = self
+            .wait_until(|s| matches!(s, DownloadStatus::Complete(_) | DownloadStatus::Failed(_)))
+            .await;
+
+        match status {
+            DownloadStatus::Complete(firmware) => Ok(firmware),
+            DownloadStatus::Failed(e) => Err(anyhow::anyhow!(e)),
+            DownloadStatus::Resolving
+            | DownloadStatus::Receiving(_)
+            | DownloadStatus::Received(_) => Err(anyhow::anyhow!("Download did not finish")),
+        }
+    }
+
+    /// Waits for the blob download to start, i.e. once the artifact has been
+    /// resolved and verified.
+    pub async fn started(&self) -> DownloadStatus {
+        self.wait_until(|s| !matches!(s, DownloadStatus::Resolving))
+            .await
+    }
+
+    /// Streams the firmware from the first byte, following the download as
+    /// new chunks arrive.
+    #[must_use]
+    pub fn subscribe(self: &Arc<Self>) -> BoxStream<'static, std::io::Result<Bytes>> {
+        let state = Subscriber {
+            download: Arc::clone(self),
+            rx: self.status.subscribe(),
+            next_chunk: 0,
+            offset: 0,
+            done: false,
+        };
+
+        stream::unfold(state, |mut state| async move {
+            if state.done {
+                return None;
+            }
+
+            loop {
+                let status = state.rx.borrow_and_update().clone();
+                match status {
+                    DownloadStatus::Complete(firmware) => {
+                        // Serve whatever the subscriber hasn't received yet
+                        state.done = true;
+                        if state.offset >= firmware.binary.len() {
+                            return None;
+                        }
+                        let remaining = firmware.binary.slice(state.offset..);
+                        return Some((Ok(remaining), state));
+                    }
+                    DownloadStatus::Failed(e) => {
+                        state.done = true;
+                        return Some((Err(std::io::Error::other(e)), state));
+                    }
+                    DownloadStatus::Received(size)
+                        if u64::try_from(state.offset).is_ok_and(|offset| offset >= size) =>
+                    {
+                        // The whole binary was served, don't wait for the
+                        // remaining components of the artifact
+                        return None;
+                    }
+                    DownloadStatus::Resolving
+                    | DownloadStatus::Receiving(_)
+                    | DownloadStatus::Received(_) => {}
+                }
+
+                // The last chunk received is held back until the blob matches
+                // its digest
+                let received = matches!(status, DownloadStatus::Received(_));
+                let chunk = {
+                    let chunks = state.download.chunks.lock();
+                    if received || state.next_chunk + 1 < chunks.len() {
+                        chunks.get(state.next_chunk).cloned()
+                    } else {
+                        None
+                    }
+                };
+                if let Some(chunk) = chunk {
+                    state.next_chunk += 1;
+                    state.offset += chunk.len();
+                    return Some((Ok(chunk), state));
+                }
+
+                if state.rx.changed().await.is_err() {
+                    state.done = true;
+                    return Some((Err(std::io::Error::other("Download was aborted")), state));
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+impl Abort for Download {
+    fn abort(&self) {
+        let aborted = self.status.send_if_modified(|status| {
+            if matches!(
+                status,
+                DownloadStatus::Complete(_) | DownloadStatus::Failed(_)
+            ) {
+                return false;
+            }
+            *status = DownloadStatus::Failed("Download was aborted".to_string());
+            true
+        });
+        if aborted {
+            self.chunks.lock().clear();
+        }
+    }
+}
+
+/// Position of a subscriber in a [`Download`].
+struct Subscriber {
+    download: Arc<Download>,
+    rx: watch::Receiver<DownloadStatus>,
+    next_chunk: usize,
+    offset: usize,
+    done: bool,
+}
@@ -0,0 +1,243 @@
+use anyhow::{anyhow, Context, Result};
+use parking_lot::Mutex;
+use rcgen::{
+    Certificate, CertificateParams, CertificateSigningRequestParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SerialNumber,
+};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::credential::hash_token;
+use crate::pin::glob_matches;
+
+/// Lifetime of enrollment codes created without one.
+pub const DEFAULT_CODE_TTL: Duration = Duration::from_hours(7 * 24);
+
+/// Lifetime of certificates issued to enrolling devices by default.
+pub const DEFAULT_CERTIFICATE_VALIDITY: Duration = Duration::from_hours(365 * 24);
+
+/// Returns a random secret, hex-encoded, for enrollment codes and device
+/// tokens.
+#[must_use]
+pub fn generate_secret() -> String {
+    hex(&rand::random::<[u8; 32]>())
+}
+
+/// Returns the lowercase hex encoding of bytes.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
+
+/// One-time code a device redeems to enroll.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnrollmentCode {
+    /// Hex-encoded SHA-256 of the code.
+    pub code_sha256: String,
+    /// Device IDs the device may enroll as, as globs.
+    pub devices: Vec<String>,
+    pub expires_at: SystemTime,
+}
+
+impl EnrollmentCode {
+    /// Creates an enrollment code, valid for `ttl` from `now`.
+    #[must_use]
+    pub fn new(code: &str, devices: Vec<String>, now: SystemTime, ttl: Duration) -> Self {
+        Self {
+            code_sha256: hash_token(code),
+            devices,
+            expires_at: now + ttl,
+        }
+    }
+}
+
+/// Pending enrollment codes by device serial.
+#[derive(Debug, Default)]
+pub struct EnrollmentCodes {
+    codes: Mutex<BTreeMap<String, EnrollmentCode>>,
+}
+
+impl EnrollmentCodes {
+    /// Returns the pending codes, sorted by serial.
+    #[must_use]
+    pub fn list(&self) -> Vec<(String, EnrollmentCode)> {
+        self.codes
+            .lock()
+            .iter()
+            .map(|(serial, code)| (serial.clone(), code.clone()))
+            .collect()
+    }
+
+    /// Creates or replaces the code of a device.
+    pub fn set(&self, serial: &str, code: EnrollmentCode) {
+        self.codes.lock().insert(serial.to_string(), code);
+    }
+
+    /// Removes the code of a device, returning it if it existed.
+    pub fn remove(&self, serial: &str) -> Option<EnrollmentCode> {
+        self.codes.lock().remove(serial)
+    }
+
+    /// Redeems the code of a device enrolling as `device_id`, consuming it.
+    ///
+    /// Returns `false` if the device has no code, the code doesn't match or
+    /// expired, or it doesn't allow the device ID. Expired codes are dropped.
+    pub fn redeem(&self, serial: &str, code: &str, device_id: &str, now: SystemTime) -> bool {
+        let mut codes = self.codes.lock();
+        let Some(pending) = codes.get(serial) else {
+            return false;
+        };
+
+        if pending.expires_at <= now {
+            codes.remove(serial);
+            return false;
+        }
+        let valid = pending.code_sha256 == hash_token(code)
+            && pending
+                .devices
+                .iter()
+                .any(|pattern| glob_matches(pattern, device_id));
+        if valid {
+            codes.remove(serial);
+        }
+        valid
+    }
+}
+
+/// Certificate issued to an enrolling device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IssuedCertificate {
+    /// PEM-encoded certificate.
+    pub pem: String,
+    /// Hex-encoded serial number of the certificate.
+    pub serial_number: String,
+}
+
+/// CA signing the certificate requests of enrolling devices.
+pub struct CertificateAuthority {
+    issuer: Certificate,
+    key: KeyPair,
+    certificate_pem: String,
+    validity: Duration,
+}
+
+impl fmt::Debug for CertificateAuthority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertificateAuthority")
+            .field("validity", &self.validity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CertificateAuthority {
+    /// Loads the PEM certificate and private key of the CA.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate or key can't be read or is invalid.
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        let certificate_pem = std::fs::read_to_string(cert_path).with_context(|| {
+            format!(
+                "Failed to read enrollment CA certificate {}",
+                cert_path.display()
+            )
+        })?;
+        let key_pem = std::fs::read_to_string(key_path)
+            .with_context(|| format!("Failed to read enrollment CA key {}", key_path.display()))?;
+
+        let key = KeyPair::from_pem(&key_pem).context("Invalid enrollment CA key")?;
+        // Re-signing the parsed CA certificate with its key yields an issuer
+        // with the same subject and key identifier as the original
+        let issuer = CertificateParams::from_ca_cert_pem(&certificate_pem)
+            .context("Invalid enrollment CA certificate")?
+            .self_signed(&key)
+            .context("Enrollment CA key doesn't match its certificate")?;
+
+        Ok(Self {
+            issuer,
+            key,
+            certificate_pem,
+            validity: DEFAULT_CERTIFICATE_VALIDITY,
+        })
+    }
+
+    /// Sets the lifetime of issued certificates.
+    #[must_use]
+    pub fn with_validity(mut self, validity: Duration) -> Self {
+        self.validity = validity;
+        self
+    }
+
+    /// Returns the PEM certificate of the CA.
+    #[must_use]
+    pub fn certificate_pem(&self) -> &str {
+        &self.certificate_pem
+    }
+
+    /// Signs a PEM certificate signing request of a device.
+    ///
+    /// Only the public key of the request is kept: the certificate names the
+    /// serial as its common name and the device ID as its organizational unit,
+    /// and is restricted to client authentication, whatever the request asked
+    /// for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request is invalid or can't be signed.
+    pub fn sign(
+        &self,
+        csr_pem: &str,
+        serial: &str,
+        device_id: &str,
+        now: SystemTime,
+    ) -> Result<IssuedCertificate> {
+        let mut csr = CertificateSigningRequestParams::from_pem(csr_pem)
+            .map_err(|e| anyhow!("Invalid certificate signing request: {e}"))?;
+
+        let mut serial_number = rand::random::<[u8; 16]>();
+        // Keep the serial number positive once DER-encoded
+        serial_number[0] &= 0x7f;
+
+        let mut params = CertificateParams::new(Vec::<String>::new())?;
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, serial);
+        params
+            .distinguished_name
+            .push(DnType::OrganizationalUnitName, device_id);
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.serial_number = Some(SerialNumber::from(serial_number.to_vec()));
+        params.not_before = now.into();
+        params.not_after = (now + self.validity).into();
+        csr.params = params;
+
+        let certificate = csr.signed_by(&self.issuer, &self.key)?;
+        Ok(IssuedCertificate {
+            pem: certificate.pem(),
+            serial_number: hex(&serial_number),
+        })
+    }
+}
+
+/// Device enrollment: pending codes, and the CA signing device certificates
+/// when configured.
+#[derive(Debug, Default)]
+pub struct Enrollment {
+    pub codes: EnrollmentCodes,
+    pub authority: Option<CertificateAuthority>,
+}
+
+impl Enrollment {
+    #[must_use]
+    pub fn new(authority: Option<CertificateAuthority>) -> Self {
+        Self {
+            codes: EnrollmentCodes::default(),
+            authority,
+        }
+    }
+}
@@ -1,15 +1,29 @@
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
+use futures::stream::BoxStream;
 use lru::LruCache;
 use parking_lot::Mutex;
 use semver::Version;
-use std::collections::HashSet;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
 use std::num::NonZeroUsize;
 use std::sync::Arc;
-use tokio::sync::broadcast;
-use tracing::{debug, info, instrument, warn};
+use std::time::SystemTime;
+use tracing::{debug, info, instrument, warn, Instrument};
 
+use crate::channel::Channel;
+use crate::compression::{CompressedFirmware, ContentEncoding};
+use crate::download::{Download, DownloadStatus};
+use crate::halt::{Halt, Halts, ReleaseHalted, RepositoryPolicy};
+use crate::in_flight::{Abort, InFlight};
+use crate::inventory::{self, DeviceStore, UpdateReport};
+use crate::pin::{self, PinRule, PinTarget, Pins};
 use crate::registry::RegistryClient;
+use crate::rollback::{RollbackTarget, Rollbacks};
+use crate::rollout::{RepositorySchedule, Rollout, Rollouts};
+use crate::upgrade_path::{self, SteppingStone, UpgradePaths};
+use crate::yank::{Yank, YankedRelease, Yanks};
 
 /// Default maximum number of firmware entries to cache.
 const DEFAULT_CACHE_SIZE: usize = 100;
@@ -18,24 +32,367 @@ const DEFAULT_CACHE_SIZE: usize = 100;
 pub struct FirmwareInfo {
     pub binary: Bytes,
     pub crc: u32,
+    /// Hex-encoded SHA-256 digest of the firmware binary.
+    pub sha256: String,
     pub version: Version,
     pub size: usize,
     /// The manifest digest from the registry, used to detect rebuilt artifacts
     /// with the same semver tag.
     pub manifest_digest: String,
+    /// Every component (layer) of the artifact. The first one is the primary
+    /// firmware binary described by the fields above.
+    pub components: Vec<FirmwareComponent>,
+}
+
+/// A single component of a multi-layer firmware artifact, such as the
+/// bootloader, partition table or filesystem image.
+#[derive(Clone, Debug)]
+pub struct FirmwareComponent {
+    /// Component name, from the layer title annotation or its media type.
+    pub name: String,
+    pub media_type: String,
+    pub binary: Bytes,
+    pub crc: u32,
+    /// Hex-encoded SHA-256 digest of the component binary.
+    pub sha256: String,
+    pub size: usize,
+}
+
+impl FirmwareComponent {
+    /// Creates a component from its binary, computing its checksums.
+    #[must_use]
+    pub fn new(name: String, media_type: String, binary: Bytes) -> Self {
+        Self {
+            name,
+            media_type,
+            crc: crc32fast::hash(&binary),
+            sha256: format!("{:x}", Sha256::digest(&binary)),
+            size: binary.len(),
+            binary,
+        }
+    }
+
+    /// Returns `true` if `selector` designates this component, either by its
+    /// exact name, its name without file extension, or its media type.
+    #[must_use]
+    pub fn matches(&self, selector: &str) -> bool {
+        self.name == selector
+            || self.name.split('.').next() == Some(selector)
+            || self.media_type == selector
+    }
+}
+
+impl FirmwareInfo {
+    /// Creates the firmware of an artifact from its components, the first one
+    /// being the primary firmware binary.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `components` is empty.
+    pub fn new(
+        version: Version,
+        manifest_digest: String,
+        components: Vec<FirmwareComponent>,
+    ) -> Result<Self> {
+        let primary = components
+            .first()
+            .ok_or_else(|| anyhow!("Firmware artifact has no components"))?;
+
+        Ok(Self {
+            binary: primary.binary.clone(),
+            crc: primary.crc,
+            sha256: primary.sha256.clone(),
+            version,
+            size: primary.size,
+            manifest_digest,
+            components,
+        })
+    }
+
+    /// Finds a component by name or media type, see [`FirmwareComponent::matches`].
+    #[must_use]
+    pub fn component(&self, selector: &str) -> Option<&FirmwareComponent> {
+        self.components.iter().find(|c| c.matches(selector))
+    }
+
+    /// Returns the entity tag for a component of this firmware served with the
+    /// given content encoding. The primary component shares the firmware
+    /// entity tag, see [`FirmwareInfo::encoded_etag`], while other components
+    /// are scoped by their name.
+    #[must_use]
+    pub fn component_etag(
+        &self,
+        component: &FirmwareComponent,
+        encoding: ContentEncoding,
+    ) -> String {
+        if self
+            .components
+            .first()
+            .is_some_and(|primary| std::ptr::eq(primary, component))
+        {
+            return self.encoded_etag(encoding);
+        }
+
+        match encoding {
+            ContentEncoding::Identity => {
+                format!("\"{}/{}\"", self.manifest_digest, component.name)
+            }
+            _ => format!(
+                "\"{}/{}+{}\"",
+                self.manifest_digest,
+                component.name,
+                encoding.as_str()
+            ),
+        }
+    }
+
+    /// Returns the strong HTTP entity tag for this firmware, derived from the
+    /// manifest digest so it changes whenever the artifact is rebuilt.
+    #[must_use]
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.manifest_digest)
+    }
+
+    /// Returns the entity tag for this firmware served with the given content
+    /// encoding, so that compressed and raw representations never share one.
+    #[must_use]
+    pub fn encoded_etag(&self, encoding: ContentEncoding) -> String {
+        match encoding {
+            ContentEncoding::Identity => self.etag(),
+            _ => format!("\"{}+{}\"", self.manifest_digest, encoding.as_str()),
+        }
+    }
+
+    /// Returns `true` if this firmware is newer than the `current` version
+    /// reported by a device, following semver precedence rules.
+    #[must_use]
+    pub fn is_update_for(&self, current: &Version) -> bool {
+        self.version > *current
+    }
+}
+
+/// Identifies the firmware requested by a device.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FirmwareQuery {
+    /// The unique identifier of the device (repository name).
+    pub device_id: String,
+    /// Hardware variant (board revision, chip) selecting a manifest when a
+    /// release is published as an image index.
+    pub hw: Option<String>,
+    /// Release channel, the server default when not set.
+    pub channel: Option<Channel>,
+    /// Serial number of the device, bucketing it into staged rollouts.
+    pub serial: Option<String>,
+    /// Group of the device (site, rack, ...), matched by pin rules.
+    pub group: Option<String>,
+    /// Firmware version running on the device, selecting the next hop of its
+    /// upgrade path.
+    pub current: Option<Version>,
+}
+
+impl FirmwareQuery {
+    #[must_use]
+    pub fn new(device_id: impl Into<String>) -> Self {
+        Self {
+            device_id: device_id.into(),
+            hw: None,
+            channel: None,
+            serial: None,
+            group: None,
+            current: None,
+        }
+    }
+
+    /// Sets the requested hardware variant.
+    #[must_use]
+    pub fn with_hw(mut self, hw: Option<String>) -> Self {
+        self.hw = hw.filter(|hw| !hw.is_empty());
+        self
+    }
+
+    /// Sets the requested release channel.
+    #[must_use]
+    pub fn with_channel(mut self, channel: Option<Channel>) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    /// Sets the serial number of the device.
+    #[must_use]
+    pub fn with_serial(mut self, serial: Option<String>) -> Self {
+        self.serial = serial.filter(|serial| !serial.is_empty());
+        self
+    }
+
+    /// Sets the group of the device.
+    #[must_use]
+    pub fn with_group(mut self, group: Option<String>) -> Self {
+        self.group = group.filter(|group| !group.is_empty());
+        self
+    }
+
+    /// Sets the firmware version running on the device.
+    #[must_use]
+    pub fn with_current(mut self, current: Option<Version>) -> Self {
+        self.current = current;
+        self
+    }
+
+    /// Returns the key of the firmware `version` selected for this query in
+    /// the firmware cache, so that every version and hardware variant of a
+    /// device is cached separately.
+    fn cache_key(&self, version: &Version) -> String {
+        match &self.hw {
+            Some(hw) => format!("{}#{hw}:{version}", self.device_id),
+            None => format!("{}:{version}", self.device_id),
+        }
+    }
+}
+
+/// Returns the device ID of a cache key, see [`FirmwareQuery::cache_key`].
+fn cache_key_device(key: &str) -> &str {
+    key.split(['#', ':']).next().unwrap_or(key)
+}
+
+/// A binary patch (bsdiff) turning a device's current firmware into `target`.
+#[derive(Clone, Debug)]
+pub struct DeltaInfo {
+    pub patch: Bytes,
+    pub from_version: Version,
+    /// Manifest digest of the firmware the patch applies to.
+    pub from_digest: String,
+    /// The firmware reconstructed by applying the patch, used to expose its
+    /// checksums and size so devices can verify the result.
+    pub target: Arc<FirmwareInfo>,
+}
+
+/// Identifies a cached delta by the manifest digests of both of its ends.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct DeltaKey {
+    cache_key: String,
+    from_digest: String,
+    to_digest: String,
+}
+
+/// Identifies the manifest a tag points to for a hardware variant of a device.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ManifestKey {
+    device_id: String,
+    hw: Option<String>,
+    tag: String,
 }
 
 struct CacheState {
+    /// Firmware per device, hardware variant and version, see [`FirmwareQuery::cache_key`].
     entries: LruCache<String, Arc<FirmwareInfo>>,
-    /// Tracks device IDs currently being fetched to prevent thundering herd.
-    in_flight: HashSet<String>,
+    /// Generated binary patches, evicted independently from full images.
+    deltas: LruCache<DeltaKey, Arc<DeltaInfo>>,
+    /// Compressed firmware variants, keyed by component SHA-256 and encoding.
+    compressed: LruCache<(String, ContentEncoding), Arc<CompressedFirmware>>,
+    /// Downloads currently in progress per cache key, to prevent thundering herd.
+    in_flight: HashMap<String, Arc<Download>>,
+    /// Deltas currently being generated, attached to by concurrent requests.
+    deltas_in_flight: HashMap<DeltaKey, Arc<InFlight<DeltaInfo>>>,
+    /// Firmware currently being compressed, attached to by concurrent requests.
+    compressing: HashMap<(String, ContentEncoding), Arc<InFlight<CompressedFirmware>>>,
+    /// Tags of each device when they were last listed.
+    tags: HashMap<String, Vec<String>>,
+    /// Manifest digest each tag was last resolved to, forgotten when the tags
+    /// of its device change.
+    manifest_digests: HashMap<ManifestKey, String>,
+    /// Manifest annotations per manifest digest, which never change for a
+    /// given digest.
+    annotations: LruCache<String, Arc<BTreeMap<String, String>>>,
+}
+
+/// Removes work from its in-flight map when dropped, aborting it if it didn't
+/// finish, so that requests attached to a failed or panicked task are woken
+/// up rather than left waiting.
+struct InFlightGuard<K: Eq + Hash, T: Abort> {
+    cache: Arc<Mutex<CacheState>>,
+    in_flight: fn(&mut CacheState) -> &mut HashMap<K, Arc<T>>,
+    key: K,
+    work: Arc<T>,
+}
+
+impl<K: Eq + Hash, T: Abort> Drop for InFlightGuard<K, T> {
+    fn drop(&mut self) {
+        let mut cache = self.cache.lock();
+        let in_flight = (self.in_flight)(&mut cache);
+        // A later request may have started over once the work completed
+        if in_flight
+            .get(&self.key)
+            .is_some_and(|work| Arc::ptr_eq(work, &self.work))
+        {
+            in_flight.remove(&self.key);
+        }
+        drop(cache);
+
+        self.work.abort();
+    }
+}
+
+/// Result of looking up the latest firmware of a device.
+enum Lookup {
+    Cached(Arc<FirmwareInfo>),
+    Downloading(Arc<Download>),
+}
+
+/// Firmware being streamed from the registry while it is downloaded.
+pub struct FirmwareStream {
+    pub version: Version,
+    pub manifest_digest: String,
+    /// Size of the firmware binary announced by the registry.
+    pub size: u64,
+    pub stream: BoxStream<'static, std::io::Result<Bytes>>,
+}
+
+impl FirmwareStream {
+    /// Returns the strong HTTP entity tag for this firmware, see [`FirmwareInfo::etag`].
+    #[must_use]
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.manifest_digest)
+    }
+}
+
+/// Firmware returned by [`FirmwareManager::get_firmware_stream`].
+pub enum FirmwareBody {
+    /// The firmware is fully available, typically from the cache.
+    Buffered(Arc<FirmwareInfo>),
+    /// The firmware is being downloaded from the registry.
+    Streaming(FirmwareStream),
+}
+
+impl FirmwareBody {
+    /// Returns the version of the firmware.
+    #[must_use]
+    pub fn version(&self) -> &Version {
+        match self {
+            Self::Buffered(fw) => &fw.version,
+            Self::Streaming(stream) => &stream.version,
+        }
+    }
 }
 
 pub struct FirmwareManager {
-    cache: Mutex<CacheState>,
+    cache: Arc<Mutex<CacheState>>,
     client: Arc<RegistryClient>,
-    /// Channel to notify waiting requests when a fetch completes.
-    fetch_complete_tx: broadcast::Sender<String>,
+    /// Channel served to devices that don't request one.
+    default_channel: Channel,
+    /// Staged rollouts per device ID, adjustable at runtime.
+    rollouts: Rollouts,
+    /// Releases withdrawn per device ID, never served to devices.
+    yanks: Yanks,
+    /// Mandatory intermediate releases per device ID.
+    upgrade_paths: UpgradePaths,
+    /// Older versions deliberately served per device ID and serial.
+    rollbacks: Rollbacks,
+    /// Rules holding devices on a release.
+    pins: Pins,
+    /// Releases halted on the failure rate reported by devices.
+    halts: Halts,
+    /// Store persisting rollouts across restarts, if any.
+    device_store: Option<Arc<dyn DeviceStore>>,
 }
 
 impl FirmwareManager {
@@ -123,50 +480,764 @@ impl FirmwareManager {
         let cache_capacity = NonZeroUsize::new(cache_size)
             .ok_or_else(|| anyhow!("Cache size must be greater than 0"))?;
 
-        let (fetch_complete_tx, _) = broadcast::channel(16);
-
         Ok(Self {
-            cache: Mutex::new(CacheState {
+            cache: Arc::new(Mutex::new(CacheState {
                 entries: LruCache::new(cache_capacity),
-                in_flight: HashSet::new(),
-            }),
+                deltas: LruCache::new(cache_capacity),
+                compressed: LruCache::new(cache_capacity),
+                in_flight: HashMap::new(),
+                deltas_in_flight: HashMap::new(),
+                compressing: HashMap::new(),
+                tags: HashMap::new(),
+                manifest_digests: HashMap::new(),
+                annotations: LruCache::new(cache_capacity),
+            })),
             client,
-            fetch_complete_tx,
+            default_channel: Channel::default(),
+            rollouts: Rollouts::default(),
+            yanks: Yanks::default(),
+            upgrade_paths: UpgradePaths::default(),
+            rollbacks: Rollbacks::default(),
+            pins: Pins::default(),
+            halts: Halts::default(),
+            device_store: None,
         })
     }
 
-    /// Fetches the latest semantic version tag for a given device ID from the registry.
+    /// Sets the release channel served to devices that don't request one.
+    #[must_use]
+    pub fn with_default_channel(mut self, channel: Channel) -> Self {
+        self.default_channel = channel;
+        self
+    }
+
+    /// Returns the release channel served to devices that don't request one.
+    #[must_use]
+    pub fn default_channel(&self) -> Channel {
+        self.default_channel
+    }
+
+    /// Resolves the release channel of a query.
+    fn channel(&self, query: &FirmwareQuery) -> Channel {
+        query.channel.unwrap_or(self.default_channel)
+    }
+
+    /// Sets the schedules automatically rolling out new versions per repository.
+    #[must_use]
+    pub fn with_rollout_schedules(mut self, schedules: Vec<RepositorySchedule>) -> Self {
+        self.rollouts = Rollouts::new(schedules);
+        self
+    }
+
+    /// Persists rollouts and halted releases in a device store, restoring
+    /// those saved before a restart. Must be set after the rollout schedules
+    /// and failure policies.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to read the saved state.
+    pub fn with_device_store(mut self, store: Arc<dyn DeviceStore>) -> Result<Self> {
+        self.rollouts.load(store.rollouts()?, SystemTime::now());
+
+        let halts = store.halts()?;
+        for (device_id, halt) in &halts {
+            info!(device_id = %device_id, version = %halt.version, "Restored halted release");
+            metrics::gauge!("firmware_release_halted", "device_id" => device_id.clone()).set(1.0);
+        }
+        self.halts.load(halts);
+
+        self.device_store = Some(store);
+        Ok(self)
+    }
+
+    /// Sets the releases withdrawn from devices at startup.
+    #[must_use]
+    pub fn with_yanks(mut self, yanks: Vec<Yank>) -> Self {
+        self.yanks = Yanks::new(yanks);
+        self
+    }
+
+    /// Sets the releases devices must install before any newer one.
+    #[must_use]
+    pub fn with_stepping_stones(mut self, stepping_stones: Vec<SteppingStone>) -> Self {
+        self.upgrade_paths = UpgradePaths::new(stepping_stones);
+        self
+    }
+
+    /// Returns all yanked releases, sorted by device ID.
+    #[must_use]
+    pub fn yanks(&self) -> Vec<Yank> {
+        self.yanks.list()
+    }
+
+    /// Yanks a release of a device ID, evicting it from the cache so devices
+    /// fall back to the previous release. Returns `false` if it was already
+    /// yanked.
+    pub fn yank(&self, device_id: &str, release: &YankedRelease) -> bool {
+        if !self.yanks.add(device_id, release.clone()) {
+            return false;
+        }
+        info!(device_id = %device_id, release = %release, "Yanked release");
+
+        let mut cache = self.cache.lock();
+        let stale: Vec<String> = cache
+            .entries
+            .iter()
+            .filter(|(key, fw)| {
+                cache_key_device(key) == device_id
+                    && self
+                        .yanks
+                        .is_yanked(device_id, &fw.version, &fw.manifest_digest)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &stale {
+            cache.entries.pop(key);
+        }
+
+        let stale_deltas: Vec<DeltaKey> = cache
+            .deltas
+            .iter()
+            .filter(|(key, _)| {
+                stale.contains(&key.cache_key)
+                    || (cache_key_device(&key.cache_key) == device_id
+                        && self.yanks.is_digest_yanked(device_id, &key.to_digest))
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &stale_deltas {
+            cache.deltas.pop(key);
+        }
+
+        debug!(
+            entries = stale.len(),
+            deltas = stale_deltas.len(),
+            "Evicted yanked firmware from cache"
+        );
+        self.update_cache_size_metric(&cache);
+        true
+    }
+
+    /// Restores a yanked release of a device ID. Returns `false` if it wasn't
+    /// yanked.
+    pub fn unyank(&self, device_id: &str, release: &YankedRelease) -> bool {
+        let removed = self.yanks.remove(device_id, release);
+        if removed {
+            info!(device_id = %device_id, release = %release, "Restored yanked release");
+        }
+        removed
+    }
+
+    /// Sets the rules holding devices on a release at startup.
+    #[must_use]
+    pub fn with_pins(mut self, rules: BTreeMap<String, PinRule>) -> Self {
+        self.pins = Pins::new(rules);
+        self
+    }
+
+    /// Returns all pin rules, sorted by ID.
+    #[must_use]
+    pub fn pins(&self) -> Vec<(String, PinRule)> {
+        self.pins.list()
+    }
+
+    /// Creates or replaces a pin rule.
+    pub fn set_pin(&self, id: &str, rule: PinRule) {
+        info!(pin = %id, device = %rule.device, target = %rule.target, "Set pin rule");
+        self.pins.set(id, rule);
+    }
+
+    /// Removes a pin rule, returning it if it existed.
+    pub fn remove_pin(&self, id: &str) -> Option<PinRule> {
+        let rule = self.pins.remove(id)?;
+        info!(pin = %id, "Removed pin rule");
+        Some(rule)
+    }
+
+    /// Returns the rollback target of a query, if any.
+    #[must_use]
+    pub fn rollback_target(&self, query: &FirmwareQuery) -> Option<Version> {
+        self.rollbacks
+            .get(&query.device_id, query.serial.as_deref())
+    }
+
+    /// Returns `true` if `firmware` is the rollback target of a query, and
+    /// may be served to devices running a newer version.
+    #[must_use]
+    pub fn is_rollback(&self, query: &FirmwareQuery, firmware_version: &Version) -> bool {
+        self.rollback_target(query)
+            .is_some_and(|target| target == *firmware_version)
+    }
+
+    /// Returns all rollback targets, sorted by device ID and serial.
+    #[must_use]
+    pub fn rollbacks(&self) -> Vec<RollbackTarget> {
+        self.rollbacks.list()
+    }
+
+    /// Creates or replaces a rollback target.
+    pub fn set_rollback(&self, target: RollbackTarget) {
+        info!(
+            device_id = %target.device,
+            serial = ?target.serial,
+            version = %target.version,
+            "Set rollback target"
+        );
+        self.rollbacks.set(target);
+    }
+
+    /// Removes a rollback target, returning its version if it existed.
+    pub fn remove_rollback(&self, device_id: &str, serial: Option<&str>) -> Option<Version> {
+        let version = self.rollbacks.remove(device_id, serial)?;
+        info!(device_id = %device_id, serial = ?serial, "Removed rollback target");
+        Some(version)
+    }
+
+    /// Returns the staged rollout of a device ID, if any.
+    #[must_use]
+    pub fn rollout(&self, device_id: &str) -> Option<Rollout> {
+        self.rollouts.get(device_id)
+    }
+
+    /// Returns all staged rollouts, sorted by device ID.
+    #[must_use]
+    pub fn rollouts(&self) -> Vec<(String, Rollout)> {
+        self.rollouts.list()
+    }
+
+    /// Creates or updates the staged rollout of a device ID.
+    pub async fn set_rollout(&self, device_id: &str, rollout: Rollout) {
+        self.rollouts.set(device_id, rollout);
+        self.save_rollout(device_id).await;
+    }
+
+    /// Removes the staged rollout of a device ID, returning it if it existed.
+    pub async fn remove_rollout(&self, device_id: &str) -> Option<Rollout> {
+        let rollout = self.rollouts.remove(device_id)?;
+        self.save_rollout(device_id).await;
+        Some(rollout)
+    }
+
+    /// Halts the staged rollout of a device ID at its current percentage,
+    /// returning it if it exists.
+    pub async fn halt_rollout(&self, device_id: &str) -> Option<Rollout> {
+        let rollout = self.rollouts.update(device_id, Rollout::halt)?;
+        info!(device_id = %device_id, version = %rollout.version, "Halted rollout");
+        self.save_rollout(device_id).await;
+        Some(rollout)
+    }
+
+    /// Resumes the halted rollout of a device ID, returning it if it exists.
+    pub async fn resume_rollout(&self, device_id: &str) -> Option<Rollout> {
+        let now = SystemTime::now();
+        let rollout = self.rollouts.update(device_id, |r| r.resume(now))?;
+        info!(device_id = %device_id, version = %rollout.version, "Resumed rollout");
+        self.save_rollout(device_id).await;
+        Some(rollout)
+    }
+
+    /// Persists the rollout state of a device ID in the device store, if any.
+    ///
+    /// Progress of scheduled rollouts isn't saved, it is derived again from
+    /// the start of their current step when restored.
+    async fn save_rollout(&self, device_id: &str) {
+        let Some(store) = &self.device_store else {
+            return;
+        };
+        let record = self.rollouts.record(device_id);
+        if let Err(e) =
+            inventory::run_blocking(Arc::clone(store), move |store| store.save_rollout(record))
+                .await
+        {
+            warn!(device_id = %device_id, error = ?e, "Failed to persist rollout");
+        }
+    }
+
+    /// Advances scheduled rollouts whose current step elapsed.
+    pub fn progress_rollouts(&self) {
+        self.rollouts.progress(SystemTime::now());
+    }
+
+    /// Sets the failure rates halting releases per repository.
+    #[must_use]
+    pub fn with_failure_policies(mut self, policies: Vec<RepositoryPolicy>) -> Self {
+        self.halts = Halts::new(policies);
+        self
+    }
+
+    /// Returns all halted releases, sorted by device ID and version.
+    #[must_use]
+    pub fn halts(&self) -> Vec<(String, Halt)> {
+        self.halts.list()
+    }
+
+    /// Records the install outcome reported by a device. When the failure
+    /// rate of the release exceeds the policy of its repository, the release
+    /// is halted: the device model is held on the releases preceding it and
+    /// its rollout is halted. Returns the halt when it was just tripped.
+    pub async fn record_outcome(&self, report: &UpdateReport) -> Option<Halt> {
+        let mut halt = self.halts.record(report, SystemTime::now())?;
+        let device_id = report.model.as_str();
+
+        // Devices are held below the lowest halted release
+        let lowest = self
+            .halts
+            .get(device_id)
+            .into_iter()
+            .map(|halt| halt.version)
+            .chain([halt.version.clone()])
+            .min()
+            .unwrap_or_else(|| halt.version.clone());
+        halt.previous = match self.previous_release(device_id, &lowest).await {
+            Ok(previous) => previous,
+            Err(e) => {
+                warn!(device_id = %device_id, error = ?e, "Failed to find release preceding halted release");
+                None
+            }
+        };
+        self.halts.set(device_id, halt.clone());
+        self.save_halt(device_id, Some(halt.clone())).await;
+        metrics::gauge!("firmware_release_halted", "device_id" => device_id.to_string()).set(1.0);
+
+        warn!(
+            device_id = %device_id,
+            version = %halt.version,
+            previous = ?halt.previous,
+            devices = halt.devices,
+            failed = halt.failed,
+            "Halted release on failure rate"
+        );
+
+        if self
+            .rollout(device_id)
+            .is_some_and(|rollout| rollout.version == halt.version)
+        {
+            self.halt_rollout(device_id).await;
+        }
+
+        Some(halt)
+    }
+
+    /// Lifts the halts of a device ID, resuming the rollout of a halted
+    /// release if any. Returns the lifted halts, lowest version first.
+    pub async fn resume_release(&self, device_id: &str) -> Vec<Halt> {
+        let halts = self.halts.remove(device_id);
+        if halts.is_empty() {
+            return halts;
+        }
+        self.save_halt(device_id, None).await;
+        metrics::gauge!("firmware_release_halted", "device_id" => device_id.to_string()).set(0.0);
+        for halt in &halts {
+            info!(device_id = %device_id, version = %halt.version, "Resumed halted release");
+        }
+
+        if self.rollout(device_id).is_some_and(|rollout| {
+            rollout.halted && halts.iter().any(|halt| halt.version == rollout.version)
+        }) {
+            self.resume_rollout(device_id).await;
+        }
+
+        halts
+    }
+
+    /// Persists a halted release of a device ID in the device store, or lifts
+    /// all of its halts when `halt` is `None`, if any.
+    async fn save_halt(&self, device_id: &str, halt: Option<Halt>) {
+        let Some(store) = &self.device_store else {
+            return;
+        };
+        let model = device_id.to_string();
+        if let Err(e) = inventory::run_blocking(Arc::clone(store), move |store| {
+            store.save_halt(&model, halt)
+        })
+        .await
+        {
+            warn!(device_id = %device_id, error = ?e, "Failed to persist halted release");
+        }
+    }
+
+    /// Returns the latest release of the default channel preceding `version`,
+    /// skipping yanked versions.
+    async fn previous_release(
+        &self,
+        device_id: &str,
+        version: &Version,
+    ) -> Result<Option<Version>> {
+        Ok(self
+            .versions_descending(device_id)
+            .await?
+            .into_iter()
+            .map(|(v, _)| v)
+            .filter(|v| v < version && self.default_channel.includes(v))
+            .find(|v| !self.yanks.is_version_yanked(device_id, v)))
+    }
+
+    /// Selects the version served to a device: its rollback target unless it
+    /// is yanked or halted, else the latest release of its channel preceding
+    /// the halted releases of its model, else the release of the pin rule
+    /// matching it, else the latest version of its channel.
+    ///
+    /// Returns [`ReleaseHalted`] when releases are halted and none precedes
+    /// them on the channel of the device.
+    async fn select_version(
+        &self,
+        query: &FirmwareQuery,
+        channel: Channel,
+    ) -> Result<(String, Version)> {
+        let device_id = query.device_id.as_str();
+
+        // Rollback targets are served as-is, even to devices running newer
+        // versions, unless they were withdrawn since
+        if let Some(target) = self.rollback_target(query) {
+            let tag = self.find_tag(device_id, &target).await?;
+            if self.is_withdrawn(query, &target, &tag).await? {
+                warn!(version = %target, "Rollback target is yanked or halted, ignoring it");
+            } else {
+                info!(version = %target, "Serving rollback target");
+                return Ok((tag, target));
+            }
+        }
+
+        // Halted releases hold the whole device model on the releases
+        // preceding the lowest of them
+        if let Some(halt) = self.halts.get(device_id).into_iter().next() {
+            let candidates: Vec<_> = self
+                .versions_descending(device_id)
+                .await?
+                .into_iter()
+                .filter(|(v, _)| *v < halt.version && channel.includes(v))
+                .collect();
+            let (tag, previous) =
+                self.first_installable(query, &candidates)
+                    .await?
+                    .ok_or(ReleaseHalted {
+                        version: halt.version.clone(),
+                    })?;
+            info!(halted = %halt.version, version = %previous, "Serving release preceding halted release");
+            return Ok((tag, previous));
+        }
+
+        if let Some((id, rule)) =
+            self.pins
+                .find(device_id, query.serial.as_deref(), query.group.as_deref())
+        {
+            let (tag, version) = self.resolve_pin(query, &rule.target).await?;
+            info!(pin = %id, target = %rule.target, version = %version, "Serving pinned version");
+            return Ok((tag, version));
+        }
+
+        self.get_latest_version(query, channel).await
+    }
+
+    /// Returns `true` if a release was withdrawn from a device: yanked, by
+    /// version or build, or halted.
+    async fn is_withdrawn(
+        &self,
+        query: &FirmwareQuery,
+        version: &Version,
+        tag: &str,
+    ) -> Result<bool> {
+        let device_id = query.device_id.as_str();
+        if self.yanks.is_version_yanked(device_id, version)
+            || self.halts.is_halted(device_id, version)
+        {
+            return Ok(true);
+        }
+        if !self.yanks.has_digests(device_id) {
+            return Ok(false);
+        }
+
+        let (digest, _) = self
+            .manifest_annotations(device_id, tag, query.hw.as_deref())
+            .await?;
+        Ok(self.yanks.is_digest_yanked(device_id, &digest))
+    }
+
+    /// Resolves the tag and version a pin rule holds a device on: its
+    /// target, or the latest release preceding it when the target is yanked
+    /// or the device must install a stepping stone first.
+    async fn resolve_pin(
+        &self,
+        query: &FirmwareQuery,
+        target: &PinTarget,
+    ) -> Result<(String, Version)> {
+        let device_id = query.device_id.as_str();
+        let versions = self.versions_descending(device_id).await?;
+
+        let (pinned, candidates) = match target {
+            PinTarget::Tag(tag) => {
+                let version = Version::parse(tag)
+                    .map_err(|e| anyhow!("Pinned tag '{tag}' is not a semantic version: {e}"))?;
+                if !versions.iter().any(|(v, _)| *v == version) {
+                    return Err(anyhow!("No tag found for {device_id} version {version}"));
+                }
+                (Some(version), versions)
+            }
+            PinTarget::Version(requirement) => {
+                let candidates: Vec<_> = versions
+                    .into_iter()
+                    .filter(|(v, _)| requirement.matches(v))
+                    .collect();
+                (candidates.first().map(|(v, _)| v.clone()), candidates)
+            }
+            PinTarget::Digest(digest) => {
+                let mut pinned = None;
+                for (version, tag) in &versions {
+                    let (manifest_digest, _) = self
+                        .manifest_annotations(device_id, tag, query.hw.as_deref())
+                        .await?;
+                    if manifest_digest == *digest {
+                        pinned = Some(version.clone());
+                        break;
+                    }
+                }
+                let pinned = pinned.ok_or_else(|| {
+                    anyhow!("No tag of {device_id} points to pinned digest {digest}")
+                })?;
+                (Some(pinned), versions)
+            }
+        };
+
+        let candidates: Vec<_> = candidates
+            .into_iter()
+            .filter(|(v, _)| pinned.as_ref().is_none_or(|pinned| v <= pinned))
+            .collect();
+        let (tag, version) = self
+            .first_installable(query, &candidates)
+            .await?
+            .ok_or_else(|| anyhow!("No installable version of {device_id} matches pin {target}"))?;
+
+        if pinned.as_ref().is_some_and(|pinned| *pinned != version) {
+            warn!(
+                target = %target,
+                pinned = ?pinned,
+                version = %version,
+                "Pinned release is yanked or requires a stepping stone, serving preceding release"
+            );
+        }
+        Ok((tag, version))
+    }
+
+    /// Returns the yanked release a pin rule holds devices on, if any.
+    #[must_use]
+    pub fn yanked_pin_target(&self, rule: &PinRule) -> Option<Yank> {
+        self.yanks.list().into_iter().find(|yank| {
+            pin::glob_matches(&rule.device, &yank.device)
+                && match (&rule.target, &yank.release) {
+                    (PinTarget::Tag(tag), YankedRelease::Version(version)) => {
+                        Version::parse(tag).is_ok_and(|tag| tag == *version)
+                    }
+                    (PinTarget::Digest(digest), YankedRelease::Digest(yanked)) => digest == yanked,
+                    _ => false,
+                }
+        })
+    }
+
+    /// Returns the semantic version tags of a device, latest first.
+    async fn versions_descending(&self, device_id: &str) -> Result<Vec<(Version, String)>> {
+        let tags = self.fetch_tags(device_id).await?;
+        let mut versions: Vec<_> = tags
+            .into_iter()
+            .filter_map(|t| Version::parse(&t).ok().map(|v| (v, t)))
+            .collect();
+        versions.sort_by(|(a, _), (b, _)| b.cmp(a));
+        Ok(versions)
+    }
+
+    /// Fetches the latest semantic version tag released on a channel for a given device
+    /// from the registry, honouring the staged rollout of the device ID and
+    /// skipping yanked releases.
+    ///
+    /// When the query carries the version running on the device, releases it
+    /// can't install yet are skipped, so the next hop of its upgrade path is
+    /// returned instead of the absolute latest.
     ///
     /// # Arguments
     ///
-    /// * `device_id` - The unique identifier of the device.
+    /// * `query` - The device to select the version for.
+    /// * `channel` - The release channel to select versions from.
     ///
     /// # Returns
     ///
     /// A `Result` containing a tuple of the latest tag string and its parsed `Version`,
     /// or an error if no valid semantic version tag is found or parsing fails.
-    #[instrument(skip(self), fields(device_id = %device_id))]
-    async fn get_latest_version(&self, device_id: &str) -> Result<(String, Version)> {
-        let tags = self.client.fetch_tags(device_id).await?;
+    #[instrument(skip(self, query), fields(device_id = %query.device_id, channel = %channel))]
+    async fn get_latest_version(
+        &self,
+        query: &FirmwareQuery,
+        channel: Channel,
+    ) -> Result<(String, Version)> {
+        let device_id = query.device_id.as_str();
+        let tags = self.fetch_tags(device_id).await?;
+        let versions: Vec<_> = tags
+            .into_iter()
+            .filter_map(|t| Version::parse(&t).ok().map(|v| (v, t)))
+            .collect();
 
-        let latest_tag = tags
-            .iter()
-            .filter_map(|t| Version::parse(t).ok().map(|v| (v, t)))
-            .max_by_key(|(v, _)| v.clone())
-            .map(|(_, t)| t.clone());
+        // Yanked versions are never served, nor rolled out
+        let versions: Vec<_> = versions
+            .into_iter()
+            .filter(|(v, _)| !self.yanks.is_version_yanked(device_id, v))
+            .collect();
 
-        let Some(latest_tag) = latest_tag else {
-            warn!("No semver tag for {}", device_id);
+        let now = SystemTime::now();
+        if self
+            .rollouts
+            .observe(device_id, versions.iter().map(|(v, _)| v), now)
+            .is_some()
+        {
+            self.save_rollout(device_id).await;
+        }
+        self.rollouts.progress(now);
+        let rollout = self.rollout(device_id);
+
+        let mut candidates: Vec<_> = versions
+            .into_iter()
+            .filter(|(v, _)| channel.includes(v))
+            .filter(|(v, _)| {
+                rollout
+                    .as_ref()
+                    .is_none_or(|r| r.allows(v, device_id, query.serial.as_deref()))
+            })
+            .collect();
+        candidates.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        let Some((latest_tag, latest_version)) = self.first_installable(query, &candidates).await?
+        else {
+            warn!("No semver tag for {} on channel {}", device_id, channel);
             // Return an error to prevent further processing if no valid semver tag found
-            return Err(anyhow!("No semver tag found for {device_id}"));
+            return Err(anyhow!(
+                "No semver tag found for {device_id} on channel {channel}"
+            ));
         };
 
-        let latest_version = Version::parse(&latest_tag)
-            .map_err(|e| anyhow!("Couldn't parse version from tag '{latest_tag}': {e}"))?;
-
         Ok((latest_tag, latest_version))
     }
 
+    /// Returns the first of `candidates`, sorted latest first, that may be
+    /// served to the device: skipping yanked releases and builds, and releases
+    /// it can't install before a stepping stone.
+    async fn first_installable(
+        &self,
+        query: &FirmwareQuery,
+        candidates: &[(Version, String)],
+    ) -> Result<Option<(String, Version)>> {
+        let device_id = query.device_id.as_str();
+
+        // Resolving manifests costs a registry request the first time, only do
+        // it when builds are yanked or the device may need a stepping stone.
+        // Stepping stones required by any release newer than the device apply
+        // to every later release, so all of them are resolved.
+        let current = query.current.as_ref();
+        let mut annotated = BTreeMap::new();
+        if let Some(current) = current {
+            for (version, tag) in candidates.iter().filter(|(v, _)| v > current) {
+                let (_, annotations) = self
+                    .manifest_annotations(device_id, tag, query.hw.as_deref())
+                    .await?;
+                if let Some(required) = upgrade_path::min_version(device_id, version, &annotations)
+                {
+                    annotated.insert(version.clone(), required);
+                }
+            }
+        }
+
+        let check_digest = self.yanks.has_digests(device_id);
+        for (version, tag) in candidates {
+            if self.yanks.is_version_yanked(device_id, version) {
+                continue;
+            }
+            if check_digest {
+                let (digest, _) = self
+                    .manifest_annotations(device_id, tag, query.hw.as_deref())
+                    .await?;
+                if self.yanks.is_digest_yanked(device_id, &digest) {
+                    debug!(version = %version, digest = %digest, "Skipping yanked build");
+                    continue;
+                }
+            }
+            if let Some(current) = current.filter(|current| *current < version) {
+                let required = self
+                    .upgrade_paths
+                    .required_version(device_id, version, &annotated);
+                if required.as_ref().is_some_and(|required| current < required) {
+                    debug!(
+                        version = %version,
+                        current = %current,
+                        required = ?required,
+                        "Skipping release requiring a stepping stone"
+                    );
+                    continue;
+                }
+            }
+            return Ok(Some((tag.clone(), version.clone())));
+        }
+
+        Ok(None)
+    }
+
+    /// Fetches the tags of a device, forgetting the manifests its tags were
+    /// resolved to when they changed since they were last listed.
+    async fn fetch_tags(&self, device_id: &str) -> Result<Vec<String>> {
+        let tags = self.client.fetch_tags(device_id).await?;
+
+        let mut cache = self.cache.lock();
+        if cache.tags.get(device_id) != Some(&tags) {
+            cache
+                .manifest_digests
+                .retain(|key, _| key.device_id != device_id);
+            cache.tags.insert(device_id.to_string(), tags.clone());
+        }
+        Ok(tags)
+    }
+
+    /// Returns the digest and annotations of the manifest a tag points to,
+    /// only requesting the registry if the tag wasn't resolved since the tags
+    /// of the device last changed.
+    async fn manifest_annotations(
+        &self,
+        device_id: &str,
+        tag: &str,
+        hw: Option<&str>,
+    ) -> Result<(String, Arc<BTreeMap<String, String>>)> {
+        let key = ManifestKey {
+            device_id: device_id.to_string(),
+            hw: hw.map(str::to_string),
+            tag: tag.to_string(),
+        };
+
+        {
+            let mut cache = self.cache.lock();
+            if let Some(digest) = cache.manifest_digests.get(&key).cloned() {
+                if let Some(annotations) = cache.annotations.get(&digest) {
+                    return Ok((digest, Arc::clone(annotations)));
+                }
+            }
+        }
+
+        let (digest, annotations) = self
+            .client
+            .fetch_manifest_annotations(device_id, tag, hw)
+            .await?;
+        let annotations = Arc::new(annotations);
+
+        let mut cache = self.cache.lock();
+        cache.manifest_digests.insert(key, digest.clone());
+        cache
+            .annotations
+            .put(digest.clone(), Arc::clone(&annotations));
+        Ok((digest, annotations))
+    }
+
+    /// Finds the registry tag matching the given semantic version.
+    async fn find_tag(&self, device_id: &str, version: &Version) -> Result<String> {
+        let tags = self.fetch_tags(device_id).await?;
+
+        tags.into_iter()
+            .find(|t| Version::parse(t).is_ok_and(|v| v == *version))
+            .ok_or_else(|| anyhow!("No tag found for {device_id} version {version}"))
+    }
+
     /// Updates the cache size metric gauge.
     #[allow(clippy::unused_self)]
     fn update_cache_size_metric(&self, cache: &CacheState) {
@@ -199,7 +1270,7 @@ impl FirmwareManager {
     ///
     /// # Arguments
     ///
-    /// * `device_id` - The unique identifier of the device.
+    /// * `query` - The device and hardware variant to retrieve the firmware for.
     ///
     /// # Returns
     ///
@@ -209,133 +1280,430 @@ impl FirmwareManager {
     ///
     /// Returns an error if:
     /// - No valid semantic version tag is found for the device.
+    /// - No manifest matches the requested hardware variant ([`VariantNotFound`](crate::registry::VariantNotFound)).
+    /// - No release precedes the halted releases of the device ([`ReleaseHalted`]).
     /// - Fetching the firmware blob from the registry fails.
-    #[instrument(skip(self), fields(device_id = %device_id))]
-    pub async fn get_firmware(&self, device_id: &str) -> Result<Arc<FirmwareInfo>> {
+    #[instrument(skip(self, query), fields(device_id = %query.device_id, hw = ?query.hw, channel = ?query.channel))]
+    pub async fn get_firmware(&self, query: &FirmwareQuery) -> Result<Arc<FirmwareInfo>> {
+        match self.lookup(query).await? {
+            Lookup::Cached(firmware) => Ok(firmware),
+            Lookup::Downloading(download) => {
+                debug!("Waiting for in-flight download to complete");
+                download.wait().await
+            }
+        }
+    }
+
+    /// Retrieves the latest firmware for the specified device, without waiting
+    /// for a cold fetch from the registry to complete.
+    ///
+    /// Cached firmware is returned as-is. Otherwise the caller receives the
+    /// firmware bytes as they arrive from the registry, while the cache fills in
+    /// the background. Concurrent requests attach to the same download.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - No valid semantic version tag is found for the device.
+    /// - No manifest matches the requested hardware variant ([`VariantNotFound`](crate::registry::VariantNotFound)).
+    /// - No release precedes the halted releases of the device ([`ReleaseHalted`]).
+    /// - Resolving or verifying the firmware artifact fails.
+    #[instrument(skip(self, query), fields(device_id = %query.device_id, hw = ?query.hw, channel = ?query.channel))]
+    pub async fn get_firmware_stream(&self, query: &FirmwareQuery) -> Result<FirmwareBody> {
+        let download = match self.lookup(query).await? {
+            Lookup::Cached(firmware) => return Ok(FirmwareBody::Buffered(firmware)),
+            Lookup::Downloading(download) => download,
+        };
+
+        match download.started().await {
+            DownloadStatus::Complete(firmware) => Ok(FirmwareBody::Buffered(firmware)),
+            DownloadStatus::Failed(e) => Err(anyhow!(e)),
+            DownloadStatus::Resolving | DownloadStatus::Receiving(None) => {
+                // Size is unknown, fall back to buffering the whole firmware
+                download.wait().await.map(FirmwareBody::Buffered)
+            }
+            DownloadStatus::Receiving(Some(size)) | DownloadStatus::Received(size) => {
+                Ok(FirmwareBody::Streaming(FirmwareStream {
+                    version: download.version.clone(),
+                    manifest_digest: download.manifest_digest.clone(),
+                    size,
+                    stream: download.subscribe(),
+                }))
+            }
+        }
+    }
+
+    /// Looks up the latest firmware in the cache, or attaches to (or starts) a
+    /// download from the registry.
+    async fn lookup(&self, query: &FirmwareQuery) -> Result<Lookup> {
         debug!("Fetching firmware for device");
 
-        let (latest_tag, latest_version) = self.get_latest_version(device_id).await?;
+        let device_id = query.device_id.as_str();
+        let channel = self.channel(query);
+        let (latest_tag, latest_version) = self.select_version(query, channel).await?;
         info!(version = %latest_version, "Found latest version for device");
 
         // Fetch manifest digest to detect rebuilt artifacts with same version
         let current_digest = self
             .client
-            .fetch_manifest_digest(device_id, &latest_tag)
+            .fetch_manifest_digest(device_id, &latest_tag, query.hw.as_deref())
             .await?;
+        let cache_key = query.cache_key(&latest_version);
 
         // Check cache and handle in-flight requests (thundering herd protection)
-        let should_fetch = {
-            let mut cache = self.cache.lock();
-            self.update_cache_size_metric(&cache);
+        let mut cache = self.cache.lock();
+        self.update_cache_size_metric(&cache);
 
-            if let Some(cached_firmware) = cache.entries.get(device_id) {
-                // Cache hit: check if version AND digest match (digest detects rebuilt artifacts)
-                if latest_version <= cached_firmware.version
-                    && current_digest == cached_firmware.manifest_digest
-                {
-                    debug!(
-                        version = %latest_version,
-                        digest = %current_digest,
-                        "Cache hit - firmware is up-to-date"
-                    );
-                    self.record_cache_hit(device_id);
-                    return Ok(Arc::clone(cached_firmware));
-                }
+        // Rebuilt artifacts are resolved again for their annotations
+        cache.manifest_digests.insert(
+            ManifestKey {
+                device_id: device_id.to_string(),
+                hw: query.hw.clone(),
+                tag: latest_tag.clone(),
+            },
+            current_digest.clone(),
+        );
+
+        if let Some(cached_firmware) = cache.entries.get(&cache_key) {
+            // Cache hit: check if version AND digest match (digest detects rebuilt artifacts)
+            if latest_version <= cached_firmware.version
+                && current_digest == cached_firmware.manifest_digest
+            {
                 debug!(
-                    cached_version = %cached_firmware.version,
-                    cached_digest = %cached_firmware.manifest_digest,
-                    latest_version = %latest_version,
-                    current_digest = %current_digest,
-                    "Cache stale - newer version or different digest"
+                    version = %latest_version,
+                    digest = %current_digest,
+                    "Cache hit - firmware is up-to-date"
                 );
+                self.record_cache_hit(device_id);
+                return Ok(Lookup::Cached(Arc::clone(cached_firmware)));
             }
+            debug!(
+                cached_version = %cached_firmware.version,
+                cached_digest = %cached_firmware.manifest_digest,
+                latest_version = %latest_version,
+                current_digest = %current_digest,
+                "Cache stale - newer version or different digest"
+            );
+        }
 
-            // Check if another request is already fetching this device
-            if cache.in_flight.contains(device_id) {
-                debug!("Another request is fetching firmware, waiting...");
-                false
-            } else {
-                cache.in_flight.insert(device_id.to_string());
-                true
-            }
+        // Attach to the download if another request is already fetching this device
+        if let Some(download) = cache.in_flight.get(&cache_key) {
+            debug!("Another request is fetching firmware, attaching to it");
+            return Ok(Lookup::Downloading(Arc::clone(download)));
+        }
+
+        debug!("Cache miss - fetching from registry");
+        self.record_cache_miss(device_id);
+
+        let download = Arc::new(Download::new(latest_version, current_digest));
+        cache
+            .in_flight
+            .insert(cache_key.clone(), Arc::clone(&download));
+        drop(cache);
+
+        let guard = InFlightGuard {
+            cache: Arc::clone(&self.cache),
+            in_flight: |cache: &mut CacheState| &mut cache.in_flight,
+            key: cache_key,
+            work: Arc::clone(&download),
         };
 
-        if !should_fetch {
-            // Wait for the in-flight request to complete
-            let mut rx = self.fetch_complete_tx.subscribe();
-            loop {
-                match rx.recv().await {
-                    Ok(completed_device) if completed_device == device_id => {
-                        break;
-                    }
-                    Ok(_) => {}
-                    Err(_) => {
-                        // Channel closed or lagged, try to get from cache anyway
-                        break;
-                    }
-                }
-            }
+        // The download runs in its own task so that it completes and fills the
+        // cache even if the request that started it goes away.
+        tokio::spawn(
+            Self::fetch_and_cache_firmware(
+                Arc::clone(&self.client),
+                query.clone(),
+                latest_tag,
+                guard,
+            )
+            .in_current_span(),
+        );
+
+        Ok(Lookup::Downloading(download))
+    }
 
-            // Check cache again after waiting
-            let cache = self.cache.lock();
-            if let Some(cached_firmware) = cache.entries.peek(device_id) {
-                debug!("Got firmware from cache after waiting");
-                return Ok(Arc::clone(cached_firmware));
+    /// Fetches firmware from the registry and caches it, publishing progress
+    /// to the requests attached to the download.
+    ///
+    /// The download is removed from the in-flight downloads once finished, or
+    /// aborted if the task panics.
+    async fn fetch_and_cache_firmware(
+        client: Arc<RegistryClient>,
+        query: FirmwareQuery,
+        latest_tag: String,
+        download: InFlightGuard<String, Download>,
+    ) {
+        let result = Self::fetch_components(&client, &query, &latest_tag, &download.work).await;
+
+        let mut cache = download.cache.lock();
+        match result {
+            Ok(info) => {
+                info!(bytes = info.size, "Downloaded firmware");
+                cache.entries.put(download.key.clone(), Arc::clone(&info));
+                debug!(version = %info.version, "Cached firmware");
+                download.work.complete(info);
             }
-            return Err(anyhow!(
-                "Failed to get firmware after waiting for in-flight request"
+            Err(e) => {
+                warn!(device_id = %query.device_id, hw = ?query.hw, error = ?e, "Failed to fetch firmware");
+                download.work.fail(&e);
+            }
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        metrics::gauge!("firmware_cache_entries").set(cache.entries.len() as f64);
+    }
+
+    /// Streams the primary firmware binary to the download subscribers, then
+    /// fetches the remaining components of the artifact.
+    async fn fetch_components(
+        client: &RegistryClient,
+        query: &FirmwareQuery,
+        latest_tag: &str,
+        download: &Download,
+    ) -> Result<Arc<FirmwareInfo>> {
+        let mut blob = client
+            .fetch_blob_stream(&query.device_id, latest_tag, query.hw.as_deref())
+            .await?;
+        let others = std::mem::take(&mut blob.components);
+        let manifest_digest = blob.manifest_digest.clone();
+
+        let mut components = vec![download.receive(blob).await?];
+        for component in &others {
+            let binary = client.fetch_component(component).await?;
+            debug!(component = %component.name, bytes = binary.len(), "Downloaded firmware component");
+            components.push(FirmwareComponent::new(
+                component.name.clone(),
+                component.media_type.clone(),
+                binary,
             ));
         }
 
-        // We're responsible for fetching - ensure we clean up in_flight on any exit path
-        let result = self
-            .fetch_and_cache_firmware(device_id, &latest_tag, latest_version)
-            .await;
+        Ok(Arc::new(FirmwareInfo::new(
+            download.version.clone(),
+            manifest_digest,
+            components,
+        )?))
+    }
 
-        // Clean up in_flight and notify waiters
-        {
-            let mut cache = self.cache.lock();
-            cache.in_flight.remove(device_id);
-            self.update_cache_size_metric(&cache);
+    /// Retrieves a binary patch from the `from` version to the latest firmware.
+    ///
+    /// Both artifacts are fetched from the registry, and the generated bsdiff
+    /// patch is cached by the manifest digests of its source and target so it is
+    /// only computed once per pair of builds.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The device and hardware variant to retrieve the patch for.
+    /// * `from` - The firmware version currently running on the device.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `Some(DeltaInfo)` when an update or a rollback
+    /// applies, or `None` when the device already runs the latest firmware or a
+    /// newer one, or no release precedes the halted releases of the device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The latest firmware cannot be retrieved.
+    /// - No tag matches the `from` version.
+    /// - Fetching the source firmware blob or computing the patch fails.
+    #[instrument(skip(self, query), fields(device_id = %query.device_id, hw = ?query.hw, channel = ?query.channel, from = %from))]
+    pub async fn get_delta(
+        &self,
+        query: &FirmwareQuery,
+        from: &Version,
+    ) -> Result<Option<Arc<DeltaInfo>>> {
+        // The target is the next hop of the upgrade path from the source version
+        let query = &query.clone().with_current(Some(from.clone()));
+        let target = match self.get_firmware(query).await {
+            Err(e) if e.is::<ReleaseHalted>() => return Ok(None),
+            target => target?,
+        };
+        let rollback = self.is_rollback(query, &target.version);
+        if target.version == *from || !(target.is_update_for(from) || rollback) {
+            return Ok(None);
         }
-        let _ = self.fetch_complete_tx.send(device_id.to_string());
 
-        result
+        let device_id = query.device_id.as_str();
+        let hw = query.hw.as_deref();
+        let from_tag = self.find_tag(device_id, from).await?;
+        let from_digest = self
+            .client
+            .fetch_manifest_digest(device_id, &from_tag, hw)
+            .await?;
+
+        let key = DeltaKey {
+            cache_key: query.cache_key(&target.version),
+            from_digest,
+            to_digest: target.manifest_digest.clone(),
+        };
+
+        let generation = {
+            let mut cache = self.cache.lock();
+            if let Some(delta) = cache.deltas.get(&key) {
+                debug!("Delta cache hit");
+                return Ok(Some(Arc::clone(delta)));
+            }
+
+            if let Some(generation) = cache.deltas_in_flight.get(&key) {
+                debug!("Another request is generating the delta, attaching to it");
+                Arc::clone(generation)
+            } else {
+                let generation = Arc::new(InFlight::default());
+                cache
+                    .deltas_in_flight
+                    .insert(key.clone(), Arc::clone(&generation));
+
+                let guard = InFlightGuard {
+                    cache: Arc::clone(&self.cache),
+                    in_flight: |cache: &mut CacheState| &mut cache.deltas_in_flight,
+                    key,
+                    work: Arc::clone(&generation),
+                };
+                let client = Arc::clone(&self.client);
+                let query = query.clone();
+                let from = from.clone();
+
+                // Generation runs in its own task so that it completes and
+                // fills the cache even if the request that started it goes away
+                tokio::spawn(
+                    async move {
+                        let result = Self::generate_delta(
+                            &client, &query, &from_tag, from, &guard.key, target,
+                        )
+                        .await;
+                        if let Ok(delta) = &result {
+                            guard
+                                .cache
+                                .lock()
+                                .deltas
+                                .put(guard.key.clone(), Arc::clone(delta));
+                        }
+                        guard.work.finish(&result);
+                    }
+                    .in_current_span(),
+                );
+
+                generation
+            }
+        };
+
+        generation.wait().await.map(Some)
     }
 
-    /// Fetches firmware from the registry and caches it.
-    async fn fetch_and_cache_firmware(
+    /// Fetches the source firmware of a delta from the registry and computes
+    /// the patch to the target firmware.
+    async fn generate_delta(
+        client: &RegistryClient,
+        query: &FirmwareQuery,
+        from_tag: &str,
+        from: Version,
+        key: &DeltaKey,
+        target: Arc<FirmwareInfo>,
+    ) -> Result<Arc<DeltaInfo>> {
+        debug!("Delta cache miss - fetching source firmware from registry");
+        let source = client
+            .fetch_blob(&query.device_id, from_tag, query.hw.as_deref())
+            .await?;
+
+        let new = target.binary.clone();
+        let patch = tokio::task::spawn_blocking(move || {
+            let mut patch = Vec::new();
+            bsdiff::diff(&source.data, &new, &mut patch).map(|()| patch)
+        })
+        .await?
+        .map_err(|e| anyhow!("Failed to compute firmware delta: {e}"))?;
+
+        info!(
+            patch_bytes = patch.len(),
+            target_bytes = target.size,
+            "Computed firmware delta"
+        );
+
+        Ok(Arc::new(DeltaInfo {
+            patch: Bytes::from(patch),
+            from_version: from,
+            from_digest: key.from_digest.clone(),
+            target,
+        }))
+    }
+
+    /// Retrieves a compressed variant of the given firmware component.
+    ///
+    /// Compression is performed once per component binary and encoding, with
+    /// concurrent requests attaching to it, and the result is kept in the cache
+    /// for subsequent requests.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if compressing the firmware fails.
+    #[instrument(skip(self, firmware), fields(component = %firmware.name, encoding = encoding.as_str()))]
+    pub async fn get_compressed(
         &self,
-        device_id: &str,
-        latest_tag: &str,
-        latest_version: Version,
-    ) -> Result<Arc<FirmwareInfo>> {
-        debug!("Cache miss - fetching from registry");
-        self.record_cache_miss(device_id);
+        firmware: &FirmwareComponent,
+        encoding: ContentEncoding,
+    ) -> Result<Arc<CompressedFirmware>> {
+        let key = (firmware.sha256.clone(), encoding);
 
-        // No lock is held here during the await
-        let fetch_result = self.client.fetch_blob(device_id, latest_tag).await?;
-        let blob_len = fetch_result.data.len();
-        info!(bytes = blob_len, "Downloaded firmware");
-
-        let firmware_bytes = Bytes::from(fetch_result.data);
-        let crc = crc32fast::hash(&firmware_bytes);
-        let info = Arc::new(FirmwareInfo {
-            version: latest_version.clone(),
-            size: blob_len,
-            crc,
-            binary: firmware_bytes,
-            manifest_digest: fetch_result.manifest_digest,
-        });
-
-        // Reacquire the lock to update the cache
-        {
+        let compression = {
             let mut cache = self.cache.lock();
-            cache.entries.put(device_id.to_string(), Arc::clone(&info));
-            self.update_cache_size_metric(&cache);
-            debug!(version = %info.version, "Cached firmware");
-        }
+            if let Some(compressed) = cache.compressed.get(&key) {
+                debug!("Compressed firmware cache hit");
+                return Ok(Arc::clone(compressed));
+            }
+
+            if let Some(compression) = cache.compressing.get(&key) {
+                debug!("Another request is compressing the firmware, attaching to it");
+                Arc::clone(compression)
+            } else {
+                let compression = Arc::new(InFlight::default());
+                cache
+                    .compressing
+                    .insert(key.clone(), Arc::clone(&compression));
+
+                let guard = InFlightGuard {
+                    cache: Arc::clone(&self.cache),
+                    in_flight: |cache: &mut CacheState| &mut cache.compressing,
+                    key,
+                    work: Arc::clone(&compression),
+                };
+                let binary = firmware.binary.clone();
+
+                tokio::spawn(
+                    async move {
+                        let size = binary.len();
+                        let result = match tokio::task::spawn_blocking(move || {
+                            CompressedFirmware::new(encoding, &binary)
+                        })
+                        .await
+                        {
+                            Ok(result) => result.map(Arc::new),
+                            Err(e) => Err(e.into()),
+                        };
+
+                        if let Ok(compressed) = &result {
+                            info!(
+                                bytes = size,
+                                compressed_bytes = compressed.data.len(),
+                                "Compressed firmware"
+                            );
+                            guard
+                                .cache
+                                .lock()
+                                .compressed
+                                .put(guard.key.clone(), Arc::clone(compressed));
+                        }
+                        guard.work.finish(&result);
+                    }
+                    .in_current_span(),
+                );
+
+                compression
+            }
+        };
 
-        Ok(info)
+        compression.wait().await
     }
 }
@@ -0,0 +1,285 @@
+use parking_lot::Mutex;
+use semver::Version;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::inventory::{UpdateReport, UpdateStatus};
+use crate::rollout::parse_duration;
+
+/// Repository name applying a policy to every repository without its own.
+const ANY_REPOSITORY: &str = "*";
+
+/// Devices that must report installing a release before its failure rate is
+/// considered, unless the policy sets its own minimum.
+pub const DEFAULT_MIN_DEVICES: usize = 10;
+
+/// Failure rate halting a release, e.g. `5%@1h` or `5%@1h/50`: more than 5% of
+/// the devices that installed it report a failure within an hour, once at
+/// least 50 devices did.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FailurePolicy {
+    /// Percentage of failed devices above which the release is halted.
+    pub threshold: f64,
+    pub window: Duration,
+    /// Devices that must have installed the release before it can be halted.
+    pub min_devices: usize,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| format!("Invalid failure policy '{value}': {reason}");
+
+        let (threshold, rest) = value
+            .split_once("%@")
+            .ok_or_else(|| invalid("expected <percentage>%@<window>[/<min devices>]"))?;
+        let (window, min_devices) = match rest.split_once('/') {
+            Some((window, min_devices)) => (
+                window,
+                min_devices
+                    .trim()
+                    .parse()
+                    .map_err(|e| invalid(&format!("minimum devices: {e}")))?,
+            ),
+            None => (rest, DEFAULT_MIN_DEVICES),
+        };
+
+        let threshold: f64 = threshold
+            .trim()
+            .parse()
+            .map_err(|e| invalid(&format!("percentage: {e}")))?;
+        if !(0.0..100.0).contains(&threshold) {
+            return Err(invalid("percentage must be between 0 and 100"));
+        }
+
+        Ok(Self {
+            threshold,
+            window: parse_duration(window.trim()).map_err(|e| invalid(&e))?,
+            min_devices: min_devices.max(1),
+        })
+    }
+}
+
+/// Failure policy of a repository, e.g. `esp32-sensor=5%@1h`.
+///
+/// The `*` repository applies to every repository without its own policy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepositoryPolicy {
+    pub repository: String,
+    pub policy: FailurePolicy,
+}
+
+impl FromStr for RepositoryPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (repository, policy) = value.split_once('=').ok_or_else(|| {
+            format!(
+                "Invalid failure policy '{value}': expected <repository>=<percentage>%@<window>"
+            )
+        })?;
+
+        Ok(Self {
+            repository: repository.trim().to_string(),
+            policy: policy.parse()?,
+        })
+    }
+}
+
+/// A release halted after too many devices failed to install it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Halt {
+    pub version: Version,
+    /// Release of the default channel the device model fell back to when the
+    /// release was halted. Devices are held on the release preceding the
+    /// lowest halted release of their own channel.
+    pub previous: Option<Version>,
+    /// Devices that reported installing the release within the policy window.
+    pub devices: usize,
+    /// Devices that reported failing to install it within the window.
+    pub failed: usize,
+    pub halted_at: SystemTime,
+}
+
+impl Halt {
+    /// Percentage of devices that failed to install the release.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn failure_rate(&self) -> f64 {
+        self.failed as f64 * 100.0 / self.devices.max(1) as f64
+    }
+}
+
+/// Error returned when no release of a device's channel precedes the halted
+/// releases of its model: the device is offered nothing newer than the
+/// version it runs.
+#[derive(Debug)]
+pub struct ReleaseHalted {
+    pub version: Version,
+}
+
+impl fmt::Display for ReleaseHalted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Release {} is halted and no release precedes it",
+            self.version
+        )
+    }
+}
+
+impl std::error::Error for ReleaseHalted {}
+
+/// An install outcome reported within a policy window.
+struct Outcome {
+    at: SystemTime,
+    serial: String,
+    failed: bool,
+}
+
+/// Releases halted on their failure rate, per device model.
+///
+/// Outcomes reported by devices are kept in memory for the window of the
+/// policy of their repository, and a release is halted once the share of
+/// devices that reported failing to install it exceeds the policy threshold.
+/// Several releases of a device model may be halted, and halts are only
+/// lifted by an operator.
+#[derive(Default)]
+pub struct Halts {
+    policies: HashMap<String, FailurePolicy>,
+    /// Recent install outcomes per device ID and version.
+    outcomes: Mutex<HashMap<(String, Version), VecDeque<Outcome>>>,
+    /// Halted releases per device ID and version.
+    halted: Mutex<BTreeMap<(String, Version), Halt>>,
+}
+
+impl Halts {
+    #[must_use]
+    pub fn new(policies: Vec<RepositoryPolicy>) -> Self {
+        Self {
+            policies: policies
+                .into_iter()
+                .map(|p| (p.repository, p.policy))
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the failure policy of a device ID, if any.
+    #[must_use]
+    pub fn policy(&self, device_id: &str) -> Option<&FailurePolicy> {
+        self.policies
+            .get(device_id)
+            .or_else(|| self.policies.get(ANY_REPOSITORY))
+    }
+
+    /// Returns the halts of a device ID, lowest version first.
+    #[must_use]
+    pub fn get(&self, device_id: &str) -> Vec<Halt> {
+        self.halted
+            .lock()
+            .iter()
+            .filter(|((id, _), _)| id == device_id)
+            .map(|(_, halt)| halt.clone())
+            .collect()
+    }
+
+    /// Returns `true` if a release of a device ID is halted.
+    #[must_use]
+    pub fn is_halted(&self, device_id: &str, version: &Version) -> bool {
+        self.halted
+            .lock()
+            .contains_key(&(device_id.to_string(), version.clone()))
+    }
+
+    /// Returns all halts, sorted by device ID and version.
+    #[must_use]
+    pub fn list(&self) -> Vec<(String, Halt)> {
+        self.halted
+            .lock()
+            .iter()
+            .map(|((device_id, _), halt)| (device_id.clone(), halt.clone()))
+            .collect()
+    }
+
+    /// Restores the halts persisted before a restart.
+    pub fn load(&self, halts: Vec<(String, Halt)>) {
+        self.halted.lock().extend(
+            halts
+                .into_iter()
+                .map(|(device_id, halt)| ((device_id, halt.version.clone()), halt)),
+        );
+    }
+
+    /// Halts a release of a device ID.
+    pub fn set(&self, device_id: &str, halt: Halt) {
+        self.halted
+            .lock()
+            .insert((device_id.to_string(), halt.version.clone()), halt);
+    }
+
+    /// Lifts the halts of a device ID, forgetting the outcomes that tripped
+    /// them so that the releases aren't halted again right away.
+    pub fn remove(&self, device_id: &str) -> Vec<Halt> {
+        let mut halted = self.halted.lock();
+        let keys: Vec<_> = halted
+            .keys()
+            .filter(|(id, _)| id == device_id)
+            .cloned()
+            .collect();
+        let mut outcomes = self.outcomes.lock();
+        keys.into_iter()
+            .filter_map(|key| {
+                outcomes.remove(&key);
+                halted.remove(&key)
+            })
+            .collect()
+    }
+
+    /// Records the outcome of an install, returning the failure counts of the
+    /// release when they exceed the policy of its repository and it isn't
+    /// halted yet. Downloads don't count as installs.
+    pub fn record(&self, report: &UpdateReport, now: SystemTime) -> Option<Halt> {
+        let policy = self.policy(&report.model)?;
+        if report.status == UpdateStatus::Downloaded {
+            return None;
+        }
+        if self.is_halted(&report.model, &report.to_version) {
+            return None;
+        }
+
+        let mut outcomes = self.outcomes.lock();
+        let window = outcomes
+            .entry((report.model.clone(), report.to_version.clone()))
+            .or_default();
+        window.push_back(Outcome {
+            at: report.reported_at,
+            serial: report.serial.clone(),
+            failed: report.status == UpdateStatus::Failed,
+        });
+        let since = now
+            .checked_sub(policy.window)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        window.retain(|outcome| outcome.at >= since);
+
+        let devices: BTreeSet<&str> = window.iter().map(|o| o.serial.as_str()).collect();
+        let failed: BTreeSet<&str> = window
+            .iter()
+            .filter(|o| o.failed)
+            .map(|o| o.serial.as_str())
+            .collect();
+
+        let halt = Halt {
+            version: report.to_version.clone(),
+            previous: None,
+            devices: devices.len(),
+            failed: failed.len(),
+            halted_at: now,
+        };
+        (halt.devices >= policy.min_devices && halt.failure_rate() > policy.threshold)
+            .then_some(halt)
+    }
+}
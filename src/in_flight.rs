@@ -0,0 +1,67 @@
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Work shared by the requests attached to it while it is in flight.
+pub trait Abort {
+    /// Fails the work if it didn't finish, waking up the attached requests.
+    fn abort(&self);
+}
+
+/// A result computed once and shared with every request waiting for it, such
+/// as a generated delta or a compressed firmware.
+///
+/// Concurrent requests attach to the computation started by the first one
+/// rather than repeating it, so that expensive work runs once per result.
+pub struct InFlight<T> {
+    result: watch::Sender<Option<Result<Arc<T>, String>>>,
+}
+
+impl<T> Default for InFlight<T> {
+    fn default() -> Self {
+        let (result, _) = watch::channel(None);
+        Self { result }
+    }
+}
+
+impl<T> InFlight<T> {
+    /// Publishes the result to the attached requests.
+    pub fn finish(&self, result: &Result<Arc<T>>) {
+        let result = match result {
+            Ok(value) => Ok(Arc::clone(value)),
+            Err(e) => Err(format!("{e:#}")),
+        };
+        self.result.send_replace(Some(result));
+    }
+
+    /// Waits for the result.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the computation failed or was aborted.
+    pub async fn wait(&self) -> Result<Arc<T>> {
+        let mut rx = self.result.subscribe();
+        let result = match rx.wait_for(Option::is_some).await {
+            Ok(result) => result.clone(),
+            Err(_) => None,
+        };
+
+        match result {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(e)) => Err(anyhow!(e)),
+            None => Err(anyhow!("Computation was aborted")),
+        }
+    }
+}
+
+impl<T> Abort for InFlight<T> {
+    fn abort(&self) {
+        self.result.send_if_modified(|result| {
+            if result.is_some() {
+                return false;
+            }
+            *result = Some(Err("Computation was aborted".to_string()));
+            true
+        });
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::Result;
+use lru::LruCache;
+use parking_lot::Mutex;
+use semver::Version;
+use std::collections::{BTreeMap, VecDeque};
+use std::num::NonZeroUsize;
+use std::time::SystemTime;
+
+use crate::halt::Halt;
+use crate::inventory::{
+    DeviceFilter, DeviceRecord, DeviceStore, EnrollmentRecord, RolloutRecord, UpdateReport,
+};
+
+/// Number of update reports kept per device, older ones are dropped.
+const MAX_REPORTS_PER_DEVICE: usize = 100;
+
+/// Number of devices kept in memory by default, the least recently seen ones
+/// being forgotten first.
+pub const DEFAULT_MAX_DEVICES: NonZeroUsize = NonZeroUsize::new(100_000).unwrap();
+
+/// Device store kept in memory, lost on restart.
+///
+/// The inventory is bounded: once it holds its maximum number of devices, the
+/// device seen least recently is forgotten, with its reports, whenever a new
+/// one checks in.
+#[derive(Debug)]
+pub struct MemoryStore {
+    devices: Mutex<LruCache<String, DeviceRecord>>,
+    /// Update reports per serial, most recent first.
+    reports: Mutex<BTreeMap<String, VecDeque<UpdateReport>>>,
+    enrollments: Mutex<BTreeMap<String, EnrollmentRecord>>,
+    rollouts: Mutex<BTreeMap<String, RolloutRecord>>,
+    halts: Mutex<BTreeMap<(String, Version), Halt>>,
+}
+
+impl MemoryStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_max_devices(DEFAULT_MAX_DEVICES)
+    }
+
+    /// Creates a store keeping at most `max_devices` devices.
+    #[must_use]
+    pub fn with_max_devices(max_devices: NonZeroUsize) -> Self {
+        Self {
+            devices: Mutex::new(LruCache::new(max_devices)),
+            reports: Mutex::default(),
+            enrollments: Mutex::default(),
+            rollouts: Mutex::default(),
+            halts: Mutex::default(),
+        }
+    }
+
+    /// Adds a device, forgetting the reports of the device it evicts if any.
+    fn insert(&self, devices: &mut LruCache<String, DeviceRecord>, device: DeviceRecord) {
+        if let Some((serial, _)) = devices.push(device.serial.clone(), device) {
+            self.reports.lock().remove(&serial);
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceStore for MemoryStore {
+    fn check_in(&self, check_in: DeviceRecord) -> Result<()> {
+        let mut devices = self.devices.lock();
+        match devices.get_mut(&check_in.serial) {
+            Some(device) => device.update(check_in),
+            None => self.insert(&mut devices, check_in),
+        }
+        Ok(())
+    }
+
+    fn get(&self, serial: &str) -> Result<Option<DeviceRecord>> {
+        Ok(self.devices.lock().peek(serial).cloned())
+    }
+
+    fn list(&self, filter: &DeviceFilter) -> Result<Vec<DeviceRecord>> {
+        let mut devices: Vec<_> = self
+            .devices
+            .lock()
+            .iter()
+            .map(|(_, device)| device)
+            .filter(|device| filter.matches(device))
+            .cloned()
+            .collect();
+        devices.sort_by(|a, b| a.serial.cmp(&b.serial));
+        Ok(devices)
+    }
+
+    fn report(&self, report: UpdateReport) -> Result<()> {
+        {
+            let mut reports = self.reports.lock();
+            let history = reports.entry(report.serial.clone()).or_default();
+            history.push_front(report.clone());
+            history.truncate(MAX_REPORTS_PER_DEVICE);
+        }
+
+        let mut devices = self.devices.lock();
+        match devices.get_mut(&report.serial) {
+            Some(device) => device.report(report),
+            None => self.insert(&mut devices, DeviceRecord::reported(report)),
+        }
+        Ok(())
+    }
+
+    fn reports(&self, serial: &str) -> Result<Vec<UpdateReport>> {
+        Ok(self
+            .reports
+            .lock()
+            .get(serial)
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn enroll(&self, enrollment: EnrollmentRecord) -> Result<()> {
+        self.enrollments
+            .lock()
+            .insert(enrollment.serial.clone(), enrollment);
+        Ok(())
+    }
+
+    fn enrollments(&self) -> Result<Vec<EnrollmentRecord>> {
+        Ok(self.enrollments.lock().values().cloned().collect())
+    }
+
+    fn revoke(&self, serial: &str, revoked_at: SystemTime) -> Result<bool> {
+        let mut enrollments = self.enrollments.lock();
+        match enrollments.get_mut(serial) {
+            Some(enrollment) if enrollment.revoked_at.is_none() => {
+                enrollment.revoked_at = Some(revoked_at);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn save_rollout(&self, record: RolloutRecord) -> Result<()> {
+        let mut rollouts = self.rollouts.lock();
+        if record.rollout.is_none() && record.baseline.is_none() {
+            rollouts.remove(&record.model);
+        } else {
+            rollouts.insert(record.model.clone(), record);
+        }
+        Ok(())
+    }
+
+    fn rollouts(&self) -> Result<Vec<RolloutRecord>> {
+        Ok(self.rollouts.lock().values().cloned().collect())
+    }
+
+    fn save_halt(&self, model: &str, halt: Option<Halt>) -> Result<()> {
+        let mut halts = self.halts.lock();
+        match halt {
+            Some(halt) => {
+                halts.insert((model.to_string(), halt.version.clone()), halt);
+            }
+            None => halts.retain(|(halted, _), _| halted != model),
+        }
+        Ok(())
+    }
+
+    fn halts(&self) -> Result<Vec<(String, Halt)>> {
+        Ok(self
+            .halts
+            .lock()
+            .iter()
+            .map(|((model, _), halt)| (model.clone(), halt.clone()))
+            .collect())
+    }
+}
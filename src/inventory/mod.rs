@@ -0,0 +1,310 @@
+pub mod memory;
+pub mod sqlite;
+
+use anyhow::Result;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::halt::Halt;
+use crate::rollout::Rollout;
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+/// Last known state of a device, identified by its serial.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceRecord {
+    pub serial: String,
+    /// Device model, the repository its firmware is served from.
+    pub model: String,
+    /// Time of the last check-in.
+    pub last_seen: SystemTime,
+    /// Firmware version the device last reported running.
+    pub reported_version: Option<Version>,
+    /// Firmware version the device was last offered.
+    pub served_version: Option<Version>,
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    /// Outcome of the last update reported by the device.
+    pub last_report: Option<UpdateReport>,
+}
+
+impl DeviceRecord {
+    /// Creates the record of a device first known from an update report.
+    fn reported(report: UpdateReport) -> Self {
+        Self {
+            serial: report.serial.clone(),
+            model: report.model.clone(),
+            last_seen: report.reported_at,
+            reported_version: report.running_version().cloned(),
+            served_version: None,
+            ip: None,
+            user_agent: None,
+            last_report: Some(report),
+        }
+    }
+
+    /// Merges a check-in into the record. Versions that weren't reported or
+    /// resolved keep their previously known value.
+    fn update(&mut self, check_in: Self) {
+        let reported_version = check_in
+            .reported_version
+            .or_else(|| self.reported_version.take());
+        let served_version = check_in
+            .served_version
+            .or_else(|| self.served_version.take());
+        let last_report = check_in.last_report.or_else(|| self.last_report.take());
+
+        *self = Self {
+            reported_version,
+            served_version,
+            last_report,
+            ..check_in
+        };
+    }
+
+    /// Merges an update report into the record.
+    fn report(&mut self, report: UpdateReport) {
+        self.last_seen = self.last_seen.max(report.reported_at);
+        if let Some(version) = report.running_version() {
+            self.reported_version = Some(version.clone());
+        }
+        self.last_report = Some(report);
+    }
+}
+
+/// Stage reached by a firmware update on a device.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateStatus {
+    /// Firmware downloaded, not installed yet.
+    Downloaded,
+    /// Firmware written to the device, pending reboot.
+    Installed,
+    /// Device booted the new firmware.
+    Booted,
+    /// Update failed, the device keeps running its previous firmware.
+    Failed,
+}
+
+impl UpdateStatus {
+    /// All statuses, in the order an update goes through them.
+    pub const ALL: [Self; 4] = [
+        Self::Downloaded,
+        Self::Installed,
+        Self::Booted,
+        Self::Failed,
+    ];
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Downloaded => "downloaded",
+            Self::Installed => "installed",
+            Self::Booted => "booted",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for UpdateStatus {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == value)
+            .ok_or_else(|| format!("Invalid update status '{value}'"))
+    }
+}
+
+impl fmt::Display for UpdateStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Outcome of a firmware update, reported by the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateReport {
+    pub serial: String,
+    pub model: String,
+    /// Firmware version the device ran before the update.
+    pub from_version: Option<Version>,
+    /// Firmware version being installed.
+    pub to_version: Version,
+    pub status: UpdateStatus,
+    /// Device-specific error code of failed updates.
+    pub error_code: Option<String>,
+    pub reported_at: SystemTime,
+}
+
+impl UpdateReport {
+    /// Returns the version the device runs after this report, if known.
+    #[must_use]
+    pub fn running_version(&self) -> Option<&Version> {
+        match self.status {
+            UpdateStatus::Booted => Some(&self.to_version),
+            UpdateStatus::Failed => self.from_version.as_ref(),
+            UpdateStatus::Downloaded | UpdateStatus::Installed => None,
+        }
+    }
+}
+
+/// Credentials issued to a device when it enrolled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnrollmentRecord {
+    pub serial: String,
+    /// Device model the device enrolled as.
+    pub model: String,
+    /// Hex-encoded SHA-256 of the issued API token.
+    pub token_sha256: Option<String>,
+    /// Hex-encoded serial number of the issued certificate.
+    pub certificate_serial: Option<String>,
+    pub enrolled_at: SystemTime,
+    pub revoked_at: Option<SystemTime>,
+}
+
+/// Rollout state of a device model, kept across restarts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RolloutRecord {
+    pub model: String,
+    /// Staged rollout of the model, if any.
+    pub rollout: Option<Rollout>,
+    /// Latest version served to every device of the model outside a
+    /// rollout, never rolled out again.
+    pub baseline: Option<Version>,
+}
+
+/// Criteria selecting devices of the inventory.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceFilter {
+    pub model: Option<String>,
+    /// Firmware version reported by the device.
+    pub version: Option<Version>,
+}
+
+impl DeviceFilter {
+    #[must_use]
+    pub fn matches(&self, device: &DeviceRecord) -> bool {
+        self.model
+            .as_ref()
+            .is_none_or(|model| *model == device.model)
+            && self
+                .version
+                .as_ref()
+                .is_none_or(|version| device.reported_version.as_ref() == Some(version))
+    }
+}
+
+/// Storage of device check-ins, backing the fleet inventory.
+///
+/// Stores are called from blocking tasks and may perform blocking I/O.
+pub trait DeviceStore: Send + Sync + fmt::Debug {
+    /// Records a check-in, creating the device or updating its record.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to persist the record.
+    fn check_in(&self, check_in: DeviceRecord) -> Result<()>;
+
+    /// Returns the record of a device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to read the record.
+    fn get(&self, serial: &str) -> Result<Option<DeviceRecord>>;
+
+    /// Lists the devices matching a filter, ordered by serial.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to read the records.
+    fn list(&self, filter: &DeviceFilter) -> Result<Vec<DeviceRecord>>;
+
+    /// Records an update report and updates the record of the device,
+    /// creating it if it never checked in.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to persist the report.
+    fn report(&self, report: UpdateReport) -> Result<()>;
+
+    /// Returns the update reports of a device, most recent first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to read the reports.
+    fn reports(&self, serial: &str) -> Result<Vec<UpdateReport>>;
+
+    /// Records the credentials issued to an enrolling device, replacing
+    /// those of a previous enrollment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to persist the enrollment.
+    fn enroll(&self, enrollment: EnrollmentRecord) -> Result<()>;
+
+    /// Lists the enrollments of all devices, ordered by serial.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to read the enrollments.
+    fn enrollments(&self) -> Result<Vec<EnrollmentRecord>>;
+
+    /// Revokes the enrollment of a device, returning `false` if the device
+    /// never enrolled or was already revoked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to persist the revocation.
+    fn revoke(&self, serial: &str, revoked_at: SystemTime) -> Result<bool>;
+
+    /// Records the rollout state of a device model, replacing the previous
+    /// one. Records without rollout nor baseline are deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to persist the rollout.
+    fn save_rollout(&self, record: RolloutRecord) -> Result<()>;
+
+    /// Lists the rollout state of all device models, ordered by model.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to read the rollouts.
+    fn rollouts(&self) -> Result<Vec<RolloutRecord>>;
+
+    /// Records a halted release of a device model, or lifts all of its halts
+    /// when `halt` is `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to persist the halt.
+    fn save_halt(&self, model: &str, halt: Option<Halt>) -> Result<()>;
+
+    /// Lists the halted releases of all device models, ordered by model.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to read the halts.
+    fn halts(&self) -> Result<Vec<(String, Halt)>>;
+}
+
+/// Runs a store operation on the blocking thread pool.
+///
+/// # Errors
+///
+/// Returns an error if the operation fails or panics.
+pub async fn run_blocking<T, F>(store: Arc<dyn DeviceStore>, operation: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn DeviceStore) -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || operation(store.as_ref())).await?
+}
@@ -0,0 +1,584 @@
+use anyhow::{Context, Result};
+use lru::LruCache;
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use semver::Version;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::halt::Halt;
+use crate::inventory::{
+    DeviceFilter, DeviceRecord, DeviceStore, EnrollmentRecord, RolloutRecord, UpdateReport,
+};
+use crate::rollout::{Progression, Rollout};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS devices (
+    serial TEXT PRIMARY KEY NOT NULL,
+    model TEXT NOT NULL,
+    last_seen_ms INTEGER NOT NULL,
+    reported_version TEXT,
+    served_version TEXT,
+    ip TEXT,
+    user_agent TEXT
+);
+CREATE INDEX IF NOT EXISTS devices_model ON devices (model, reported_version);
+CREATE TABLE IF NOT EXISTS reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    serial TEXT NOT NULL,
+    model TEXT NOT NULL,
+    from_version TEXT,
+    to_version TEXT NOT NULL,
+    status TEXT NOT NULL,
+    error_code TEXT,
+    reported_at_ms INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS reports_serial ON reports (serial, id);
+CREATE TABLE IF NOT EXISTS enrollments (
+    serial TEXT PRIMARY KEY NOT NULL,
+    model TEXT NOT NULL,
+    token_sha256 TEXT,
+    certificate_serial TEXT,
+    enrolled_at_ms INTEGER NOT NULL,
+    revoked_at_ms INTEGER
+);
+CREATE TABLE IF NOT EXISTS rollouts (
+    model TEXT PRIMARY KEY NOT NULL,
+    baseline TEXT,
+    version TEXT,
+    percentage INTEGER,
+    halted INTEGER,
+    schedule TEXT,
+    step INTEGER,
+    step_started_at_ms INTEGER
+);
+CREATE TABLE IF NOT EXISTS halts (
+    model TEXT NOT NULL,
+    version TEXT NOT NULL,
+    previous TEXT,
+    devices INTEGER NOT NULL,
+    failed INTEGER NOT NULL,
+    halted_at_ms INTEGER NOT NULL,
+    PRIMARY KEY (model, version)
+);
+";
+
+/// Time other writers may hold the database locked before a write fails.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolution of the last seen time of devices: check-ins that change nothing
+/// but the last seen time aren't written more often, sparing a disk write per
+/// poll.
+pub const LAST_SEEN_RESOLUTION: Duration = Duration::from_mins(1);
+
+/// Number of devices whose last written check-in is remembered to coalesce
+/// their check-ins.
+const COALESCED_DEVICES: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+
+/// Device columns, followed by the columns of the last update report.
+const DEVICE_QUERY: &str = "
+SELECT d.serial, d.model, d.last_seen_ms, d.reported_version, d.served_version, d.ip, d.user_agent,
+       r.serial, r.model, r.from_version, r.to_version, r.status, r.error_code, r.reported_at_ms
+FROM devices d
+LEFT JOIN reports r ON r.id = (SELECT MAX(id) FROM reports WHERE serial = d.serial)
+";
+
+/// Device store persisted in a `SQLite` database.
+///
+/// The database is written ahead (WAL), and the last seen time of devices
+/// polling repeatedly is only updated every [`LAST_SEEN_RESOLUTION`].
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+    /// Last check-in written per serial.
+    written: Mutex<LruCache<String, DeviceRecord>>,
+}
+
+impl SqliteStore {
+    /// Opens the database at the given path, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be opened or migrated.
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open device database {}", path.display()))?;
+        Self::with_connection(connection)
+    }
+
+    /// Opens a database held in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be created.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self> {
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))
+            .context("Failed to enable device database write-ahead log")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection
+            .execute_batch(SCHEMA)
+            .context("Failed to create device database schema")?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+            written: Mutex::new(LruCache::new(COALESCED_DEVICES)),
+        })
+    }
+}
+
+/// Returns `true` if a check-in only moves the last seen time of the check-in
+/// written before it, by less than [`LAST_SEEN_RESOLUTION`].
+fn is_coalesced(written: &DeviceRecord, check_in: &DeviceRecord) -> bool {
+    check_in
+        .last_seen
+        .duration_since(written.last_seen)
+        .is_ok_and(|elapsed| elapsed < LAST_SEEN_RESOLUTION)
+        && *written
+            == DeviceRecord {
+                last_seen: written.last_seen,
+                ..check_in.clone()
+            }
+}
+
+impl DeviceStore for SqliteStore {
+    fn check_in(&self, check_in: DeviceRecord) -> Result<()> {
+        if self
+            .written
+            .lock()
+            .get(&check_in.serial)
+            .is_some_and(|written| is_coalesced(written, &check_in))
+        {
+            return Ok(());
+        }
+
+        let written = check_in.clone();
+        self.connection.lock().execute(
+            "INSERT INTO devices (serial, model, last_seen_ms, reported_version, served_version, ip, user_agent)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (serial) DO UPDATE SET
+                 model = excluded.model,
+                 last_seen_ms = excluded.last_seen_ms,
+                 reported_version = COALESCE(excluded.reported_version, devices.reported_version),
+                 served_version = COALESCE(excluded.served_version, devices.served_version),
+                 ip = excluded.ip,
+                 user_agent = excluded.user_agent",
+            params![
+                check_in.serial,
+                check_in.model,
+                to_millis(check_in.last_seen),
+                check_in.reported_version.map(|v| v.to_string()),
+                check_in.served_version.map(|v| v.to_string()),
+                check_in.ip.map(|ip| ip.to_string()),
+                check_in.user_agent,
+            ],
+        )?;
+        self.written.lock().put(written.serial.clone(), written);
+        Ok(())
+    }
+
+    fn get(&self, serial: &str) -> Result<Option<DeviceRecord>> {
+        let row = self
+            .connection
+            .lock()
+            .query_row(
+                &format!("{DEVICE_QUERY} WHERE d.serial = ?1"),
+                params![serial],
+                StoredDevice::from_row,
+            )
+            .optional()?;
+
+        row.map(StoredDevice::into_record).transpose()
+    }
+
+    fn list(&self, filter: &DeviceFilter) -> Result<Vec<DeviceRecord>> {
+        let connection = self.connection.lock();
+        let mut statement = connection.prepare(&format!(
+            "{DEVICE_QUERY}
+             WHERE (?1 IS NULL OR d.model = ?1) AND (?2 IS NULL OR d.reported_version = ?2)
+             ORDER BY d.serial"
+        ))?;
+
+        let rows = statement
+            .query_map(
+                params![
+                    filter.model,
+                    filter.version.as_ref().map(Version::to_string)
+                ],
+                StoredDevice::from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter().map(StoredDevice::into_record).collect()
+    }
+
+    fn report(&self, report: UpdateReport) -> Result<()> {
+        // The next check-in of the device is written whatever it reports
+        self.written.lock().pop(&report.serial);
+
+        let mut connection = self.connection.lock();
+        let transaction = connection.transaction()?;
+        let reported_at_ms = to_millis(report.reported_at);
+
+        transaction.execute(
+            "INSERT INTO reports (serial, model, from_version, to_version, status, error_code, reported_at_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                report.serial,
+                report.model,
+                report.from_version.as_ref().map(Version::to_string),
+                report.to_version.to_string(),
+                report.status.as_str(),
+                report.error_code,
+                reported_at_ms,
+            ],
+        )?;
+        transaction.execute(
+            "INSERT INTO devices (serial, model, last_seen_ms, reported_version)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (serial) DO UPDATE SET
+                 last_seen_ms = MAX(devices.last_seen_ms, excluded.last_seen_ms),
+                 reported_version = COALESCE(excluded.reported_version, devices.reported_version)",
+            params![
+                report.serial,
+                report.model,
+                reported_at_ms,
+                report.running_version().map(Version::to_string),
+            ],
+        )?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn reports(&self, serial: &str) -> Result<Vec<UpdateReport>> {
+        let connection = self.connection.lock();
+        let mut statement = connection.prepare(
+            "SELECT serial, model, from_version, to_version, status, error_code, reported_at_ms
+             FROM reports WHERE serial = ?1 ORDER BY id DESC",
+        )?;
+
+        let rows = statement
+            .query_map(params![serial], |row| StoredReport::from_row(row, 0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter()
+            .flatten()
+            .map(StoredReport::into_report)
+            .collect()
+    }
+
+    fn enroll(&self, enrollment: EnrollmentRecord) -> Result<()> {
+        self.connection.lock().execute(
+            "INSERT OR REPLACE INTO enrollments
+                 (serial, model, token_sha256, certificate_serial, enrolled_at_ms, revoked_at_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                enrollment.serial,
+                enrollment.model,
+                enrollment.token_sha256,
+                enrollment.certificate_serial,
+                to_millis(enrollment.enrolled_at),
+                enrollment.revoked_at.map(to_millis),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn enrollments(&self) -> Result<Vec<EnrollmentRecord>> {
+        let connection = self.connection.lock();
+        let mut statement = connection.prepare(
+            "SELECT serial, model, token_sha256, certificate_serial, enrolled_at_ms, revoked_at_ms
+             FROM enrollments ORDER BY serial",
+        )?;
+
+        let enrollments = statement
+            .query_map([], |row| {
+                Ok(EnrollmentRecord {
+                    serial: row.get(0)?,
+                    model: row.get(1)?,
+                    token_sha256: row.get(2)?,
+                    certificate_serial: row.get(3)?,
+                    enrolled_at: from_millis(row.get(4)?),
+                    revoked_at: row.get::<_, Option<i64>>(5)?.map(from_millis),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(enrollments)
+    }
+
+    fn revoke(&self, serial: &str, revoked_at: SystemTime) -> Result<bool> {
+        let revoked = self.connection.lock().execute(
+            "UPDATE enrollments SET revoked_at_ms = ?2
+             WHERE serial = ?1 AND revoked_at_ms IS NULL",
+            params![serial, to_millis(revoked_at)],
+        )?;
+        Ok(revoked > 0)
+    }
+
+    fn save_rollout(&self, record: RolloutRecord) -> Result<()> {
+        let connection = self.connection.lock();
+        if record.rollout.is_none() && record.baseline.is_none() {
+            connection.execute(
+                "DELETE FROM rollouts WHERE model = ?1",
+                params![record.model],
+            )?;
+            return Ok(());
+        }
+
+        let rollout = record.rollout.as_ref();
+        let progression = rollout.and_then(|rollout| rollout.progression.as_ref());
+        connection.execute(
+            "INSERT OR REPLACE INTO rollouts
+                 (model, baseline, version, percentage, halted, schedule, step, step_started_at_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                record.model,
+                record.baseline.as_ref().map(Version::to_string),
+                rollout.map(|rollout| rollout.version.to_string()),
+                rollout.map(|rollout| rollout.percentage),
+                rollout.map(|rollout| rollout.halted),
+                progression.map(|progression| progression.schedule.to_string()),
+                progression.and_then(|progression| i64::try_from(progression.step).ok()),
+                progression.map(|progression| to_millis(progression.step_started_at)),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn rollouts(&self) -> Result<Vec<RolloutRecord>> {
+        let connection = self.connection.lock();
+        let mut statement = connection.prepare(
+            "SELECT model, baseline, version, percentage, halted, schedule, step, step_started_at_ms
+             FROM rollouts ORDER BY model",
+        )?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok(StoredRollout {
+                    model: row.get(0)?,
+                    baseline: row.get(1)?,
+                    version: row.get(2)?,
+                    percentage: row.get(3)?,
+                    halted: row.get(4)?,
+                    schedule: row.get(5)?,
+                    step: row.get(6)?,
+                    step_started_at_ms: row.get(7)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter().map(StoredRollout::into_record).collect()
+    }
+
+    fn save_halt(&self, model: &str, halt: Option<Halt>) -> Result<()> {
+        let connection = self.connection.lock();
+        let Some(halt) = halt else {
+            connection.execute("DELETE FROM halts WHERE model = ?1", params![model])?;
+            return Ok(());
+        };
+
+        connection.execute(
+            "INSERT OR REPLACE INTO halts (model, version, previous, devices, failed, halted_at_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                model,
+                halt.version.to_string(),
+                halt.previous.as_ref().map(Version::to_string),
+                i64::try_from(halt.devices).unwrap_or(i64::MAX),
+                i64::try_from(halt.failed).unwrap_or(i64::MAX),
+                to_millis(halt.halted_at),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn halts(&self) -> Result<Vec<(String, Halt)>> {
+        let connection = self.connection.lock();
+        let mut statement = connection.prepare(
+            "SELECT model, version, previous, devices, failed, halted_at_ms
+             FROM halts ORDER BY model",
+        )?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter()
+            .map(
+                |(model, version, previous, devices, failed, halted_at_ms)| {
+                    let halt = Halt {
+                        version: Version::parse(&version)
+                            .with_context(|| format!("Invalid version '{version}' stored"))?,
+                        previous: parse_version(previous)?,
+                        devices: usize::try_from(devices).unwrap_or_default(),
+                        failed: usize::try_from(failed).unwrap_or_default(),
+                        halted_at: from_millis(halted_at_ms),
+                    };
+                    Ok((model, halt))
+                },
+            )
+            .collect()
+    }
+}
+
+/// Raw columns of a device row.
+struct StoredDevice {
+    serial: String,
+    model: String,
+    last_seen_ms: i64,
+    reported_version: Option<String>,
+    served_version: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    last_report: Option<StoredReport>,
+}
+
+impl StoredDevice {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            serial: row.get(0)?,
+            model: row.get(1)?,
+            last_seen_ms: row.get(2)?,
+            reported_version: row.get(3)?,
+            served_version: row.get(4)?,
+            ip: row.get(5)?,
+            user_agent: row.get(6)?,
+            last_report: StoredReport::from_row(row, 7)?,
+        })
+    }
+
+    fn into_record(self) -> Result<DeviceRecord> {
+        Ok(DeviceRecord {
+            reported_version: parse_version(self.reported_version)?,
+            served_version: parse_version(self.served_version)?,
+            ip: self
+                .ip
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .with_context(|| format!("Invalid IP stored for device '{}'", self.serial))?,
+            last_seen: from_millis(self.last_seen_ms),
+            last_report: self
+                .last_report
+                .map(StoredReport::into_report)
+                .transpose()?,
+            serial: self.serial,
+            model: self.model,
+            user_agent: self.user_agent,
+        })
+    }
+}
+
+/// Raw columns of an update report row.
+struct StoredReport {
+    serial: String,
+    model: String,
+    from_version: Option<String>,
+    to_version: String,
+    status: String,
+    error_code: Option<String>,
+    reported_at_ms: i64,
+}
+
+impl StoredReport {
+    /// Reads a report from the columns starting at `offset`, `None` when
+    /// joined to a device without report.
+    fn from_row(row: &Row<'_>, offset: usize) -> rusqlite::Result<Option<Self>> {
+        let Some(serial) = row.get::<_, Option<String>>(offset)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            serial,
+            model: row.get(offset + 1)?,
+            from_version: row.get(offset + 2)?,
+            to_version: row.get(offset + 3)?,
+            status: row.get(offset + 4)?,
+            error_code: row.get(offset + 5)?,
+            reported_at_ms: row.get(offset + 6)?,
+        }))
+    }
+
+    fn into_report(self) -> Result<UpdateReport> {
+        Ok(UpdateReport {
+            from_version: parse_version(self.from_version)?,
+            to_version: Version::parse(&self.to_version)
+                .with_context(|| format!("Invalid version '{}' stored", self.to_version))?,
+            status: self.status.parse().map_err(anyhow::Error::msg)?,
+            error_code: self.error_code,
+            reported_at: from_millis(self.reported_at_ms),
+            serial: self.serial,
+            model: self.model,
+        })
+    }
+}
+
+/// Raw columns of a rollout row.
+struct StoredRollout {
+    model: String,
+    baseline: Option<String>,
+    version: Option<String>,
+    percentage: Option<u8>,
+    halted: Option<bool>,
+    schedule: Option<String>,
+    step: Option<i64>,
+    step_started_at_ms: Option<i64>,
+}
+
+impl StoredRollout {
+    fn into_record(self) -> Result<RolloutRecord> {
+        let progression = match (self.schedule, self.step, self.step_started_at_ms) {
+            (Some(schedule), Some(step), Some(step_started_at_ms)) => Some(Progression {
+                schedule: schedule.parse().map_err(anyhow::Error::msg)?,
+                step: usize::try_from(step).unwrap_or_default(),
+                step_started_at: from_millis(step_started_at_ms),
+            }),
+            _ => None,
+        };
+        let rollout = parse_version(self.version)?.map(|version| Rollout {
+            version,
+            percentage: self.percentage.unwrap_or_default(),
+            halted: self.halted.unwrap_or_default(),
+            progression,
+        });
+
+        Ok(RolloutRecord {
+            baseline: parse_version(self.baseline)?,
+            model: self.model,
+            rollout,
+        })
+    }
+}
+
+fn parse_version(version: Option<String>) -> Result<Option<Version>> {
+    version
+        .map(|v| Version::parse(&v).with_context(|| format!("Invalid version '{v}' stored")))
+        .transpose()
+}
+
+fn to_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|d| i64::try_from(d.as_millis()).ok())
+        .unwrap_or_default()
+}
+
+fn from_millis(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(u64::try_from(millis).unwrap_or_default())
+}
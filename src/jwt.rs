@@ -0,0 +1,185 @@
+use anyhow::{anyhow, Context, Result};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::channel::Channel;
+
+/// Key verifying the signature of device tokens.
+#[derive(Clone)]
+pub struct VerificationKey {
+    /// Key ID matched against the `kid` header of tokens.
+    pub kid: Option<String>,
+    pub key: DecodingKey,
+}
+
+impl std::fmt::Debug for VerificationKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VerificationKey")
+            .field("kid", &self.kid)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Loads the verification keys of a JSON Web Key Set file.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or contains an invalid key.
+pub fn load_jwks(path: &Path) -> Result<Vec<VerificationKey>> {
+    let content = std::fs::read(path)
+        .with_context(|| format!("Failed to read JWKS file {}", path.display()))?;
+    let jwks: JwkSet = serde_json::from_slice(&content)
+        .with_context(|| format!("Invalid JWKS file {}", path.display()))?;
+
+    jwks.keys
+        .iter()
+        .map(|jwk| {
+            Ok(VerificationKey {
+                kid: jwk.common.key_id.clone(),
+                key: DecodingKey::from_jwk(jwk).with_context(|| {
+                    format!("Invalid key {:?} in {}", jwk.common.key_id, path.display())
+                })?,
+            })
+        })
+        .collect()
+}
+
+/// Loads an RSA, EC or Ed25519 public key from a PEM file.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or holds no supported key.
+pub fn load_public_key(path: &Path) -> Result<VerificationKey> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("Failed to read public key {}", path.display()))?;
+
+    DecodingKey::from_rsa_pem(&pem)
+        .or_else(|_| DecodingKey::from_ec_pem(&pem))
+        .or_else(|_| DecodingKey::from_ed_pem(&pem))
+        .map(|key| VerificationKey { kid: None, key })
+        .map_err(|_| anyhow!("No RSA, EC or Ed25519 public key in {}", path.display()))
+}
+
+/// Values of a claim holding one string or an array of strings.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for Vec<String> {
+    fn from(values: OneOrMany) -> Self {
+        match values {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: Option<String>,
+    model: OneOrMany,
+    channel: Option<Channel>,
+}
+
+/// Device claims of a verified token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceClaims {
+    /// Serial of the device (`sub`).
+    pub serial: Option<String>,
+    /// Device IDs the token may request, as globs (`model`).
+    pub models: Vec<String>,
+    /// Most permissive release channel the token may request (`channel`),
+    /// unrestricted when `None`.
+    pub channel: Option<Channel>,
+}
+
+/// Verifies device tokens signed by a provisioning service.
+///
+/// Tokens must carry an expiry (`exp`), the device IDs they may request
+/// (`model`), and the issuer and audience when configured. The signing algorithm is taken from the token header and must
+/// match the family of the key, which is selected by the `kid` header, or
+/// tried in turn when the token has none.
+#[derive(Debug)]
+pub struct JwtVerifier {
+    keys: Vec<VerificationKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl JwtVerifier {
+    #[must_use]
+    pub fn new(keys: Vec<VerificationKey>) -> Self {
+        Self {
+            keys,
+            issuer: None,
+            audience: None,
+        }
+    }
+
+    /// Requires tokens to be issued by `issuer` (`iss`).
+    #[must_use]
+    pub fn with_issuer(mut self, issuer: Option<String>) -> Self {
+        self.issuer = issuer;
+        self
+    }
+
+    /// Requires tokens to be intended for `audience` (`aud`).
+    #[must_use]
+    pub fn with_audience(mut self, audience: Option<String>) -> Self {
+        self.audience = audience;
+        self
+    }
+
+    /// Verifies a token and returns its device claims.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the token is malformed, expired, lacks the `model`
+    /// claim, not signed by a configured key, or from another issuer or
+    /// audience.
+    pub fn verify(&self, token: &str) -> Result<DeviceClaims> {
+        let header = decode_header(token)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_required_spec_claims(&["exp"]);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let candidates = self
+            .keys
+            .iter()
+            .filter(|key| header.kid.is_none() || key.kid == header.kid);
+
+        let mut error = anyhow!("No key matches the token");
+        for key in candidates {
+            match decode::<Claims>(token, &key.key, &validation) {
+                Ok(data) => {
+                    return Ok(DeviceClaims {
+                        serial: data.claims.sub.filter(|sub| !sub.trim().is_empty()),
+                        models: data.claims.model.into(),
+                        channel: data.claims.channel,
+                    });
+                }
+                Err(e) => error = e.into(),
+            }
+        }
+        Err(error)
+    }
+}
+
+/// Returns `true` if the token has the compact JWS form of a JWT rather than
+/// being an opaque API token.
+#[must_use]
+pub fn is_jwt(token: &str) -> bool {
+    token.split('.').count() == 3
+}
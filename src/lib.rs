@@ -1,11 +1,32 @@
+pub mod alert;
 pub mod api;
+pub mod channel;
+pub mod compression;
+pub mod credential;
+pub mod download;
+pub mod enrollment;
 pub mod firmware_manager;
+pub mod halt;
+pub mod in_flight;
+pub mod inventory;
+pub mod jwt;
 pub mod metrics;
 pub mod notifier;
+pub mod pin;
+pub mod rate_limit;
 pub mod registry;
+pub mod rollback;
+pub mod rollout;
+pub mod tls;
+pub mod upgrade_path;
+pub mod yank;
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{ArgGroup, Parser};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpListener;
@@ -13,10 +34,24 @@ use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::{filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::api::router::api_router;
+use crate::alert::AlertWebhook;
+use crate::api::identity::TrustedProxy;
+use crate::api::router::{api_router_with_config, ApiConfig};
+use crate::api::throttle::Throttle;
+use crate::channel::Channel;
+use crate::credential::{Credential, Credentials};
+use crate::enrollment::{CertificateAuthority, Enrollment};
 use crate::firmware_manager::FirmwareManager;
+use crate::halt::RepositoryPolicy;
+use crate::inventory::{DeviceStore, MemoryStore, SqliteStore};
+use crate::jwt::JwtVerifier;
 use crate::metrics::router::metrics_router;
 use crate::notifier::{Notifier, TlsConfig};
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::rollout::RepositorySchedule;
+use crate::tls::ServerTls;
+use crate::upgrade_path::SteppingStone;
+use crate::yank::Yank;
 
 const DEFAULT_CACHE_SIZE: usize = 100;
 /// Initial backoff delay for MQTT reconnection attempts (in milliseconds).
@@ -24,9 +59,22 @@ const MQTT_INITIAL_BACKOFF_MS: u64 = 100;
 /// Maximum backoff delay for MQTT reconnection attempts (in milliseconds).
 /// Caps the exponential growth to prevent excessively long waits.
 const MQTT_MAX_BACKOFF_MS: u64 = 30_000;
+/// How often scheduled rollouts are checked for progression (in seconds).
+const ROLLOUT_PROGRESS_INTERVAL_SECS: u64 = 60;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(group(
+    ArgGroup::new("device_auth")
+        .multiple(true)
+        .args([
+            "device_credentials_file",
+            "jwt_jwks_path",
+            "jwt_public_key_path",
+            "tls_client_ca_path",
+            "enrollment",
+        ])
+))]
 pub struct Cli {
     #[clap(long, env)]
     pub registry_url: String,
@@ -61,10 +109,110 @@ pub struct Cli {
     pub listen_addr: String,
     #[clap(long, env, default_value = "0.0.0.0:9090")]
     pub metrics_listen_addr: String,
+    /// Path to the PEM certificate chain of the main server (enables TLS if
+    /// provided)
+    #[clap(long, env, requires = "tls_key_path")]
+    pub tls_cert_path: Option<PathBuf>,
+    /// Path to the PEM private key of the main server certificate
+    #[clap(long, env, requires = "tls_cert_path")]
+    pub tls_key_path: Option<PathBuf>,
+    /// Path to the PEM certificates of the CA issuing device certificates
+    /// (requires client certificates on the device endpoints if provided)
+    #[clap(long, env, requires = "tls_cert_path")]
+    pub tls_client_ca_path: Option<PathBuf>,
+    /// Device IDs, as globs, that device certificates naming none in their
+    /// organizational units may access, e.g. `esp32-*`
+    #[clap(long, env, value_delimiter = ',', requires = "tls_client_ca_path")]
+    pub tls_client_devices: Vec<String>,
     #[clap(long, env, default_value = "info")]
     log_level: LevelFilter,
     #[clap(long, env, default_value_t = DEFAULT_CACHE_SIZE)]
     pub cache_size: usize,
+    /// Release channel served to devices that don't request one
+    #[clap(long, env, value_enum, default_value_t = Channel::Stable)]
+    pub default_channel: Channel,
+    /// Bearer token protecting the admin API (disabled if not provided)
+    #[clap(long, env)]
+    pub admin_token: Option<String>,
+    /// Rollout schedule of a repository, e.g. `esp32-sensor=1,10,50,100@6h`
+    /// (`*` for every repository, separated by `;` in the environment,
+    /// requires the device database to keep rollouts across restarts)
+    #[clap(long, env, value_delimiter = ';', requires = "device_database")]
+    pub rollout_schedule: Vec<RepositorySchedule>,
+    /// Release withdrawn from a repository, by version or manifest digest,
+    /// e.g. `esp32-sensor=1.2.0` or `esp32-sensor=sha256:...`
+    #[clap(long, env, value_delimiter = ',')]
+    pub yank: Vec<Yank>,
+    /// Release devices of a repository must install before any newer one,
+    /// e.g. `esp32-sensor=2.0.0`
+    #[clap(long, env, value_delimiter = ',')]
+    pub stepping_stone: Vec<SteppingStone>,
+    /// Path to a JSON file of pin rules holding devices on a release
+    #[clap(long, env)]
+    pub pin_file: Option<PathBuf>,
+    /// Path to a `SQLite` database storing device check-ins, rollouts and
+    /// halted releases (kept in memory if not provided)
+    #[clap(long, env)]
+    pub device_database: Option<PathBuf>,
+    /// Failure rate halting the releases of a repository, e.g.
+    /// `esp32-sensor=5%@1h` (`*` for every repository), counting outcomes
+    /// reported by authenticated devices
+    #[clap(long, env, value_delimiter = ',', requires = "device_auth")]
+    pub failure_policy: Vec<RepositoryPolicy>,
+    /// URL receiving alerts as JSON `POST` requests, such as halted releases
+    #[clap(long, env)]
+    pub alert_webhook_url: Option<String>,
+    /// Path to a JSON file of device credentials, required on the firmware
+    /// endpoints when provided
+    #[clap(long, env)]
+    pub device_credentials_file: Option<PathBuf>,
+    /// Path to a JSON Web Key Set verifying JWT device tokens
+    #[clap(long, env)]
+    pub jwt_jwks_path: Option<PathBuf>,
+    /// Path to a PEM public key verifying JWT device tokens (RSA, EC or
+    /// Ed25519)
+    #[clap(long, env, value_delimiter = ',')]
+    pub jwt_public_key_path: Vec<PathBuf>,
+    /// Issuer (`iss`) required in JWT device tokens
+    #[clap(long, env)]
+    pub jwt_issuer: Option<String>,
+    /// Audience (`aud`) required in JWT device tokens
+    #[clap(long, env)]
+    pub jwt_audience: Option<String>,
+    /// Enable device enrollment on `/enroll` with one-time codes or factory
+    /// certificates
+    #[clap(long, env)]
+    pub enrollment: bool,
+    /// Path to the PEM certificate of the CA signing the certificate
+    /// requests of enrolling devices
+    #[clap(
+        long,
+        env,
+        requires = "enrollment",
+        requires = "enrollment_ca_key_path"
+    )]
+    pub enrollment_ca_cert_path: Option<PathBuf>,
+    /// Path to the PEM private key of the enrollment CA
+    #[clap(long, env, requires = "enrollment_ca_cert_path")]
+    pub enrollment_ca_key_path: Option<PathBuf>,
+    /// Lifetime of the certificates issued to enrolling devices
+    #[clap(long, env, value_parser = rollout::parse_duration, default_value = "365d")]
+    pub enrollment_certificate_validity: Duration,
+    /// Requests a device may send to the device endpoints, e.g. `60/1m`
+    /// (disabled if not provided)
+    #[clap(long, env)]
+    pub device_rate_limit: Option<RateLimit>,
+    /// Requests a client IP address may send to the device endpoints, e.g.
+    /// `600/1m` (disabled if not provided)
+    #[clap(long, env)]
+    pub ip_rate_limit: Option<RateLimit>,
+    /// Firmware downloads served at once (unlimited if not provided)
+    #[clap(long, env)]
+    pub max_concurrent_downloads: Option<NonZeroUsize>,
+    /// Proxies trusted to set the `X-Forwarded-For` header, by address or
+    /// network, e.g. `10.0.0.0/8` (the header is ignored if not provided)
+    #[clap(long, env, value_delimiter = ',')]
+    pub trusted_proxies: Vec<TrustedProxy>,
 }
 
 #[allow(clippy::unnecessary_wraps)]
@@ -87,6 +235,14 @@ fn normalize_repository_prefix(val: &str) -> Result<String, String> {
 /// # Errors
 ///
 /// Returns an error if:
+/// - The pin file can't be read or contains invalid rules.
+/// - The device credentials file can't be read or contains invalid credentials.
+/// - The JWKS file or a public key verifying device tokens is invalid.
+/// - The enrollment CA certificate or key can't be read.
+/// - The device database can't be opened.
+/// - The enrollments can't be loaded from the device database.
+/// - The alert webhook client fails to initialize.
+/// - The TLS certificates of the main server can't be read.
 /// - The firmware manager fails to initialize.
 /// - Reading MQTT TLS certificates fails.
 /// - The MQTT notifier fails to initialize.
@@ -117,24 +273,199 @@ pub async fn run(cli: Cli) -> Result<()> {
         }
     });
 
+    let pins = match &cli.pin_file {
+        Some(path) => pin::load_rules(path)?,
+        None => BTreeMap::new(),
+    };
+
+    let device_store: Arc<dyn DeviceStore> = match &cli.device_database {
+        Some(path) => {
+            info!(path = %path.display(), "Storing device check-ins in SQLite");
+            Arc::new(SqliteStore::open(path)?)
+        }
+        None => Arc::new(MemoryStore::new()),
+    };
+
     // Firmware manager initialization
-    let firmware_manager = Arc::new(FirmwareManager::with_cache_size(
-        cli.registry_url,
-        cli.registry_username,
-        cli.registry_password,
-        cli.registry_insecure,
-        &cli.repository_prefix,
-        cli.cosign_pub_key_path,
-        cli.cache_size,
-    )?);
+    let firmware_manager = Arc::new(
+        FirmwareManager::with_cache_size(
+            cli.registry_url,
+            cli.registry_username,
+            cli.registry_password,
+            cli.registry_insecure,
+            &cli.repository_prefix,
+            cli.cosign_pub_key_path,
+            cli.cache_size,
+        )?
+        .with_default_channel(cli.default_channel)
+        .with_rollout_schedules(cli.rollout_schedule.clone())
+        .with_yanks(cli.yank.clone())
+        .with_stepping_stones(cli.stepping_stone.clone())
+        .with_pins(pins)
+        .with_failure_policies(cli.failure_policy.clone())
+        .with_device_store(Arc::clone(&device_store))?,
+    );
 
     info!(
         cache_size = cli.cache_size,
+        default_channel = %cli.default_channel,
         "Firmware manager created. Server will fetch firmware on demand per device."
     );
 
+    for schedule in &cli.rollout_schedule {
+        info!(
+            repository = %schedule.repository,
+            steps = ?schedule.schedule.steps,
+            interval = ?schedule.schedule.interval,
+            "Rollout schedule configured"
+        );
+    }
+
+    for yank in &cli.yank {
+        info!(device_id = %yank.device, release = %yank.release, "Release yanked");
+    }
+
+    for (id, rule) in firmware_manager.pins() {
+        info!(pin = %id, device = %rule.device, target = %rule.target, "Pin rule loaded");
+    }
+
+    for policy in &cli.failure_policy {
+        info!(
+            repository = %policy.repository,
+            threshold = policy.policy.threshold,
+            window = ?policy.policy.window,
+            min_devices = policy.policy.min_devices,
+            "Failure policy configured"
+        );
+    }
+
+    for stone in &cli.stepping_stone {
+        info!(device_id = %stone.device, version = %stone.version, "Stepping stone configured");
+    }
+
+    // Scheduled rollouts progress even when no device is polling
+    tokio::spawn({
+        let firmware_manager = Arc::clone(&firmware_manager);
+        let cancel_token = cancel_token.clone();
+        async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(ROLLOUT_PROGRESS_INTERVAL_SECS));
+            loop {
+                tokio::select! {
+                    () = cancel_token.cancelled() => break,
+                    _ = interval.tick() => firmware_manager.progress_rollouts(),
+                }
+            }
+        }
+    });
+
     let fm = Arc::clone(&firmware_manager);
 
+    let alert_webhook = cli.alert_webhook_url.map(AlertWebhook::new).transpose()?;
+
+    let mut device_credentials = match &cli.device_credentials_file {
+        Some(path) => {
+            let credentials = credential::load_credentials(path)?;
+            info!(count = credentials.len(), "Device authentication enabled");
+            Some(Arc::new(Credentials::new(credentials)))
+        }
+        None => None,
+    };
+
+    let enrollment = if cli.enrollment {
+        let authority = match (cli.enrollment_ca_cert_path, cli.enrollment_ca_key_path) {
+            (Some(cert_path), Some(key_path)) => Some(
+                CertificateAuthority::load(&cert_path, &key_path)?
+                    .with_validity(cli.enrollment_certificate_validity),
+            ),
+            _ => None,
+        };
+
+        // Devices enrolled before a restart keep their credentials
+        let credentials =
+            device_credentials.get_or_insert_with(|| Arc::new(Credentials::default()));
+        let enrollments = device_store.enrollments()?;
+        for enrollment in &enrollments {
+            if enrollment.revoked_at.is_some() {
+                credentials.revoke_serial(&enrollment.serial);
+            } else if let Some(token_sha256) = &enrollment.token_sha256 {
+                credentials.set_enrolled(
+                    &enrollment.serial,
+                    Credential {
+                        token_sha256: token_sha256.clone(),
+                        devices: vec![enrollment.model.clone()],
+                        serial: Some(enrollment.serial.clone()),
+                    },
+                );
+            }
+        }
+        info!(
+            enrolled = enrollments.len(),
+            certificates = authority.is_some(),
+            "Device enrollment enabled"
+        );
+        Some(Arc::new(Enrollment::new(authority)))
+    } else {
+        None
+    };
+
+    let mut jwt_keys = match &cli.jwt_jwks_path {
+        Some(path) => jwt::load_jwks(path)?,
+        None => Vec::new(),
+    };
+    for path in &cli.jwt_public_key_path {
+        jwt_keys.push(jwt::load_public_key(path)?);
+    }
+    let jwt_verifier = if jwt_keys.is_empty() {
+        None
+    } else {
+        info!(keys = jwt_keys.len(), "JWT device tokens enabled");
+        Some(Arc::new(
+            JwtVerifier::new(jwt_keys)
+                .with_issuer(cli.jwt_issuer)
+                .with_audience(cli.jwt_audience),
+        ))
+    };
+
+    let api_config = ApiConfig {
+        admin_token: cli.admin_token,
+        device_store: Some(device_store),
+        alert_webhook,
+        device_credentials,
+        jwt_verifier,
+        certificate_devices: cli.tls_client_devices,
+        require_client_certificate: cli.tls_client_ca_path.is_some(),
+        enrollment,
+        throttle: Throttle {
+            devices: cli
+                .device_rate_limit
+                .map(|limit| Arc::new(RateLimiter::new(limit))),
+            ips: cli
+                .ip_rate_limit
+                .map(|limit| Arc::new(RateLimiter::new(limit))),
+        },
+        max_concurrent_downloads: cli.max_concurrent_downloads.map(NonZeroUsize::get),
+        trusted_proxies: cli.trusted_proxies,
+    };
+    if api_config.admin_token.is_some() {
+        info!("Admin API enabled");
+    }
+
+    let server_tls = match (cli.tls_cert_path, cli.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let tls = ServerTls {
+                cert_path,
+                key_path,
+                client_ca_path: cli.tls_client_ca_path,
+            };
+            if tls.client_ca_path.is_some() {
+                info!("Client certificates required on the device endpoints");
+            }
+            Some(tls.server_config()?)
+        }
+        _ => None,
+    };
+
     let main_server_cancel_token = cancel_token.clone();
     let metrics_server_cancel_token = cancel_token.clone();
 
@@ -249,6 +580,8 @@ pub async fn run(cli: Cli) -> Result<()> {
             &cli.listen_addr,
             Arc::clone(&fm),
             notifier,
+            api_config,
+            server_tls,
             main_server_cancel_token
         ),
         start_metrics_server(&cli.metrics_listen_addr, metrics_server_cancel_token),
@@ -266,18 +599,32 @@ async fn start_main_server(
     listen_address: &str,
     firmware_manager: Arc<FirmwareManager>,
     notifier: Option<Notifier>,
+    api_config: ApiConfig,
+    server_tls: Option<Arc<rustls::ServerConfig>>,
     cancel_token: CancellationToken,
 ) -> Result<()> {
     let listener = TcpListener::bind(listen_address).await?;
+    let router = api_router_with_config(firmware_manager, notifier, api_config);
+
+    if let Some(config) = server_tls {
+        info!("OtaFlux listening on {} (TLS)", listener.local_addr()?);
+        tls::serve(listener, config, router, cancel_token).await;
+        info!("Main server shut down gracefully");
+        return Ok(());
+    }
+
     info!("OtaFlux listening on {}", listener.local_addr()?);
 
     let shutdown_future = async move {
         cancel_token.cancelled().await;
     };
 
-    axum::serve(listener, api_router(firmware_manager, notifier))
-        .with_graceful_shutdown(shutdown_future) // Pass the 'static future
-        .await?;
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_future) // Pass the 'static future
+    .await?;
     info!("Main server shut down gracefully");
     Ok(())
 }
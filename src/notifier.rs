@@ -5,6 +5,8 @@ use std::sync::Arc;
 use std::time::Duration;
 use tracing::{info, instrument};
 
+use crate::channel::Channel;
+
 #[derive(Clone, Debug)]
 pub struct TlsConfig {
     pub ca_cert: Vec<u8>,
@@ -57,14 +59,55 @@ impl Notifier {
         ))
     }
 
-    /// Publishes a payload to the MQTT topic for a specific device.
+    /// Returns the MQTT topic of a device on a release channel: the device
+    /// topic for stable releases, suffixed with the channel name otherwise.
+    #[must_use]
+    pub fn topic(&self, device_id: &str, channel: Channel) -> String {
+        match channel.topic_suffix() {
+            Some(suffix) => format!("{}/{}/{}", self.topic, device_id, suffix),
+            None => format!("{}/{}", self.topic, device_id),
+        }
+    }
+
+    /// Returns the MQTT topic of the alerts raised for a device.
+    #[must_use]
+    pub fn alert_topic(&self, device_id: &str) -> String {
+        format!("{}/{}/alerts", self.topic, device_id)
+    }
+
+    /// Publishes an alert payload to the alert topic of a device, without
+    /// retaining it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the MQTT client fails to publish the message.
+    #[instrument(skip(self, payload), fields(topic = %self.alert_topic(device_id)))]
+    pub async fn publish_alert(
+        &self,
+        device_id: &str,
+        payload: Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        let topic = self.alert_topic(device_id);
+        info!(payload_size = payload.len(), "Publishing MQTT alert");
+        self.client
+            .publish(topic.clone(), QoS::AtLeastOnce, false, payload)
+            .await
+            .map_err(|e| anyhow!("Failed to publish alert to {topic:?}: {e:?}"))
+    }
+
+    /// Publishes a payload to the MQTT topic for a specific device and release channel.
     ///
     /// # Errors
     ///
     /// Returns an error if the MQTT client fails to publish the message.
-    #[instrument(skip(self, payload), fields(topic = %format!("{}/{}", self.topic, device_id)))]
-    pub async fn publish(&self, device_id: String, payload: Vec<u8>) -> Result<(), anyhow::Error> {
-        let topic = format!("{}/{}", self.topic, device_id);
+    #[instrument(skip(self, payload), fields(topic = %self.topic(&device_id, channel)))]
+    pub async fn publish(
+        &self,
+        device_id: String,
+        channel: Channel,
+        payload: Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        let topic = self.topic(&device_id, channel);
         info!(payload_size = payload.len(), "Publishing MQTT message");
         self.client
             .publish(topic.clone(), QoS::AtLeastOnce, true, payload)
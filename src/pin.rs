@@ -0,0 +1,226 @@
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use semver::VersionReq;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// Release a pin rule holds devices on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PinTarget {
+    /// Exact registry tag.
+    Tag(String),
+    /// Manifest digest of a single build, e.g. `sha256:...`.
+    Digest(String),
+    /// Latest version matching a semver requirement, e.g. `~1.4`.
+    Version(VersionReq),
+}
+
+impl fmt::Display for PinTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tag(tag) => write!(f, "tag {tag}"),
+            Self::Digest(digest) => write!(f, "digest {digest}"),
+            Self::Version(requirement) => write!(f, "version {requirement}"),
+        }
+    }
+}
+
+/// Holds matching devices on a release while the rest of the fleet moves on.
+///
+/// Rules match a device ID glob (`*` and `?` wildcards), and optionally the
+/// serial or group reported by the device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "PinRuleSpec", into = "PinRuleSpec")]
+pub struct PinRule {
+    pub device: String,
+    pub serial: Option<String>,
+    pub group: Option<String>,
+    pub target: PinTarget,
+}
+
+/// Serialized form of a [`PinRule`], with exactly one of `tag`, `digest` and
+/// `version` set.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PinRuleSpec {
+    #[serde(default = "any_device")]
+    device: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    serial: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+}
+
+fn any_device() -> String {
+    "*".to_string()
+}
+
+impl TryFrom<PinRuleSpec> for PinRule {
+    type Error = String;
+
+    fn try_from(spec: PinRuleSpec) -> Result<Self, Self::Error> {
+        let target = match (spec.tag, spec.digest, spec.version) {
+            (Some(tag), None, None) => PinTarget::Tag(tag),
+            (None, Some(digest), None) => PinTarget::Digest(digest),
+            (None, None, Some(version)) => PinTarget::Version(
+                VersionReq::parse(&version)
+                    .map_err(|e| format!("Invalid version requirement '{version}': {e}"))?,
+            ),
+            _ => {
+                return Err(
+                    "Pin rules must set exactly one of 'tag', 'digest' or 'version'".to_string(),
+                )
+            }
+        };
+
+        Ok(Self {
+            device: spec.device,
+            serial: spec.serial,
+            group: spec.group,
+            target,
+        })
+    }
+}
+
+impl From<PinRule> for PinRuleSpec {
+    fn from(rule: PinRule) -> Self {
+        let (mut tag, mut digest, mut version) = (None, None, None);
+        match rule.target {
+            PinTarget::Tag(t) => tag = Some(t),
+            PinTarget::Digest(d) => digest = Some(d),
+            PinTarget::Version(v) => version = Some(v.to_string()),
+        }
+
+        Self {
+            device: rule.device,
+            serial: rule.serial,
+            group: rule.group,
+            tag,
+            digest,
+            version,
+        }
+    }
+}
+
+impl PinRule {
+    /// Returns `true` if the rule applies to the device.
+    #[must_use]
+    pub fn matches(&self, device_id: &str, serial: Option<&str>, group: Option<&str>) -> bool {
+        glob_matches(&self.device, device_id)
+            && self.serial.as_deref().is_none_or(|s| Some(s) == serial)
+            && self.group.as_deref().is_none_or(|g| Some(g) == group)
+    }
+
+    /// Rules naming a serial take precedence over rules naming a group, which
+    /// take precedence over rules only matching the device ID.
+    fn specificity(&self) -> u8 {
+        u8::from(self.serial.is_some()) * 2 + u8::from(self.group.is_some())
+    }
+}
+
+/// Matches `text` against a glob `pattern` supporting `*` and `?` wildcards.
+pub(crate) fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern, and of the text it matched up to
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Loads pin rules from a JSON file mapping rule IDs to rules.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or contains invalid rules.
+pub fn load_rules(path: &Path) -> Result<BTreeMap<String, PinRule>> {
+    let content = std::fs::read(path)
+        .with_context(|| format!("Failed to read pin file {}", path.display()))?;
+    serde_json::from_slice(&content).with_context(|| format!("Invalid pin file {}", path.display()))
+}
+
+/// Pin rules by ID, adjustable at runtime.
+#[derive(Default)]
+pub struct Pins {
+    rules: Mutex<BTreeMap<String, PinRule>>,
+}
+
+impl Pins {
+    #[must_use]
+    pub fn new(rules: BTreeMap<String, PinRule>) -> Self {
+        Self {
+            rules: Mutex::new(rules),
+        }
+    }
+
+    /// Returns all pin rules, sorted by ID.
+    #[must_use]
+    pub fn list(&self) -> Vec<(String, PinRule)> {
+        self.rules
+            .lock()
+            .iter()
+            .map(|(id, rule)| (id.clone(), rule.clone()))
+            .collect()
+    }
+
+    /// Creates or replaces a pin rule.
+    pub fn set(&self, id: &str, rule: PinRule) {
+        self.rules.lock().insert(id.to_string(), rule);
+    }
+
+    /// Removes a pin rule, returning it if it existed.
+    pub fn remove(&self, id: &str) -> Option<PinRule> {
+        self.rules.lock().remove(id)
+    }
+
+    /// Returns the most specific rule matching a device, along with its ID.
+    /// Rules equally specific are ordered by ID.
+    #[must_use]
+    pub fn find(
+        &self,
+        device_id: &str,
+        serial: Option<&str>,
+        group: Option<&str>,
+    ) -> Option<(String, PinRule)> {
+        let rules = self.rules.lock();
+        let mut found: Option<(&String, &PinRule)> = None;
+        for (id, rule) in rules.iter() {
+            if rule.matches(device_id, serial, group)
+                && found.is_none_or(|(_, f)| rule.specificity() > f.specificity())
+            {
+                found = Some((id, rule));
+            }
+        }
+        found.map(|(id, rule)| (id.clone(), rule.clone()))
+    }
+}
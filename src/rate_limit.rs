@@ -0,0 +1,134 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::rollout::parse_duration;
+
+/// Request rate allowed for a client, e.g. `60/1m`: 60 requests per minute,
+/// spread over the period or sent in a burst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    /// Interval at which a client regains one request.
+    fn interval(&self) -> Duration {
+        self.period / self.requests
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| format!("Invalid rate limit '{value}': {reason}");
+
+        let (requests, period) = value
+            .split_once('/')
+            .ok_or_else(|| invalid("expected <requests>/<period>"))?;
+        let requests: u32 = requests
+            .trim()
+            .parse()
+            .map_err(|e| invalid(&format!("requests: {e}")))?;
+        if requests == 0 {
+            return Err(invalid("requests must be positive"));
+        }
+
+        Ok(Self {
+            requests,
+            period: parse_duration(period.trim()).map_err(|e| invalid(&e))?,
+        })
+    }
+}
+
+struct Clients {
+    /// Time at which each client regains its full allowance, for clients
+    /// that used part of it.
+    replenished_at: HashMap<String, Instant>,
+    pruned_at: Instant,
+}
+
+/// Limits the request rate of each client, identified by a key such as its
+/// serial or IP address.
+///
+/// Clients start with the full allowance of the limit, and regain it at a
+/// steady pace: a `60/1m` client can send 60 requests at once, then one every
+/// second.
+pub struct RateLimiter {
+    limit: RateLimit,
+    clients: Mutex<Clients>,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            clients: Mutex::new(Clients {
+                replenished_at: HashMap::new(),
+                pruned_at: Instant::now(),
+            }),
+        }
+    }
+
+    #[must_use]
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Counts a request of a client.
+    ///
+    /// # Errors
+    ///
+    /// Returns how long the client must wait before its next request if it
+    /// exceeded the limit. Rejected requests aren't counted.
+    pub fn check(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut clients = self.clients.lock();
+
+        // Forget clients that regained their full allowance, so that the
+        // map only holds recently active clients
+        if now.saturating_duration_since(clients.pruned_at) >= self.limit.period {
+            clients.replenished_at.retain(|_, at| *at > now);
+            clients.pruned_at = now;
+        }
+
+        let interval = self.limit.interval();
+        let replenished_at = clients
+            .replenished_at
+            .get(key)
+            .copied()
+            .filter(|at| *at > now)
+            .unwrap_or(now);
+        let wait = (replenished_at - now + interval).saturating_sub(self.limit.period);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        clients
+            .replenished_at
+            .insert(key.to_string(), replenished_at + interval);
+        Ok(())
+    }
+
+    /// Returns the number of clients that used part of their allowance.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.clients.lock().replenished_at.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("limit", &self.limit)
+            .finish_non_exhaustive()
+    }
+}
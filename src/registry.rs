@@ -1,20 +1,68 @@
 use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
 use oci_client::{
     client::{Client, ClientConfig, ClientProtocol},
     manifest::{
-        OciManifest,
+        ImageIndexEntry, OciDescriptor, OciImageManifest, OciManifest,
         OciManifest::{Image, ImageIndex},
     },
     secrets::RegistryAuth,
     Reference,
 };
 use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha512};
 use sigstore::cosign::client::Client as CosignClient;
 use sigstore::cosign::CosignCapabilities;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
 use tracing::{debug, error, info, instrument};
 
 const COSIGN_SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+/// Standard OCI annotation holding the file name of a layer.
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+/// Image index entry annotation naming the hardware variant of a manifest.
+pub const HARDWARE_ANNOTATION: &str = "dev.otaflux.hardware";
+
+/// Error returned when no manifest of an image index matches the hardware
+/// variant requested by a device.
+#[derive(Debug)]
+pub struct VariantNotFound {
+    pub hw: String,
+}
+
+impl fmt::Display for VariantNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No manifest matches hardware variant '{}'", self.hw)
+    }
+}
+
+impl std::error::Error for VariantNotFound {}
+
+/// Returns `true` if an image index entry targets the `hw` hardware variant,
+/// either through its platform (architecture, variant, OS or CPU features) or
+/// the [`HARDWARE_ANNOTATION`] annotation.
+fn matches_hardware(entry: &ImageIndexEntry, hw: &str) -> bool {
+    let annotated = entry
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(HARDWARE_ANNOTATION))
+        .is_some_and(|value| value == hw);
+
+    let platform = entry.platform.as_ref().is_some_and(|platform| {
+        platform.architecture == hw
+            || platform.variant.as_deref() == Some(hw)
+            || platform
+                .os_features
+                .iter()
+                .chain(platform.features.iter())
+                .flatten()
+                .any(|feature| feature == hw)
+    });
+
+    annotated || platform
+}
 
 #[derive(Deserialize, Debug)]
 struct CosignSignedPayload {
@@ -33,6 +81,9 @@ struct ImageSection {
     docker_manifest_digest: String,
 }
 
+/// Stream of firmware blob chunks as they arrive from the registry.
+pub type BlobStream = BoxStream<'static, Result<Bytes>>;
+
 /// Result of fetching a firmware blob from the registry.
 #[derive(Debug)]
 pub struct FetchBlobResult {
@@ -44,6 +95,122 @@ pub struct FetchBlobResult {
     pub manifest_digest: String,
 }
 
+/// Result of opening a streaming download of a firmware blob.
+pub struct FetchBlobStream {
+    /// Chunks of the firmware binary, in order.
+    pub stream: BlobStream,
+    /// The blob size announced by the layer descriptor, if valid.
+    pub size: Option<u64>,
+    /// The manifest digest of the artifact, see [`FetchBlobResult::manifest_digest`].
+    pub manifest_digest: String,
+    /// The component streamed by `stream`, i.e. the first layer of the artifact.
+    pub primary: ComponentRef,
+    /// The remaining components of a multi-layer artifact, to be fetched with
+    /// [`RegistryClient::fetch_component`].
+    pub components: Vec<ComponentRef>,
+}
+
+/// A component (layer) of a firmware artifact, e.g. bootloader, partition
+/// table, application or filesystem image.
+#[derive(Clone, Debug)]
+pub struct ComponentRef {
+    /// Component name, taken from the `org.opencontainers.image.title`
+    /// annotation of the layer, or its media type when not annotated.
+    pub name: String,
+    pub media_type: String,
+    image_ref: Reference,
+    descriptor: OciDescriptor,
+}
+
+impl ComponentRef {
+    fn new(image_ref: Reference, descriptor: OciDescriptor) -> Self {
+        let name = descriptor
+            .annotations
+            .as_ref()
+            .and_then(|a| a.get(TITLE_ANNOTATION))
+            .cloned()
+            .unwrap_or_else(|| descriptor.media_type.clone());
+
+        Self {
+            name,
+            media_type: descriptor.media_type.clone(),
+            image_ref,
+            descriptor,
+        }
+    }
+
+    /// Returns the content digest of the component, e.g. `sha256:abc123...`.
+    #[must_use]
+    pub fn digest(&self) -> &str {
+        &self.descriptor.digest
+    }
+}
+
+enum BlobHasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+/// Content digest of a blob (e.g. `sha256:abc123...`), checked against its
+/// bytes as they are received so that a corrupted or tampered blob is never
+/// served.
+pub struct BlobDigest {
+    expected: String,
+    hasher: BlobHasher,
+}
+
+impl BlobDigest {
+    /// # Errors
+    ///
+    /// Returns an error if the digest algorithm isn't supported.
+    pub fn new(digest: &str) -> Result<Self> {
+        let hasher = match digest.split_once(':') {
+            Some(("sha256", _)) => BlobHasher::Sha256(Sha256::new()),
+            Some(("sha512", _)) => BlobHasher::Sha512(Sha512::new()),
+            _ => return Err(anyhow!("Unsupported blob digest '{digest}'")),
+        };
+
+        Ok(Self {
+            expected: digest.to_ascii_lowercase(),
+            hasher,
+        })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.hasher {
+            BlobHasher::Sha256(hasher) => hasher.update(data),
+            BlobHasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Checks the bytes received so far against the expected digest.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the digests don't match.
+    pub fn verify(self) -> Result<()> {
+        let actual = match self.hasher {
+            BlobHasher::Sha256(hasher) => format!("sha256:{:x}", hasher.finalize()),
+            BlobHasher::Sha512(hasher) => format!("sha512:{:x}", hasher.finalize()),
+        };
+
+        if actual != self.expected {
+            return Err(anyhow!(
+                "Blob digest mismatch: expected {}, got {actual}",
+                self.expected
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks a fully received blob against the digest of its descriptor.
+fn verify_blob(descriptor: &OciDescriptor, data: &[u8]) -> Result<()> {
+    let mut digest = BlobDigest::new(&descriptor.digest)?;
+    digest.update(data);
+    digest.verify()
+}
+
 #[derive(Clone)]
 pub struct RegistryClient {
     client: Client,
@@ -117,37 +284,173 @@ impl RegistryClient {
     /// Fetches the manifest digest for a given repository and tag without downloading the blob.
     ///
     /// This is a lightweight operation used to check if the cached firmware is still valid
-    /// by comparing digests.
+    /// by comparing digests. When the tag points to an image index, the digest
+    /// of the manifest selected for the `hw` hardware variant is returned.
     ///
     /// # Errors
     ///
-    /// Returns an error if the manifest cannot be pulled from the registry.
+    /// Returns an error if the manifest cannot be pulled from the registry, or
+    /// [`VariantNotFound`] if no manifest matches the hardware variant.
     #[instrument(skip(self), fields(repository = %repository, tag = %tag))]
-    pub async fn fetch_manifest_digest(&self, repository: &str, tag: &str) -> Result<String> {
-        let image_ref = self.image_path(repository, Some(tag))?;
-        let (_, digest) = self.client.pull_manifest(&image_ref, &self.auth).await?;
+    pub async fn fetch_manifest_digest(
+        &self,
+        repository: &str,
+        tag: &str,
+        hw: Option<&str>,
+    ) -> Result<String> {
+        let (_, _, digest) = self.resolve_manifest(repository, tag, hw).await?;
         Ok(digest)
     }
 
+    /// Fetches the manifest digest and annotations for a given repository and
+    /// tag without downloading the blob. When the tag points to an image index,
+    /// the manifest selected for the `hw` hardware variant is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the manifest cannot be pulled from the registry, or
+    /// [`VariantNotFound`] if no manifest matches the hardware variant.
+    #[instrument(skip(self), fields(repository = %repository, tag = %tag))]
+    pub async fn fetch_manifest_annotations(
+        &self,
+        repository: &str,
+        tag: &str,
+        hw: Option<&str>,
+    ) -> Result<(String, BTreeMap<String, String>)> {
+        let (_, manifest, digest) = self.resolve_manifest(repository, tag, hw).await?;
+        Ok((digest, manifest.annotations.unwrap_or_default()))
+    }
+
     /// Fetches a firmware blob from the registry for a given repository and tag.
     ///
-    /// If cosign verification is enabled, validates the artifact signature before returning.
+    /// When the tag points to an image index, the manifest matching the `hw`
+    /// hardware variant is used, or the first one if no variant is requested.
+    /// If cosign verification is enabled, validates the signature of that
+    /// manifest before returning.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The manifest cannot be pulled
+    /// - No manifest matches the hardware variant ([`VariantNotFound`])
+    /// - Cosign signature verification fails (when enabled)
+    /// - The blob cannot be fetched or doesn't match its digest
+    #[instrument(skip(self), fields(repository = %repository, tag = %tag))]
+    pub async fn fetch_blob(
+        &self,
+        repository: &str,
+        tag: &str,
+        hw: Option<&str>,
+    ) -> Result<FetchBlobResult> {
+        let (image_ref, layers, manifest_digest) =
+            self.resolve_artifact(repository, tag, hw).await?;
+        let layer = layers
+            .first()
+            .ok_or_else(|| anyhow!("Image manifest for {image_ref} has no layers"))?;
+
+        let mut data: Vec<u8> = Vec::new();
+        self.client.pull_blob(&image_ref, layer, &mut data).await?;
+
+        if data.is_empty() {
+            return Err(anyhow!("Fetched artifact blob for {image_ref} is empty"));
+        }
+        verify_blob(layer, &data).with_context(|| format!("Invalid blob for {image_ref}"))?;
+
+        Ok(FetchBlobResult {
+            data,
+            manifest_digest,
+        })
+    }
+
+    /// Opens a streaming download of a firmware blob for a given repository and tag.
+    ///
+    /// Signature verification happens before the stream is opened, exactly as in
+    /// [`RegistryClient::fetch_blob`]. The blob itself is not buffered, chunks are
+    /// yielded as they are received from the registry, and must be checked
+    /// against the digest of [`FetchBlobStream::primary`] with [`BlobDigest`].
     ///
     /// # Errors
     ///
     /// Returns an error if:
     /// - The manifest cannot be pulled
+    /// - No manifest matches the hardware variant ([`VariantNotFound`])
     /// - Cosign signature verification fails (when enabled)
-    /// - The blob cannot be fetched
+    /// - The blob download cannot be started
     #[instrument(skip(self), fields(repository = %repository, tag = %tag))]
-    pub async fn fetch_blob(&self, repository: &str, tag: &str) -> Result<FetchBlobResult> {
-        let artifact_image_ref = self.image_path(repository, Some(tag))?;
-        let (_artifact_manifest, artifact_manifest_digest) = self
+    pub async fn fetch_blob_stream(
+        &self,
+        repository: &str,
+        tag: &str,
+        hw: Option<&str>,
+    ) -> Result<FetchBlobStream> {
+        let (image_ref, layers, manifest_digest) =
+            self.resolve_artifact(repository, tag, hw).await?;
+
+        let mut components = layers
+            .into_iter()
+            .map(|layer| ComponentRef::new(image_ref.clone(), layer));
+        let primary = components
+            .next()
+            .ok_or_else(|| anyhow!("Image manifest for {image_ref} has no layers"))?;
+        let components: Vec<ComponentRef> = components.collect();
+
+        let sized_stream = self
             .client
-            .pull_manifest(&artifact_image_ref, &self.auth)
+            .pull_blob_stream(&image_ref, &primary.descriptor)
             .await?;
 
-        let artifact_manifest_digest_str = artifact_manifest_digest.clone();
+        Ok(FetchBlobStream {
+            stream: sized_stream.stream.map_err(anyhow::Error::from).boxed(),
+            size: u64::try_from(primary.descriptor.size).ok(),
+            manifest_digest,
+            primary,
+            components,
+        })
+    }
+
+    /// Fetches a single component of a firmware artifact.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the blob cannot be fetched, is empty or doesn't
+    /// match its digest.
+    #[instrument(skip(self, component), fields(component = %component.name))]
+    pub async fn fetch_component(&self, component: &ComponentRef) -> Result<Bytes> {
+        let mut data: Vec<u8> = Vec::new();
+        self.client
+            .pull_blob(&component.image_ref, &component.descriptor, &mut data)
+            .await?;
+
+        if data.is_empty() {
+            return Err(anyhow!(
+                "Fetched component '{}' for {} is empty",
+                component.name,
+                component.image_ref
+            ));
+        }
+        verify_blob(&component.descriptor, &data).with_context(|| {
+            format!(
+                "Invalid component '{}' for {}",
+                component.name, component.image_ref
+            )
+        })?;
+
+        Ok(Bytes::from(data))
+    }
+
+    /// Resolves the artifact for a given repository, tag and hardware variant,
+    /// verifying its cosign signature when enabled.
+    ///
+    /// Returns the image reference, the firmware layer descriptors and the
+    /// manifest digest of the artifact.
+    async fn resolve_artifact(
+        &self,
+        repository: &str,
+        tag: &str,
+        hw: Option<&str>,
+    ) -> Result<(Reference, Vec<OciDescriptor>, String)> {
+        let (artifact_image_ref, image_manifest, artifact_manifest_digest_str) =
+            self.resolve_manifest(repository, tag, hw).await?;
 
         if self.cosign_pub_key.is_some() {
             debug!("Verifying cosign signature");
@@ -190,14 +493,23 @@ impl RegistryClient {
             info!("Cosign payload verified and matches artifact digest");
         }
 
-        let data = self
-            .fetch_layer_blob(&artifact_image_ref, repository)
-            .await?;
+        let Some(artifact_layer_descriptor) = image_manifest.layers.first() else {
+            return Err(anyhow!(
+                "Image manifest for {artifact_image_ref} has no layers"
+            ));
+        };
 
-        Ok(FetchBlobResult {
-            data,
-            manifest_digest: artifact_manifest_digest_str,
-        })
+        info!(
+            digest = %artifact_layer_descriptor.digest,
+            components = image_manifest.layers.len(),
+            "Found artifact blob"
+        );
+
+        Ok((
+            artifact_image_ref,
+            image_manifest.layers,
+            artifact_manifest_digest_str,
+        ))
     }
 
     /// Fetches the cosign signature data for a given repository and signature tag.
@@ -257,64 +569,53 @@ impl RegistryClient {
         Ok((signature_payload_bytes, signature_base64))
     }
 
-    /// Fetches the actual artifact blob (firmware binary) from the first layer of the image.
-    async fn fetch_layer_blob(
+    /// Resolves the image manifest of an artifact for a given repository and tag.
+    ///
+    /// When the tag points to an image index, the entry matching the `hw`
+    /// hardware variant is selected, or the first entry if no variant is
+    /// requested. Returns the reference, manifest and digest of the selected
+    /// image manifest.
+    async fn resolve_manifest(
         &self,
-        image_ref: &Reference,
-        repository_name_for_error: &str,
-    ) -> Result<Vec<u8>> {
-        debug!(image = %image_ref, "Fetching artifact blob");
-
-        let (manifest, _) = self.client.pull_manifest(image_ref, &self.auth).await?;
-
-        let image_manifest = match manifest {
-            ImageIndex(index) => {
-                let first_manifest_descriptor = index
-                    .manifests
-                    .first()
-                    .ok_or_else(|| anyhow!("Image index for {image_ref} is empty"))?;
-
-                let platform_specific_image_ref = self.image_path(
-                    repository_name_for_error,
-                    Some(&first_manifest_descriptor.digest),
-                )?;
-
-                let (resolved_manifest, _resolved_digest) = self
-                    .client
-                    .pull_manifest(&platform_specific_image_ref, &self.auth)
-                    .await?;
-
-                match resolved_manifest {
-                    OciManifest::Image(m) => m,
-                    OciManifest::ImageIndex(_) => {
-                        return Err(anyhow!(
-                            "Resolved manifest for {platform_specific_image_ref} (from index) is not an ImageManifest"
-                        ))
-                    }
-                }
-            }
-            Image(m) => m,
+        repository: &str,
+        tag: &str,
+        hw: Option<&str>,
+    ) -> Result<(Reference, OciImageManifest, String)> {
+        let image_ref = self.image_path(repository, Some(tag))?;
+        debug!(image = %image_ref, "Resolving artifact manifest");
+
+        let (manifest, digest) = self.client.pull_manifest(&image_ref, &self.auth).await?;
+
+        let index = match manifest {
+            Image(m) => return Ok((image_ref, m, digest)),
+            ImageIndex(index) => index,
         };
 
-        let artifact_layer_descriptor = image_manifest
-            .layers
-            .first()
-            .ok_or_else(|| anyhow!("Image manifest for {image_ref} has no layers"))?;
+        let entry = match hw {
+            Some(hw) => index
+                .manifests
+                .iter()
+                .find(|entry| matches_hardware(entry, hw))
+                .ok_or_else(|| VariantNotFound { hw: hw.to_string() })?,
+            None => index
+                .manifests
+                .first()
+                .ok_or_else(|| anyhow!("Image index for {image_ref} is empty"))?,
+        };
+        debug!(digest = %entry.digest, hw = ?hw, "Selected manifest from image index");
 
-        info!(
-            digest = %artifact_layer_descriptor.digest,
-            "Found artifact blob"
-        );
+        let platform_specific_image_ref = self.image_digest_path(repository, &entry.digest)?;
 
-        let mut blob_data: Vec<u8> = Vec::new();
-        self.client
-            .pull_blob(image_ref, artifact_layer_descriptor, &mut blob_data)
+        let (resolved_manifest, _resolved_digest) = self
+            .client
+            .pull_manifest(&platform_specific_image_ref, &self.auth)
             .await?;
 
-        if blob_data.is_empty() {
-            Err(anyhow!("Fetched artifact blob for {image_ref} is empty"))
-        } else {
-            Ok(blob_data)
+        match resolved_manifest {
+            Image(m) => Ok((platform_specific_image_ref, m, entry.digest.clone())),
+            ImageIndex(_) => Err(anyhow!(
+                "Resolved manifest for {platform_specific_image_ref} (from index) is not an ImageManifest"
+            )),
         }
     }
 
@@ -331,6 +632,15 @@ impl RegistryClient {
             .with_context(|| format!("Invalid image reference: {reference_string}"))
     }
 
+    /// Constructs an OCI image reference pinned to a manifest digest (e.g., "registry/repository@sha256:...").
+    fn image_digest_path(&self, repository: &str, digest: &str) -> Result<Reference> {
+        let reference_string = format!("{}/{}@{}", self.registry, repository, digest);
+
+        reference_string
+            .parse()
+            .with_context(|| format!("Invalid image reference: {reference_string}"))
+    }
+
     /// Verifies a Cosign signature against a payload using the configured public key.
     ///
     /// # Arguments
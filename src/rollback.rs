@@ -0,0 +1,61 @@
+use parking_lot::Mutex;
+use semver::Version;
+use std::collections::BTreeMap;
+
+/// An older version deliberately served to a device model, or to a single
+/// device of that model identified by its serial, even though devices
+/// otherwise never receive a version lower than the one they run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RollbackTarget {
+    pub device: String,
+    /// Serial of the device rolled back, the whole model when not set.
+    pub serial: Option<String>,
+    pub version: Version,
+}
+
+/// Rollback targets per device ID and serial, adjustable at runtime.
+#[derive(Default)]
+pub struct Rollbacks {
+    targets: Mutex<BTreeMap<(String, Option<String>), Version>>,
+}
+
+impl Rollbacks {
+    /// Returns the rollback target of a device, preferring the target of its
+    /// serial over the target of its model.
+    #[must_use]
+    pub fn get(&self, device_id: &str, serial: Option<&str>) -> Option<Version> {
+        let targets = self.targets.lock();
+        serial
+            .and_then(|serial| targets.get(&(device_id.to_string(), Some(serial.to_string()))))
+            .or_else(|| targets.get(&(device_id.to_string(), None)))
+            .cloned()
+    }
+
+    /// Returns all rollback targets, sorted by device ID and serial.
+    #[must_use]
+    pub fn list(&self) -> Vec<RollbackTarget> {
+        self.targets
+            .lock()
+            .iter()
+            .map(|((device, serial), version)| RollbackTarget {
+                device: device.clone(),
+                serial: serial.clone(),
+                version: version.clone(),
+            })
+            .collect()
+    }
+
+    /// Creates or replaces a rollback target.
+    pub fn set(&self, target: RollbackTarget) {
+        self.targets
+            .lock()
+            .insert((target.device, target.serial), target.version);
+    }
+
+    /// Removes a rollback target, returning its version if it existed.
+    pub fn remove(&self, device_id: &str, serial: Option<&str>) -> Option<Version> {
+        self.targets
+            .lock()
+            .remove(&(device_id.to_string(), serial.map(str::to_string)))
+    }
+}
@@ -0,0 +1,449 @@
+use parking_lot::Mutex;
+use semver::Version;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+use crate::inventory::RolloutRecord;
+
+/// Number of buckets devices are spread over, one per rollout percent.
+const BUCKETS: u64 = 100;
+
+/// Repository name applying a schedule to every repository without its own.
+const ANY_REPOSITORY: &str = "*";
+
+/// A staged rollout of a firmware version to a percentage of a device model.
+///
+/// Devices are hashed into stable buckets from their serial, so a device
+/// included at 5% remains included at 25% and 100%. Devices outside the
+/// rollout are served the latest version preceding it, and stable versions
+/// newer than the rollout are held back until it completes or is removed.
+/// Rollouts only stage stable releases: pre-releases of other channels are
+/// never held back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rollout {
+    /// The version being rolled out.
+    pub version: Version,
+    /// Percentage of devices receiving `version`, from 0 to 100.
+    pub percentage: u8,
+    /// Halted rollouts keep their current percentage until resumed.
+    pub halted: bool,
+    /// Automatic progression of rollouts started from a schedule.
+    pub progression: Option<Progression>,
+}
+
+/// Position of a scheduled rollout in its schedule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Progression {
+    pub schedule: RolloutSchedule,
+    /// Index of the current step in the schedule.
+    pub step: usize,
+    /// When the current step started.
+    pub step_started_at: SystemTime,
+}
+
+impl Rollout {
+    /// Creates a rollout at a fixed percentage.
+    #[must_use]
+    pub fn new(version: Version, percentage: u8) -> Self {
+        Self {
+            version,
+            percentage,
+            halted: false,
+            progression: None,
+        }
+    }
+
+    /// Creates a rollout ramping through `schedule`, starting at `now`.
+    #[must_use]
+    pub fn scheduled(version: Version, schedule: RolloutSchedule, now: SystemTime) -> Self {
+        Self {
+            version,
+            percentage: schedule.steps.first().copied().unwrap_or(100),
+            halted: false,
+            progression: Some(Progression {
+                schedule,
+                step: 0,
+                step_started_at: now,
+            }),
+        }
+    }
+
+    /// Returns `true` once the version is rolled out to every device.
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.percentage >= 100
+    }
+
+    /// Returns `true` if the device with the given serial is part of the
+    /// rollout. Devices without serial can't be bucketed and only receive the
+    /// version once it is rolled out to the whole fleet.
+    #[must_use]
+    pub fn includes(&self, device_id: &str, serial: Option<&str>) -> bool {
+        if self.is_complete() {
+            return true;
+        }
+        serial.is_some_and(|serial| bucket(device_id, serial) < self.percentage)
+    }
+
+    /// Returns `true` if `version` may be served to the given device.
+    #[must_use]
+    pub fn allows(&self, version: &Version, device_id: &str, serial: Option<&str>) -> bool {
+        if *version == self.version {
+            return self.includes(device_id, serial);
+        }
+        *version < self.version || !version.pre.is_empty() || self.is_complete()
+    }
+
+    /// Advances a scheduled rollout through every step whose interval elapsed
+    /// at `now`. Returns `true` if the percentage changed.
+    pub fn progress(&mut self, now: SystemTime) -> bool {
+        if self.halted {
+            return false;
+        }
+        let Some(progression) = &mut self.progression else {
+            return false;
+        };
+
+        let interval = progression.schedule.interval;
+        let last_step = progression.schedule.steps.len().saturating_sub(1);
+        let mut changed = false;
+
+        while progression.step < last_step
+            && now
+                .duration_since(progression.step_started_at)
+                .is_ok_and(|elapsed| elapsed >= interval)
+        {
+            progression.step += 1;
+            progression.step_started_at += interval;
+            self.percentage = progression.schedule.steps[progression.step];
+            changed = true;
+        }
+
+        changed
+    }
+
+    /// Pauses the rollout at its current percentage.
+    pub fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    /// Resumes a halted rollout. Scheduled rollouts wait a full interval
+    /// before ramping to their next step.
+    pub fn resume(&mut self, now: SystemTime) {
+        if self.halted {
+            self.halted = false;
+            if let Some(progression) = &mut self.progression {
+                progression.step_started_at = now;
+            }
+        }
+    }
+}
+
+/// Returns the stable rollout bucket, from 0 to 99, of a device.
+#[must_use]
+pub fn bucket(device_id: &str, serial: &str) -> u8 {
+    let digest = Sha256::digest(format!("{device_id}/{serial}").as_bytes());
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    let bucket = u64::from_be_bytes(prefix) % BUCKETS;
+    u8::try_from(bucket).unwrap_or_default()
+}
+
+/// Percentages a rollout ramps through, one step per interval, e.g.
+/// `1,10,50,100@6h`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RolloutSchedule {
+    pub steps: Vec<u8>,
+    pub interval: Duration,
+}
+
+impl FromStr for RolloutSchedule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (steps, interval) = value.split_once('@').ok_or_else(|| {
+            format!("Invalid rollout schedule '{value}': expected <steps>@<interval>")
+        })?;
+
+        let steps = steps
+            .split(',')
+            .map(|step| {
+                step.trim()
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|step| *step <= 100)
+                    .ok_or_else(|| format!("Invalid rollout step '{step}': expected 0 to 100"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if steps.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(format!(
+                "Invalid rollout schedule '{value}': steps must be increasing"
+            ));
+        }
+
+        Ok(Self {
+            steps,
+            interval: parse_duration(interval.trim())?,
+        })
+    }
+}
+
+impl fmt::Display for RolloutSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps: Vec<String> = self.steps.iter().map(u8::to_string).collect();
+        write!(f, "{}@{}", steps.join(","), format_duration(self.interval))
+    }
+}
+
+/// Formats a duration in the largest unit parsed by [`parse_duration`] that
+/// represents it exactly.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)]
+        .into_iter()
+        .find(|(_, unit_secs)| seconds > 0 && seconds.is_multiple_of(*unit_secs))
+        .map_or_else(
+            || format!("{seconds}s"),
+            |(unit, unit_secs)| format!("{}{unit}", seconds / unit_secs),
+        )
+}
+
+/// Parses a duration such as `30s`, `15m`, `6h` or `1d`.
+pub(crate) fn parse_duration(value: &str) -> Result<Duration, String> {
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("Invalid duration '{value}': missing unit (s, m, h, d)"))?;
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|e| format!("Invalid duration '{value}': {e}"))?;
+
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Invalid duration '{value}': unknown unit '{unit}'")),
+    };
+
+    match amount.checked_mul(unit_secs) {
+        Some(0) => Err(format!("Invalid duration '{value}': must be positive")),
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+        None => Err(format!("Invalid duration '{value}': too large")),
+    }
+}
+
+/// Rollout schedule of a repository, e.g. `esp32-sensor=1,10,50,100@6h`.
+///
+/// The `*` repository applies to every repository without its own schedule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RepositorySchedule {
+    pub repository: String,
+    pub schedule: RolloutSchedule,
+}
+
+impl FromStr for RepositorySchedule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (repository, schedule) = value.split_once('=').ok_or_else(|| {
+            format!("Invalid rollout schedule '{value}': expected <repository>=<steps>@<interval>")
+        })?;
+
+        Ok(Self {
+            repository: repository.trim().to_string(),
+            schedule: schedule.parse()?,
+        })
+    }
+}
+
+/// Staged rollouts of every device model, adjustable at runtime.
+///
+/// Repositories with a schedule get a rollout started automatically when a
+/// new stable version appears in the registry, once the previous rollout
+/// completed. A version is only considered fully rolled out once its rollout
+/// completed or was removed, never because it was the latest version when
+/// the repository was first observed.
+#[derive(Default)]
+pub struct Rollouts {
+    by_device: Mutex<HashMap<String, Rollout>>,
+    schedules: HashMap<String, RolloutSchedule>,
+    /// Latest stable version per device ID when its rollout was removed.
+    baselines: Mutex<HashMap<String, Version>>,
+}
+
+impl Rollouts {
+    #[must_use]
+    pub fn new(schedules: Vec<RepositorySchedule>) -> Self {
+        Self {
+            schedules: schedules
+                .into_iter()
+                .map(|s| (s.repository, s.schedule))
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the rollout schedule of a device ID, if any.
+    #[must_use]
+    pub fn schedule(&self, device_id: &str) -> Option<&RolloutSchedule> {
+        self.schedules
+            .get(device_id)
+            .or_else(|| self.schedules.get(ANY_REPOSITORY))
+    }
+
+    /// Returns the rollout of a device ID, if any.
+    #[must_use]
+    pub fn get(&self, device_id: &str) -> Option<Rollout> {
+        self.by_device.lock().get(device_id).cloned()
+    }
+
+    /// Returns all rollouts, sorted by device ID.
+    #[must_use]
+    pub fn list(&self) -> Vec<(String, Rollout)> {
+        let mut rollouts: Vec<_> = self
+            .by_device
+            .lock()
+            .iter()
+            .map(|(device_id, rollout)| (device_id.clone(), rollout.clone()))
+            .collect();
+        rollouts.sort_by(|(a, _), (b, _)| a.cmp(b));
+        rollouts
+    }
+
+    /// Creates or replaces the rollout of a device ID.
+    pub fn set(&self, device_id: &str, rollout: Rollout) {
+        info!(
+            device_id = %device_id,
+            version = %rollout.version,
+            percentage = rollout.percentage,
+            "Updated rollout"
+        );
+        record_metrics(device_id, &rollout);
+        self.by_device.lock().insert(device_id.to_string(), rollout);
+    }
+
+    /// Removes the rollout of a device ID, returning it if it existed.
+    pub fn remove(&self, device_id: &str) -> Option<Rollout> {
+        let rollout = self.by_device.lock().remove(device_id)?;
+        info!(device_id = %device_id, version = %rollout.version, "Removed rollout");
+
+        // The removed version is now served to every device, don't roll it out again
+        let mut baselines = self.baselines.lock();
+        let baseline = baselines
+            .entry(device_id.to_string())
+            .or_insert_with(|| rollout.version.clone());
+        if *baseline < rollout.version {
+            *baseline = rollout.version.clone();
+        }
+
+        metrics::gauge!("firmware_rollout_percentage", "device_id" => device_id.to_string())
+            .set(100.0);
+        Some(rollout)
+    }
+
+    /// Applies `update` to the rollout of a device ID, returning the updated
+    /// rollout if it exists.
+    pub fn update(&self, device_id: &str, update: impl FnOnce(&mut Rollout)) -> Option<Rollout> {
+        let mut rollouts = self.by_device.lock();
+        let rollout = rollouts.get_mut(device_id)?;
+        update(rollout);
+        record_metrics(device_id, rollout);
+        Some(rollout.clone())
+    }
+
+    /// Records the versions found in the registry for a device ID, starting a
+    /// scheduled rollout when a new stable version appeared. Returns the
+    /// started rollout, if any.
+    pub fn observe<'a>(
+        &self,
+        device_id: &str,
+        versions: impl IntoIterator<Item = &'a Version>,
+        now: SystemTime,
+    ) -> Option<Rollout> {
+        let schedule = self.schedule(device_id)?;
+        let latest = versions.into_iter().filter(|v| v.pre.is_empty()).max()?;
+
+        let baseline = self.baselines.lock().get(device_id).cloned();
+        if baseline.is_some_and(|baseline| *latest <= baseline) {
+            return None;
+        }
+
+        let mut rollouts = self.by_device.lock();
+        if let Some(current) = rollouts.get(device_id) {
+            if current.version >= *latest || !current.is_complete() {
+                return None;
+            }
+        }
+
+        info!(
+            device_id = %device_id,
+            version = %latest,
+            "Starting scheduled rollout of new version"
+        );
+        let rollout = Rollout::scheduled(latest.clone(), schedule.clone(), now);
+        record_metrics(device_id, &rollout);
+        rollouts.insert(device_id.to_string(), rollout.clone());
+        Some(rollout)
+    }
+
+    /// Returns the rollout state of a device ID, to be persisted.
+    #[must_use]
+    pub fn record(&self, device_id: &str) -> RolloutRecord {
+        RolloutRecord {
+            model: device_id.to_string(),
+            rollout: self.get(device_id),
+            baseline: self.baselines.lock().get(device_id).cloned(),
+        }
+    }
+
+    /// Restores the rollout state persisted before a restart, advancing
+    /// scheduled rollouts to the step reached at `now`.
+    pub fn load(&self, records: Vec<RolloutRecord>, now: SystemTime) {
+        {
+            let mut rollouts = self.by_device.lock();
+            let mut baselines = self.baselines.lock();
+            for record in records {
+                if let Some(baseline) = record.baseline {
+                    baselines.insert(record.model.clone(), baseline);
+                }
+                if let Some(rollout) = record.rollout {
+                    rollouts.insert(record.model, rollout);
+                }
+            }
+        }
+        self.progress(now);
+    }
+
+    /// Advances every scheduled rollout to the step reached at `now`.
+    pub fn progress(&self, now: SystemTime) {
+        for (device_id, rollout) in self.by_device.lock().iter_mut() {
+            if rollout.progress(now) {
+                info!(
+                    device_id = %device_id,
+                    version = %rollout.version,
+                    percentage = rollout.percentage,
+                    "Rollout progressed"
+                );
+            }
+            record_metrics(device_id, rollout);
+        }
+    }
+}
+
+/// Records the current percentage and ramp step of a rollout.
+fn record_metrics(device_id: &str, rollout: &Rollout) {
+    metrics::gauge!("firmware_rollout_percentage", "device_id" => device_id.to_string())
+        .set(f64::from(rollout.percentage));
+
+    if let Some(progression) = &rollout.progression {
+        #[allow(clippy::cast_precision_loss)]
+        metrics::gauge!("firmware_rollout_step", "device_id" => device_id.to_string())
+            .set(progression.step as f64);
+    }
+}
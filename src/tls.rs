@@ -0,0 +1,227 @@
+use anyhow::{anyhow, Context, Result};
+use axum::{extract::ConnectInfo, Router};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+use tracing::{debug, warn};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+use crate::api::identity::ClientCertificate;
+
+/// Delay before accepting connections again after an accept error.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Time given to clients to complete the TLS handshake, so that stalled
+/// clients don't hold connections open.
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TLS termination of the main server.
+#[derive(Clone, Debug)]
+pub struct ServerTls {
+    /// PEM certificate chain presented by the server.
+    pub cert_path: PathBuf,
+    /// PEM private key of the server certificate.
+    pub key_path: PathBuf,
+    /// PEM certificates of the CA issuing device certificates. When set,
+    /// certificates presented by clients must be signed by it. Clients may
+    /// connect without one, and routes requiring a certificate check for the
+    /// [`ClientCertificate`] of the request.
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl ServerTls {
+    /// Builds the rustls server configuration, accepting HTTP/2 and HTTP/1.1.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a certificate or key can't be read or is invalid.
+    pub fn server_config(&self) -> Result<Arc<ServerConfig>> {
+        let certs = read_certificates(&self.cert_path)?;
+        let key = PrivateKeyDer::from_pem_file(&self.key_path).with_context(|| {
+            format!("Failed to read TLS private key {}", self.key_path.display())
+        })?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let mut config = match &self.client_ca_path {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certificates(path)? {
+                    roots.add(cert).with_context(|| {
+                        format!("Invalid client CA certificate {}", path.display())
+                    })?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .allow_unauthenticated()
+                        .build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        }
+        .with_single_cert(certs, key)
+        .context("Invalid TLS certificate or private key")?;
+
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .with_context(|| format!("Failed to read TLS certificates {}", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificate found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// Extracts the device identity of a DER-encoded client certificate: the
+/// common name of its subject, else its first DNS or URI subject alternative
+/// name, and the device IDs named by the organizational units of its subject.
+///
+/// # Errors
+///
+/// Returns an error if the certificate can't be parsed or names no device.
+pub fn client_certificate(der: &[u8]) -> Result<ClientCertificate> {
+    let (_, cert) =
+        X509Certificate::from_der(der).map_err(|e| anyhow!("Invalid client certificate: {e}"))?;
+
+    let common_name = cert
+        .subject()
+        .iter_common_name()
+        .find_map(|cn| cn.as_str().ok())
+        .map(str::to_string);
+
+    let alt_name = || {
+        let names = cert.subject_alternative_name().ok()??;
+        names
+            .value
+            .general_names
+            .iter()
+            .find_map(|name| match name {
+                GeneralName::DNSName(name) | GeneralName::URI(name) => Some((*name).to_string()),
+                _ => None,
+            })
+    };
+
+    let devices = cert
+        .subject()
+        .iter_organizational_unit()
+        .filter_map(|ou| ou.as_str().ok())
+        .map(str::trim)
+        .filter(|device| !device.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    common_name
+        .or_else(alt_name)
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .map(|common_name| ClientCertificate {
+            common_name,
+            devices,
+        })
+        .ok_or_else(|| anyhow!("Client certificate names no device"))
+}
+
+/// Serves the router over TLS until the token is cancelled, letting open
+/// connections finish their requests.
+///
+/// Each request carries the [`ConnectInfo`] of the peer, and the
+/// [`ClientCertificate`] of the connection when the client presented one.
+/// Connections are closed when the handshake doesn't complete within
+/// [`TLS_HANDSHAKE_TIMEOUT`].
+pub async fn serve(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    router: Router,
+    cancel_token: CancellationToken,
+) {
+    let acceptor = TlsAcceptor::from(config);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            () = cancel_token.cancelled() => return,
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Errors such as running out of file descriptors are
+                    // transient, back off before accepting again
+                    warn!(error = %e, "Failed to accept connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+        };
+
+        let acceptor = acceptor.clone();
+        let router = router.clone();
+        let cancel_token = cancel_token.clone();
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        debug!(peer = %peer, error = %e, "TLS handshake failed");
+                        return;
+                    }
+                    Err(_) => {
+                        debug!(peer = %peer, "TLS handshake timed out");
+                        return;
+                    }
+                };
+
+            let certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| match client_certificate(cert) {
+                    Ok(certificate) => Some(certificate),
+                    Err(e) => {
+                        warn!(peer = %peer, error = %e, "Ignoring client certificate");
+                        None
+                    }
+                });
+
+            let service =
+                hyper::service::service_fn(move |mut request: hyper::Request<Incoming>| {
+                    request.extensions_mut().insert(ConnectInfo(peer));
+                    if let Some(certificate) = &certificate {
+                        request.extensions_mut().insert(certificate.clone());
+                    }
+                    router.clone().oneshot(request)
+                });
+
+            let builder = Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            tokio::pin!(connection);
+
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                () = cancel_token.cancelled() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = result {
+                debug!(peer = %peer, error = %e, "Connection error");
+            }
+        });
+    }
+}
@@ -0,0 +1,104 @@
+use semver::Version;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+use tracing::warn;
+
+/// Manifest annotation holding the minimum version a device must run before
+/// installing a release, e.g. `2.0.0` for releases that can't be installed
+/// over 1.x.
+pub const MIN_VERSION_ANNOTATION: &str = "dev.otaflux.min-version";
+
+/// A mandatory release of a device model, e.g. `esp32-sensor=2.0.0`. Devices
+/// running an older version must install it before any newer release.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SteppingStone {
+    pub device: String,
+    pub version: Version,
+}
+
+impl FromStr for SteppingStone {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (device, version) = value.split_once('=').ok_or_else(|| {
+            format!("Invalid stepping stone '{value}': expected <repository>=<version>")
+        })?;
+        let version = version.trim();
+
+        Ok(Self {
+            device: device.trim().to_string(),
+            version: Version::parse(version.strip_prefix('v').unwrap_or(version))
+                .map_err(|e| format!("Invalid stepping stone version '{version}': {e}"))?,
+        })
+    }
+}
+
+/// Upgrade paths of every device model, from configured stepping stones and
+/// [`MIN_VERSION_ANNOTATION`] manifest annotations.
+#[derive(Default)]
+pub struct UpgradePaths {
+    stepping_stones: HashMap<String, BTreeSet<Version>>,
+}
+
+impl UpgradePaths {
+    #[must_use]
+    pub fn new(stepping_stones: Vec<SteppingStone>) -> Self {
+        let mut paths = Self::default();
+        for stone in stepping_stones {
+            paths
+                .stepping_stones
+                .entry(stone.device)
+                .or_default()
+                .insert(stone.version);
+        }
+        paths
+    }
+
+    /// Returns the minimum version a device must run before installing
+    /// `target`, if any: the latest stepping stone preceding it, or the
+    /// version required by its own annotations, whichever is higher.
+    ///
+    /// `annotated` holds the version required by the annotations of each
+    /// release, see [`min_version`]. A version required by any release is a
+    /// stepping stone for every later release, not only for the annotated
+    /// one.
+    #[must_use]
+    pub fn required_version(
+        &self,
+        device_id: &str,
+        target: &Version,
+        annotated: &BTreeMap<Version, Version>,
+    ) -> Option<Version> {
+        let stepping_stone = self
+            .stepping_stones
+            .get(device_id)
+            .and_then(|stones| stones.range(..target).next_back());
+        let annotated_stone = annotated.values().filter(|v| *v < target).max();
+
+        stepping_stone
+            .max(annotated_stone)
+            .max(annotated.get(target))
+            .cloned()
+    }
+}
+
+/// Returns the version required by the [`MIN_VERSION_ANNOTATION`] annotation
+/// of a release, if any.
+#[must_use]
+pub fn min_version(
+    device_id: &str,
+    release: &Version,
+    annotations: &BTreeMap<String, String>,
+) -> Option<Version> {
+    let value = annotations.get(MIN_VERSION_ANNOTATION)?.trim();
+    Version::parse(value.strip_prefix('v').unwrap_or(value))
+        .inspect_err(|e| {
+            warn!(
+                device_id = %device_id,
+                version = %release,
+                error = %e,
+                "Ignoring invalid {MIN_VERSION_ANNOTATION} annotation"
+            );
+        })
+        .ok()
+}
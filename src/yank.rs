@@ -0,0 +1,150 @@
+use parking_lot::Mutex;
+use semver::Version;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// A release withdrawn from a device model, by version or manifest digest.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum YankedRelease {
+    Version(Version),
+    /// Manifest digest, e.g. `sha256:...`, yanking a single build.
+    Digest(String),
+}
+
+impl FromStr for YankedRelease {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.contains(':') {
+            return Ok(Self::Digest(value.to_string()));
+        }
+
+        Version::parse(value.strip_prefix('v').unwrap_or(value))
+            .map(Self::Version)
+            .map_err(|e| format!("Invalid yanked release '{value}': {e}"))
+    }
+}
+
+impl fmt::Display for YankedRelease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Version(version) => write!(f, "{version}"),
+            Self::Digest(digest) => f.write_str(digest),
+        }
+    }
+}
+
+/// A yanked release of a device model, e.g. `esp32-sensor=1.2.0` or
+/// `esp32-sensor=sha256:...`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Yank {
+    pub device: String,
+    pub release: YankedRelease,
+}
+
+impl FromStr for Yank {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (device, release) = value.split_once('=').ok_or_else(|| {
+            format!("Invalid yank '{value}': expected <repository>=<version|digest>")
+        })?;
+
+        Ok(Self {
+            device: device.trim().to_string(),
+            release: release.parse()?,
+        })
+    }
+}
+
+/// Releases withdrawn per device ID, adjustable at runtime.
+#[derive(Default)]
+pub struct Yanks {
+    releases: Mutex<BTreeMap<String, BTreeSet<YankedRelease>>>,
+}
+
+impl Yanks {
+    #[must_use]
+    pub fn new(yanks: Vec<Yank>) -> Self {
+        let this = Self::default();
+        for yank in yanks {
+            this.add(&yank.device, yank.release);
+        }
+        this
+    }
+
+    /// Returns all yanked releases, sorted by device ID.
+    #[must_use]
+    pub fn list(&self) -> Vec<Yank> {
+        self.releases
+            .lock()
+            .iter()
+            .flat_map(|(device, releases)| {
+                releases.iter().map(|release| Yank {
+                    device: device.clone(),
+                    release: release.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Yanks a release of a device ID. Returns `false` if it was already yanked.
+    pub fn add(&self, device_id: &str, release: YankedRelease) -> bool {
+        self.releases
+            .lock()
+            .entry(device_id.to_string())
+            .or_default()
+            .insert(release)
+    }
+
+    /// Restores a yanked release of a device ID. Returns `false` if it wasn't
+    /// yanked.
+    pub fn remove(&self, device_id: &str, release: &YankedRelease) -> bool {
+        let mut releases = self.releases.lock();
+        let Some(device_releases) = releases.get_mut(device_id) else {
+            return false;
+        };
+        let removed = device_releases.remove(release);
+        if device_releases.is_empty() {
+            releases.remove(device_id);
+        }
+        removed
+    }
+
+    /// Returns `true` if the version is yanked for the device ID.
+    #[must_use]
+    pub fn is_version_yanked(&self, device_id: &str, version: &Version) -> bool {
+        self.releases
+            .lock()
+            .get(device_id)
+            .is_some_and(|releases| releases.contains(&YankedRelease::Version(version.clone())))
+    }
+
+    /// Returns `true` if the manifest digest is yanked for the device ID.
+    #[must_use]
+    pub fn is_digest_yanked(&self, device_id: &str, digest: &str) -> bool {
+        self.releases
+            .lock()
+            .get(device_id)
+            .is_some_and(|releases| releases.contains(&YankedRelease::Digest(digest.to_string())))
+    }
+
+    /// Returns `true` if manifest digests are yanked for the device ID, which
+    /// requires resolving digests when selecting a version.
+    #[must_use]
+    pub fn has_digests(&self, device_id: &str) -> bool {
+        self.releases.lock().get(device_id).is_some_and(|releases| {
+            releases
+                .iter()
+                .any(|release| matches!(release, YankedRelease::Digest(_)))
+        })
+    }
+
+    /// Returns `true` if a firmware version or build is yanked for the device ID.
+    #[must_use]
+    pub fn is_yanked(&self, device_id: &str, version: &Version, digest: &str) -> bool {
+        self.is_version_yanked(device_id, version) || self.is_digest_yanked(device_id, digest)
+    }
+}
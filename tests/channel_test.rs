@@ -0,0 +1,120 @@
+//! Release channel integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use otaflux::channel::Channel;
+use otaflux::firmware_manager::FirmwareManager;
+use semver::Version;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use common::{
+    body_to_string, create_app, init_tracing, MockRegistry, MockRegistryBuilder, TestFirmware,
+};
+
+async fn channel_registry(device_id: &str) -> MockRegistry {
+    MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.0.0", b"stable firmware"))
+        .await
+        .with_firmware(TestFirmware::new(
+            device_id,
+            "2.0.0-beta.1",
+            b"beta firmware",
+        ))
+        .await
+        .with_firmware(TestFirmware::new(
+            device_id,
+            "2.1.0-nightly.3",
+            b"nightly firmware",
+        ))
+        .await
+        .build()
+        .await
+}
+
+async fn latest_version(app: axum::Router, uri: &str) -> String {
+    let request = Request::builder()
+        .uri(uri)
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK, "{uri}");
+
+    let body = body_to_string(response.into_body()).await;
+    body.lines().next().expect("version line").to_string()
+}
+
+#[test]
+fn test_channel_includes_versions() {
+    let stable = Version::parse("1.2.0").expect("version");
+    let beta = Version::parse("1.3.0-beta.1").expect("version");
+    let rc = Version::parse("1.3.0-rc.2").expect("version");
+    let nightly = Version::parse("1.4.0-nightly.20240101").expect("version");
+
+    assert!(Channel::Stable.includes(&stable));
+    assert!(!Channel::Stable.includes(&beta));
+    assert!(Channel::Beta.includes(&stable));
+    assert!(Channel::Beta.includes(&beta));
+    assert!(Channel::Beta.includes(&rc));
+    assert!(!Channel::Beta.includes(&nightly));
+    assert!(Channel::Nightly.includes(&nightly));
+}
+
+#[tokio::test]
+async fn test_version_selects_latest_on_requested_channel() {
+    init_tracing();
+
+    let registry = channel_registry("device-channels").await;
+    let app = create_app(registry.firmware_manager());
+
+    let cases = [
+        ("/version?device=device-channels", "1.0.0"),
+        ("/version?device=device-channels&channel=stable", "1.0.0"),
+        (
+            "/version?device=device-channels&channel=beta",
+            "2.0.0-beta.1",
+        ),
+        (
+            "/version?device=device-channels&channel=nightly",
+            "2.1.0-nightly.3",
+        ),
+    ];
+
+    for (uri, expected) in cases {
+        assert_eq!(latest_version(app.clone(), uri).await, expected, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn test_version_uses_server_default_channel() {
+    init_tracing();
+
+    let registry = channel_registry("device-default-channel").await;
+    let fm = FirmwareManager::new(
+        registry.host_port(),
+        "user".to_string(),
+        "pass".to_string(),
+        true,
+        "",
+        None,
+    )
+    .expect("create firmware manager")
+    .with_default_channel(Channel::Beta);
+    let app = create_app(Arc::new(fm));
+
+    assert_eq!(
+        latest_version(app.clone(), "/version?device=device-default-channel").await,
+        "2.0.0-beta.1"
+    );
+    assert_eq!(
+        latest_version(app, "/version?device=device-default-channel&channel=stable").await,
+        "1.0.0"
+    );
+}
@@ -7,8 +7,9 @@
 #![allow(dead_code)]
 
 use axum::body::Body;
+use axum::http::Request;
 use http_body_util::BodyExt;
-use otaflux::api::router::api_router;
+use otaflux::api::router::{api_router, api_router_with_config, ApiConfig};
 use otaflux::firmware_manager::FirmwareManager;
 use otaflux::notifier::Notifier;
 use sha2::{Digest, Sha256};
@@ -26,21 +27,77 @@ pub struct TestFirmware {
     pub tag: String,
     pub bytes: Vec<u8>,
     pub digest: String,
+    /// Additional layers of a multi-component artifact.
+    pub components: Vec<TestComponent>,
+    /// Annotations of the artifact manifest.
+    pub annotations: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Represents an additional layer of a firmware artifact for testing.
+#[derive(Clone)]
+pub struct TestComponent {
+    pub title: String,
+    pub media_type: String,
+    pub bytes: Vec<u8>,
+    pub digest: String,
+}
+
+fn sha256_digest(bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    format!("sha256:{:x}", hasher.finalize())
 }
 
 impl TestFirmware {
     pub fn new(device_id: &str, tag: &str, bytes: &[u8]) -> Self {
-        let mut hasher = Sha256::new();
-        hasher.update(bytes);
-        let digest = format!("sha256:{:x}", hasher.finalize());
-
         Self {
             device_id: device_id.to_string(),
             tag: tag.to_string(),
             bytes: bytes.to_vec(),
-            digest,
+            digest: sha256_digest(bytes),
+            components: Vec::new(),
+            annotations: serde_json::Map::new(),
         }
     }
+
+    /// Adds an annotation to the artifact manifest.
+    pub fn with_annotation(mut self, key: &str, value: &str) -> Self {
+        self.annotations.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Adds a layer annotated with the given title to the artifact.
+    pub fn with_component(mut self, title: &str, media_type: &str, bytes: &[u8]) -> Self {
+        self.components.push(TestComponent {
+            title: title.to_string(),
+            media_type: media_type.to_string(),
+            bytes: bytes.to_vec(),
+            digest: sha256_digest(bytes),
+        });
+        self
+    }
+
+    /// Returns the manifest layers of the artifact.
+    fn layers(&self) -> Vec<serde_json::Value> {
+        let primary = serde_json::json!({
+            "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+            "digest": self.digest.clone(),
+            "size": self.bytes.len()
+        });
+
+        std::iter::once(primary)
+            .chain(self.components.iter().map(|c| {
+                serde_json::json!({
+                    "mediaType": c.media_type.clone(),
+                    "digest": c.digest.clone(),
+                    "size": c.bytes.len(),
+                    "annotations": {
+                        "org.opencontainers.image.title": c.title.clone()
+                    }
+                })
+            }))
+            .collect()
+    }
 }
 
 /// Builder for setting up a mock OCI registry with firmware artifacts.
@@ -69,7 +126,7 @@ impl MockRegistryBuilder {
     pub async fn with_firmware(mut self, firmware: TestFirmware) -> Self {
         let device_id = firmware.device_id.clone();
 
-        let manifest = serde_json::json!({
+        let mut manifest = serde_json::json!({
             "schemaVersion": 2,
             "mediaType": "application/vnd.oci.image.manifest.v1+json",
             "config": {
@@ -77,12 +134,11 @@ impl MockRegistryBuilder {
                 "digest": "sha256:configdigest",
                 "size": 100
             },
-            "layers": [{
-                "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
-                "digest": firmware.digest.clone(),
-                "size": firmware.bytes.len()
-            }]
+            "layers": firmware.layers()
         });
+        if !firmware.annotations.is_empty() {
+            manifest["annotations"] = serde_json::Value::Object(firmware.annotations.clone());
+        }
 
         let manifest_bytes = serde_json::to_vec(&manifest).expect("serialize manifest");
         let mut manifest_hasher = Sha256::new();
@@ -115,11 +171,121 @@ impl MockRegistryBuilder {
             .mount(&self.server)
             .await;
 
+        for component in &firmware.components {
+            Mock::given(method("GET"))
+                .and(path(format!(
+                    "/v2/{}/blobs/{}",
+                    device_id, component.digest
+                )))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Type", "application/octet-stream")
+                        .insert_header("Content-Length", component.bytes.len().to_string())
+                        .insert_header("Docker-Content-Digest", component.digest.clone())
+                        .set_body_bytes(component.bytes.clone()),
+                )
+                .mount(&self.server)
+                .await;
+        }
+
         self.devices.entry(device_id).or_default().push(firmware);
 
         self
     }
 
+    /// Adds a release published as an image index, with one image manifest per
+    /// hardware variant.
+    ///
+    /// Each variant is given as the extra fields of its index entry (e.g.
+    /// `platform` or `annotations`) and its firmware bytes.
+    pub async fn with_firmware_index(
+        mut self,
+        device_id: &str,
+        tag: &str,
+        variants: Vec<(serde_json::Value, Vec<u8>)>,
+    ) -> Self {
+        let mut entries = Vec::new();
+
+        for (entry_fields, bytes) in &variants {
+            let firmware = TestFirmware::new(device_id, tag, bytes);
+            let manifest = serde_json::json!({
+                "schemaVersion": 2,
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "config": {
+                    "mediaType": "application/vnd.oci.image.config.v1+json",
+                    "digest": "sha256:configdigest",
+                    "size": 100
+                },
+                "layers": firmware.layers()
+            });
+
+            let manifest_bytes = serde_json::to_vec(&manifest).expect("serialize manifest");
+            let manifest_digest = sha256_digest(&manifest_bytes);
+
+            Mock::given(method("GET"))
+                .and(path(format!("/v2/{device_id}/manifests/{manifest_digest}")))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+                        .insert_header("Docker-Content-Digest", manifest_digest.clone())
+                        .set_body_bytes(manifest_bytes.clone()),
+                )
+                .mount(&self.server)
+                .await;
+
+            Mock::given(method("GET"))
+                .and(path(format!("/v2/{device_id}/blobs/{}", firmware.digest)))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Type", "application/octet-stream")
+                        .insert_header("Content-Length", firmware.bytes.len().to_string())
+                        .insert_header("Docker-Content-Digest", firmware.digest.clone())
+                        .set_body_bytes(firmware.bytes.clone()),
+                )
+                .mount(&self.server)
+                .await;
+
+            let mut entry = serde_json::json!({
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": manifest_digest,
+                "size": manifest_bytes.len()
+            });
+            if let (Some(entry), Some(fields)) = (entry.as_object_mut(), entry_fields.as_object()) {
+                entry.extend(fields.clone());
+            }
+            entries.push(entry);
+        }
+
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": entries
+        });
+        let index_bytes = serde_json::to_vec(&index).expect("serialize index");
+
+        Mock::given(method("GET"))
+            .and(path(format!("/v2/{device_id}/manifests/{tag}")))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+                    .insert_header("Docker-Content-Digest", sha256_digest(&index_bytes))
+                    .set_body_bytes(index_bytes),
+            )
+            .mount(&self.server)
+            .await;
+
+        let bytes = variants
+            .first()
+            .map(|(_, b)| b.as_slice())
+            .unwrap_or_default();
+        self.devices
+            .entry(device_id.to_string())
+            .or_default()
+            .push(TestFirmware::new(device_id, tag, bytes));
+
+        self
+    }
+
     /// Adds a firmware artifact with a delayed blob response and fetch counter.
     ///
     /// Useful for testing concurrent request handling (e.g., thundering herd protection).
@@ -139,11 +305,7 @@ impl MockRegistryBuilder {
                 "digest": "sha256:configdigest",
                 "size": 100
             },
-            "layers": [{
-                "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
-                "digest": firmware.digest.clone(),
-                "size": firmware.bytes.len()
-            }]
+            "layers": firmware.layers()
         });
 
         let manifest_bytes = serde_json::to_vec(&manifest).expect("serialize manifest");
@@ -222,6 +384,18 @@ impl MockRegistry {
             .to_string()
     }
 
+    /// Returns the number of manifests of a device pulled from the registry.
+    pub async fn manifest_requests(&self, device_id: &str) -> usize {
+        let prefix = format!("/v2/{device_id}/manifests/");
+        self.server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .filter(|request| request.url.path().starts_with(&prefix))
+            .count()
+    }
+
     /// Creates a `FirmwareManager` configured to use this mock registry.
     pub fn firmware_manager(&self) -> Arc<FirmwareManager> {
         Arc::new(
@@ -243,6 +417,44 @@ pub fn create_app(fm: Arc<FirmwareManager>) -> axum::Router {
     api_router(fm, None)
 }
 
+/// Bearer token of the admin API of [`create_admin_app`].
+pub const ADMIN_TOKEN: &str = "admin-secret";
+
+/// Builds an unauthenticated `GET` request.
+pub fn get(uri: &str) -> Request<Body> {
+    Request::builder()
+        .uri(uri)
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request")
+}
+
+/// Builds a request authenticated with [`ADMIN_TOKEN`], with an optional JSON
+/// body.
+pub fn admin_request(method: &str, uri: &str, body: Option<serde_json::Value>) -> Request<Body> {
+    let builder = Request::builder()
+        .uri(uri)
+        .method(method)
+        .header("Authorization", format!("Bearer {ADMIN_TOKEN}"))
+        .header("Content-Type", "application/json");
+    let body = body.map_or_else(Body::empty, |b| {
+        Body::from(serde_json::to_vec(&b).expect("serialize"))
+    });
+    builder.body(body).expect("build request")
+}
+
+/// Creates a test app router with the admin API enabled.
+pub fn create_admin_app(fm: Arc<FirmwareManager>, admin_token: &str) -> axum::Router {
+    api_router_with_config(
+        fm,
+        None,
+        ApiConfig {
+            admin_token: Some(admin_token.to_string()),
+            ..ApiConfig::default()
+        },
+    )
+}
+
 /// Creates a test app router with MQTT notifier.
 pub fn create_app_with_mqtt(
     fm: Arc<FirmwareManager>,
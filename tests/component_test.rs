@@ -0,0 +1,154 @@
+//! Multi-component firmware artifact integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use tower::ServiceExt;
+
+use common::{
+    body_to_bytes, body_to_string, create_app, init_tracing, MockRegistryBuilder, TestFirmware,
+};
+
+const BOOTLOADER: &[u8] = b"bootloader image";
+const PARTITIONS: &[u8] = b"partition table";
+
+async fn multi_component_app(device_id: &str) -> axum::Router {
+    let firmware = TestFirmware::new(device_id, "1.0.0", b"application image")
+        .with_component(
+            "bootloader.bin",
+            "application/vnd.espressif.bootloader",
+            BOOTLOADER,
+        )
+        .with_component(
+            "partitions.bin",
+            "application/vnd.espressif.partition-table",
+            PARTITIONS,
+        );
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    create_app(registry.firmware_manager())
+}
+
+#[tokio::test]
+async fn test_firmware_component_selection() {
+    init_tracing();
+
+    let app = multi_component_app("device-components").await;
+
+    // Checksums are only known once the artifact is fully fetched, not while
+    // the first download streams it from the registry
+    let request = Request::builder()
+        .uri("/version?device=device-components")
+        .body(Body::empty())
+        .expect("build request");
+    let response = app.clone().oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let cases: [(&str, &[u8]); 4] = [
+        ("/firmware?device=device-components", b"application image"),
+        (
+            "/firmware?device=device-components&component=bootloader",
+            BOOTLOADER,
+        ),
+        (
+            "/firmware?device=device-components&component=bootloader.bin",
+            BOOTLOADER,
+        ),
+        (
+            "/firmware?device=device-components&component=application%2Fvnd.espressif.partition-table",
+            PARTITIONS,
+        ),
+    ];
+
+    for (uri, expected) in cases {
+        let request = Request::builder()
+            .uri(uri)
+            .method("GET")
+            .body(Body::empty())
+            .expect("build request");
+
+        let response = app.clone().oneshot(request).await.expect("send request");
+
+        assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        assert_eq!(
+            response
+                .headers()
+                .get("x-firmware-crc32")
+                .and_then(|v| v.to_str().ok()),
+            Some(crc32fast::hash(expected).to_string().as_str()),
+            "{uri}"
+        );
+        assert_eq!(body_to_bytes(response.into_body()).await, expected, "{uri}");
+    }
+
+    let request = Request::builder()
+        .uri("/firmware?device=device-components&component=spiffs")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_version_lists_components() {
+    init_tracing();
+
+    let app = multi_component_app("device-components-version").await;
+
+    let request = Request::builder()
+        .uri("/version?device=device-components-version&format=json")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.clone().oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    let components = body["components"].as_array().expect("components");
+    assert_eq!(components.len(), 3);
+    assert_eq!(components[1]["name"], "bootloader.bin");
+    assert_eq!(
+        components[1]["media_type"],
+        "application/vnd.espressif.bootloader"
+    );
+    assert_eq!(components[1]["crc32"], crc32fast::hash(BOOTLOADER));
+    assert_eq!(components[1]["size"], BOOTLOADER.len());
+    assert_eq!(
+        components[1]["url"],
+        "/firmware?device=device-components-version&component=bootloader.bin"
+    );
+
+    let request = Request::builder()
+        .uri("/version?device=device-components-version")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    let body = body_to_string(response.into_body()).await;
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], "1.0.0");
+    assert_eq!(
+        lines[3],
+        format!(
+            "bootloader.bin {} {}",
+            crc32fast::hash(BOOTLOADER),
+            BOOTLOADER.len()
+        )
+    );
+}
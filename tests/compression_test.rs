@@ -0,0 +1,264 @@
+//! Compressed firmware delivery integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use flate2::read::GzDecoder;
+use otaflux::compression::ContentEncoding;
+use otaflux::firmware_manager::FirmwareQuery;
+use std::io::Read;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tower::ServiceExt;
+
+use common::{body_to_bytes, create_app, init_tracing, MockRegistryBuilder, TestFirmware};
+
+fn header(response: &axum::response::Response, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Decodes a heatshrink stream, as done by the heatshrink library decoder.
+fn heatshrink_decode(data: &[u8], window_bits: u8, lookahead_bits: u8) -> Vec<u8> {
+    let mut bits = data
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1));
+    let mut read = |count: u8| -> Option<usize> {
+        (0..count).try_fold(0, |value, _| Some((value << 1) | usize::from(bits.next()?)))
+    };
+
+    let mut output: Vec<u8> = Vec::new();
+    while let Some(tag) = read(1) {
+        if tag == 1 {
+            let Some(byte) = read(8) else { break };
+            output.push(u8::try_from(byte).expect("literal byte"));
+        } else {
+            let (Some(index), Some(count)) = (read(window_bits), read(lookahead_bits)) else {
+                break;
+            };
+            for _ in 0..=count {
+                output.push(output[output.len() - index - 1]);
+            }
+        }
+    }
+    output
+}
+
+#[tokio::test]
+async fn test_firmware_gzip_negotiated_from_accept_encoding() {
+    init_tracing();
+
+    let content = b"compressible firmware ".repeat(64);
+    let firmware = TestFirmware::new("device-gzip", "1.0.0", &content);
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/firmware?device=device-gzip")
+        .method("GET")
+        .header("Accept-Encoding", "br;q=1.0, gzip;q=0.8")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        header(&response, "content-encoding").as_deref(),
+        Some("gzip")
+    );
+    assert_eq!(
+        header(&response, "x-firmware-size"),
+        Some(content.len().to_string())
+    );
+    assert_eq!(
+        header(&response, "x-firmware-crc32"),
+        Some(crc32fast::hash(&content).to_string())
+    );
+    let compressed_crc = header(&response, "x-compressed-crc32").expect("compressed CRC");
+    let compressed_size = header(&response, "x-compressed-size").expect("compressed size");
+
+    let body = body_to_bytes(response.into_body()).await;
+    assert_eq!(compressed_size, body.len().to_string());
+    assert_eq!(compressed_crc, crc32fast::hash(&body).to_string());
+    assert!(body.len() < content.len(), "Firmware should be compressed");
+
+    let mut decompressed = Vec::new();
+    GzDecoder::new(body.as_slice())
+        .read_to_end(&mut decompressed)
+        .expect("decompress gzip");
+    assert_eq!(decompressed, content);
+}
+
+#[tokio::test]
+async fn test_firmware_zstd_selected_by_query_parameter() {
+    init_tracing();
+
+    let content = b"zstd firmware payload ".repeat(64);
+    let firmware = TestFirmware::new("device-zstd", "1.0.0", &content);
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/firmware?device=device-zstd&encoding=zstd")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        header(&response, "content-encoding").as_deref(),
+        Some("zstd")
+    );
+    assert!(
+        header(&response, "etag").is_some_and(|etag| etag.ends_with("+zstd\"")),
+        "Compressed firmware should have its own ETag"
+    );
+
+    let body = body_to_bytes(response.into_body()).await;
+    let decompressed = zstd::decode_all(body.as_slice()).expect("decompress zstd");
+    assert_eq!(decompressed, content);
+}
+
+#[tokio::test]
+async fn test_firmware_heatshrink_negotiated_from_accept_encoding() {
+    init_tracing();
+
+    let content = b"heatshrink firmware payload ".repeat(64);
+    let firmware = TestFirmware::new("device-heatshrink", "1.0.0", &content);
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/firmware?device=device-heatshrink")
+        .method("GET")
+        .header("Accept-Encoding", "heatshrink")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        header(&response, "content-encoding").as_deref(),
+        Some("heatshrink")
+    );
+    let window_bits: u8 = header(&response, "x-heatshrink-window")
+        .expect("window header")
+        .parse()
+        .expect("window bits");
+    let lookahead_bits: u8 = header(&response, "x-heatshrink-lookahead")
+        .expect("lookahead header")
+        .parse()
+        .expect("lookahead bits");
+
+    let body = body_to_bytes(response.into_body()).await;
+    assert!(body.len() < content.len(), "Firmware should be compressed");
+    assert_eq!(
+        heatshrink_decode(&body, window_bits, lookahead_bits),
+        content
+    );
+}
+
+#[test]
+fn test_negotiate_prefers_higher_ratio_on_ties() {
+    assert_eq!(
+        ContentEncoding::negotiate("heatshrink, gzip"),
+        ContentEncoding::Gzip
+    );
+    assert_eq!(
+        ContentEncoding::negotiate("heatshrink, zstd;q=0.5"),
+        ContentEncoding::Heatshrink
+    );
+    assert_eq!(
+        ContentEncoding::negotiate("br, *"),
+        ContentEncoding::Identity
+    );
+}
+
+#[test]
+fn test_heatshrink_round_trip() {
+    let mut random = Vec::new();
+    let mut state = 1u64;
+    for _ in 0..4096 {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        random.push(state.to_be_bytes()[0] >> 4);
+    }
+
+    for data in [Vec::new(), vec![0x42], vec![0; 1000], random] {
+        let compressed = ContentEncoding::Heatshrink
+            .compress(&data)
+            .expect("compress heatshrink");
+        assert_eq!(heatshrink_decode(&compressed, 8, 4), data);
+    }
+}
+
+#[tokio::test]
+async fn test_concurrent_requests_compress_once() {
+    init_tracing();
+
+    let content = b"concurrently compressed firmware ".repeat(1024);
+    let firmware = TestFirmware::new("device-compress-herd", "1.0.0", &content);
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let fm = registry.firmware_manager();
+    let fw = fm
+        .get_firmware(&FirmwareQuery::new("device-compress-herd"))
+        .await
+        .expect("get firmware");
+
+    let mut join_set = JoinSet::new();
+    for _ in 0..10 {
+        let fm = Arc::clone(&fm);
+        let fw = Arc::clone(&fw);
+        join_set.spawn(async move {
+            fm.get_compressed(&fw.components[0], ContentEncoding::Zstd)
+                .await
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(result) = join_set.join_next().await {
+        results.push(result.expect("join task").expect("compress firmware"));
+    }
+
+    assert!(
+        results
+            .windows(2)
+            .all(|pair| Arc::ptr_eq(&pair[0], &pair[1])),
+        "Concurrent requests should share a single compression"
+    );
+}
@@ -0,0 +1,189 @@
+//! Firmware delta endpoint integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use otaflux::firmware_manager::FirmwareQuery;
+use semver::Version;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tower::ServiceExt;
+
+use common::{body_to_bytes, create_app, init_tracing, MockRegistryBuilder, TestFirmware};
+
+#[tokio::test]
+async fn test_delta_endpoint_returns_applicable_patch() {
+    init_tracing();
+
+    let old = TestFirmware::new(
+        "device-delta",
+        "1.0.0",
+        b"firmware image v1.0.0 with a mostly unchanged payload",
+    );
+    let new = TestFirmware::new(
+        "device-delta",
+        "1.1.0",
+        b"firmware image v1.1.0 with a mostly unchanged payload and a fix",
+    );
+
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(old.clone())
+        .await
+        .with_firmware(new.clone())
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/firmware/delta?device=device-delta&current=1.0.0")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    assert_eq!(header("x-target-version").as_deref(), Some("1.1.0"));
+    assert_eq!(
+        header("x-target-crc32"),
+        Some(crc32fast::hash(&new.bytes).to_string())
+    );
+    assert_eq!(header("x-target-size"), Some(new.bytes.len().to_string()));
+    let target_sha256 = header("x-target-sha256").expect("SHA-256 header");
+
+    let patch = body_to_bytes(response.into_body()).await;
+
+    let mut reconstructed = Vec::new();
+    bsdiff::patch(&old.bytes, &mut patch.as_slice(), &mut reconstructed).expect("apply patch");
+
+    assert_eq!(reconstructed, new.bytes, "Patched firmware should match");
+    assert_eq!(
+        format!("{:x}", Sha256::digest(&reconstructed)),
+        target_sha256,
+        "SHA-256 of the reconstructed firmware should match"
+    );
+}
+
+#[tokio::test]
+async fn test_delta_endpoint_up_to_date_device() {
+    init_tracing();
+
+    let firmware = TestFirmware::new("device-delta-latest", "2.0.0", b"latest firmware");
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/firmware/delta?device=device-delta-latest")
+        .method("GET")
+        .header("X-Firmware-Version", "2.0.0")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_delta_endpoint_requires_current_version() {
+    init_tracing();
+
+    let registry = MockRegistryBuilder::new().await.build().await;
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/firmware/delta?device=device-delta")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_concurrent_delta_requests_generate_once() {
+    init_tracing();
+
+    let old = TestFirmware::new(
+        "device-delta-herd",
+        "1.0.0",
+        b"firmware image v1.0.0 with a mostly unchanged payload",
+    );
+    let new = TestFirmware::new(
+        "device-delta-herd",
+        "1.1.0",
+        b"firmware image v1.1.0 with a mostly unchanged payload and a fix",
+    );
+
+    // The source firmware is only fetched to generate the delta
+    let source_fetch_count = Arc::new(AtomicUsize::new(0));
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(new)
+        .await
+        .with_firmware_delayed(
+            old,
+            Duration::from_millis(50),
+            Arc::clone(&source_fetch_count),
+        )
+        .await
+        .build()
+        .await;
+
+    let fm = registry.firmware_manager();
+    let from = Version::new(1, 0, 0);
+    let mut join_set = JoinSet::new();
+
+    for _ in 0..10 {
+        let fm = Arc::clone(&fm);
+        let from = from.clone();
+        join_set.spawn(async move {
+            fm.get_delta(&FirmwareQuery::new("device-delta-herd"), &from)
+                .await
+        });
+    }
+
+    let mut patches = Vec::new();
+    while let Some(result) = join_set.join_next().await {
+        let delta = result
+            .expect("join task")
+            .expect("get delta")
+            .expect("delta applies");
+        patches.push(delta.patch.clone());
+    }
+
+    assert_eq!(
+        source_fetch_count.load(Ordering::SeqCst),
+        1,
+        "Concurrent requests should generate the delta once"
+    );
+    assert!(
+        patches.windows(2).all(|pair| pair[0] == pair[1]),
+        "Every request should get the same patch"
+    );
+}
@@ -0,0 +1,354 @@
+//! Device authentication integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use otaflux::api::auth::API_KEY_HEADER;
+use otaflux::api::identity::ClientCertificate;
+use otaflux::api::router::{api_router_with_config, ApiConfig};
+use otaflux::credential::{hash_token, load_credentials, Credential, Credentials};
+use otaflux::rollback::RollbackTarget;
+use semver::Version;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use common::{body_to_string, init_tracing, MockRegistryBuilder, TestFirmware};
+
+const SENSOR_TOKEN: &str = "sensor-token";
+const SN_0042_TOKEN: &str = "sn-0042-token";
+
+fn credentials() -> Arc<Credentials> {
+    let mut credentials = BTreeMap::new();
+    credentials.insert(
+        "sensors".to_string(),
+        Credential::new(SENSOR_TOKEN, vec!["sensor-*".to_string()], None),
+    );
+    credentials.insert(
+        "sn-0042".to_string(),
+        Credential::new(
+            SN_0042_TOKEN,
+            vec!["sensor-auth".to_string()],
+            Some("SN-0042".to_string()),
+        ),
+    );
+    Arc::new(Credentials::new(credentials))
+}
+
+async fn authenticated_app() -> axum::Router {
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new("sensor-auth", "1.0.0", b"firmware 1.0.0"))
+        .await
+        .with_firmware(TestFirmware::new("sensor-auth", "1.1.0", b"firmware 1.1.0"))
+        .await
+        .build()
+        .await;
+
+    let manager = registry.firmware_manager();
+    manager.set_rollback(RollbackTarget {
+        device: "sensor-auth".to_string(),
+        serial: Some("SN-0042".to_string()),
+        version: Version::new(1, 0, 0),
+    });
+
+    api_router_with_config(
+        manager,
+        None,
+        ApiConfig {
+            device_credentials: Some(credentials()),
+            certificate_devices: vec!["sensor-*".to_string()],
+            ..ApiConfig::default()
+        },
+    )
+}
+
+#[tokio::test]
+async fn test_device_endpoints_require_credential() {
+    init_tracing();
+
+    let app = authenticated_app().await;
+
+    for request in [
+        Request::builder()
+            .uri("/version?device=sensor-auth")
+            .body(Body::empty()),
+        Request::builder()
+            .uri("/firmware?device=sensor-auth")
+            .header(header::AUTHORIZATION, "Bearer unknown-token")
+            .body(Body::empty()),
+        Request::builder()
+            .uri("/version?device=sensor-auth")
+            .header(API_KEY_HEADER, "")
+            .body(Body::empty()),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(request.expect("build request"))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+            "Bearer"
+        );
+    }
+
+    // Health is open to probes
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/health")
+                .body(Body::empty())
+                .expect("build request"),
+        )
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_credential_grants_its_devices() {
+    init_tracing();
+
+    let app = authenticated_app().await;
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/version?device=sensor-auth")
+                .header(header::AUTHORIZATION, format!("Bearer {SENSOR_TOKEN}"))
+                .body(Body::empty())
+                .expect("build request"),
+        )
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_to_string(response.into_body()).await;
+    assert_eq!(body.lines().next(), Some("1.1.0"));
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/firmware?device=sensor-auth")
+                .header(API_KEY_HEADER, SENSOR_TOKEN)
+                .body(Body::empty())
+                .expect("build request"),
+        )
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Devices outside of the credential are forbidden
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/version?device=gateway")
+                .header(header::AUTHORIZATION, format!("Bearer {SENSOR_TOKEN}"))
+                .body(Body::empty())
+                .expect("build request"),
+        )
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Queries that fail to parse are rejected rather than left unchecked
+    for uri in [
+        "/version?device=sensor-auth&device=gateway",
+        "/firmware?device=gateway&channel=unknown",
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .header(header::AUTHORIZATION, format!("Bearer {SENSOR_TOKEN}"))
+                    .body(Body::empty())
+                    .expect("build request"),
+            )
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+    }
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/report")
+                .method("POST")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, format!("Bearer {SN_0042_TOKEN}"))
+                .body(Body::from(
+                    serde_json::json!({
+                        "device": "gateway",
+                        "to_version": "1.1.0",
+                        "status": "booted",
+                    })
+                    .to_string(),
+                ))
+                .expect("build request"),
+        )
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_credential_serial_overrides_claimed_serial() {
+    init_tracing();
+
+    let app = authenticated_app().await;
+
+    // The rollback target of SN-0042 applies to its credential, whatever
+    // serial the request claims
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/version?device=sensor-auth&serial=SN-0001")
+                .header(header::AUTHORIZATION, format!("Bearer {SN_0042_TOKEN}"))
+                .body(Body::empty())
+                .expect("build request"),
+        )
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_to_string(response.into_body()).await;
+    assert_eq!(body.lines().next(), Some("1.0.0"));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/version?device=sensor-auth&serial=SN-0042")
+                .header(header::AUTHORIZATION, format!("Bearer {SENSOR_TOKEN}"))
+                .body(Body::empty())
+                .expect("build request"),
+        )
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_to_string(response.into_body()).await;
+    assert_eq!(body.lines().next(), Some("1.0.0"));
+}
+
+#[tokio::test]
+async fn test_certificate_grants_its_devices() {
+    init_tracing();
+
+    let app = authenticated_app().await;
+    let request = |devices: &[&str]| {
+        Request::builder()
+            .uri("/version?device=sensor-auth")
+            .extension(ClientCertificate {
+                common_name: "SN-0042".to_string(),
+                devices: devices.iter().map(ToString::to_string).collect(),
+            })
+            .body(Body::empty())
+            .expect("build request")
+    };
+
+    // Certificates naming no device ID fall back to the configured ones
+    for (devices, status) in [
+        (&[][..], StatusCode::OK),
+        (&["sensor-auth"][..], StatusCode::OK),
+        (&["gateway"][..], StatusCode::FORBIDDEN),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(request(devices))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), status, "{devices:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_credential_serial_must_match_certificate() {
+    init_tracing();
+
+    let app = authenticated_app().await;
+    let request = |common_name: &str, token: &str| {
+        Request::builder()
+            .uri("/version?device=sensor-auth")
+            .header(API_KEY_HEADER, token)
+            .extension(ClientCertificate {
+                common_name: common_name.to_string(),
+                devices: Vec::new(),
+            })
+            .body(Body::empty())
+            .expect("build request")
+    };
+
+    // Credentials bound to another serial are rejected, fleet tokens aren't
+    for (common_name, token, status) in [
+        ("SN-0042", SN_0042_TOKEN, StatusCode::OK),
+        ("SN-0001", SN_0042_TOKEN, StatusCode::FORBIDDEN),
+        ("SN-0001", SENSOR_TOKEN, StatusCode::OK),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(request(common_name, token))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), status, "{common_name} with {token}");
+    }
+}
+
+#[test]
+fn test_credentials_authenticate_by_token_hash() {
+    let credentials = credentials();
+
+    let (id, credential) = credentials
+        .authenticate(SN_0042_TOKEN)
+        .expect("known token");
+    assert_eq!(id, "sn-0042");
+    assert_eq!(credential.token_sha256, hash_token(SN_0042_TOKEN));
+    assert!(credential.allows("sensor-auth"));
+    assert!(!credential.allows("sensor-other"));
+
+    assert!(credentials.authenticate("unknown-token").is_none());
+
+    assert!(credentials.remove("sn-0042").is_some());
+    assert!(credentials.authenticate(SN_0042_TOKEN).is_none());
+}
+
+#[test]
+fn test_load_credentials_file() {
+    let path =
+        std::env::temp_dir().join(format!("otaflux-credentials-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        format!(
+            r#"{{ "sensors": {{ "token_sha256": "{}", "devices": ["sensor-*"] }} }}"#,
+            hash_token(SENSOR_TOKEN).to_uppercase()
+        ),
+    )
+    .expect("write credentials file");
+
+    let credentials = load_credentials(&path).expect("load credentials file");
+    std::fs::remove_file(&path).expect("remove credentials file");
+
+    let credential = &credentials["sensors"];
+    assert_eq!(credential.token_sha256, hash_token(SENSOR_TOKEN));
+    assert_eq!(credential.serial, None);
+
+    for invalid in [
+        r#"{ "token_sha256": "abc", "devices": ["sensor-*"] }"#,
+        &format!(
+            r#"{{ "token_sha256": "{}", "devices": [] }}"#,
+            hash_token("t")
+        ),
+        &format!(
+            r#"{{ "token_sha256": "{}", "devices": ["*"], "token": "t" }}"#,
+            hash_token("t")
+        ),
+    ] {
+        assert!(serde_json::from_str::<Credential>(invalid).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! Device enrollment integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use otaflux::api::identity::ClientCertificate;
+use otaflux::api::router::{api_router_with_config, ApiConfig};
+use otaflux::credential::{Credential, Credentials};
+use otaflux::enrollment::{CertificateAuthority, Enrollment};
+use otaflux::inventory::{DeviceStore, EnrollmentRecord, SqliteStore};
+use otaflux::tls::client_certificate;
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+use rustls::pki_types::{pem::PemObject, CertificateDer};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tower::ServiceExt;
+
+use common::{
+    admin_request, body_to_string, init_tracing, MockRegistryBuilder, TestFirmware, ADMIN_TOKEN,
+};
+
+const DEVICE_ID: &str = "sensor-enroll";
+const OPERATOR_TOKEN: &str = "operator-token";
+
+fn authority() -> CertificateAuthority {
+    let key = KeyPair::generate().expect("generate key");
+    let mut params = CertificateParams::new(Vec::<String>::new()).expect("CA params");
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "OtaFlux Enrollment CA");
+    let ca = params.self_signed(&key).expect("sign CA");
+
+    let dir = std::env::temp_dir();
+    let cert_path = dir.join(format!("otaflux-enrollment-ca-{}.pem", std::process::id()));
+    let key_path = dir.join(format!("otaflux-enrollment-ca-{}.key", std::process::id()));
+    std::fs::write(&cert_path, ca.pem()).expect("write CA certificate");
+    std::fs::write(&key_path, key.serialize_pem()).expect("write CA key");
+    let authority = CertificateAuthority::load(&cert_path, &key_path).expect("load CA");
+    for path in [cert_path, key_path] {
+        std::fs::remove_file(path).expect("remove PEM file");
+    }
+    authority
+}
+
+async fn enrollment_app_with_credentials(credentials: Option<Arc<Credentials>>) -> axum::Router {
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(DEVICE_ID, "1.0.0", b"firmware 1.0.0"))
+        .await
+        .build()
+        .await;
+
+    api_router_with_config(
+        registry.firmware_manager(),
+        None,
+        ApiConfig {
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            device_credentials: credentials,
+            enrollment: Some(Arc::new(Enrollment::new(Some(authority())))),
+            certificate_devices: vec!["sensor-*".to_string()],
+            ..ApiConfig::default()
+        },
+    )
+}
+
+async fn enrollment_app() -> axum::Router {
+    enrollment_app_with_credentials(None).await
+}
+
+async fn send(app: &axum::Router, request: Request<Body>) -> (StatusCode, String) {
+    let response = app.clone().oneshot(request).await.expect("send request");
+    let status = response.status();
+    (status, body_to_string(response.into_body()).await)
+}
+
+async fn create_code(app: &axum::Router, serial: &str) -> String {
+    let (status, body) = send(
+        app,
+        admin_request(
+            "PUT",
+            &format!("/admin/enrollment-codes/{serial}"),
+            Some(json!({ "devices": ["sensor-*"], "ttl": "1h" })),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{body}");
+    let body: Value = serde_json::from_str(&body).expect("parse code");
+    body["code"].as_str().expect("code").to_string()
+}
+
+fn version_request(token: &str) -> Request<Body> {
+    Request::builder()
+        .uri(format!("/version?device={DEVICE_ID}"))
+        .header(header::AUTHORIZATION, format!("Bearer {token}"))
+        .body(Body::empty())
+        .expect("build request")
+}
+
+#[tokio::test]
+async fn test_enrollment_code_issues_device_token() {
+    init_tracing();
+    let app = enrollment_app().await;
+
+    let code = create_code(&app, "SN-0042").await;
+    let (_, body) = send(&app, admin_request("GET", "/admin/enrollment-codes", None)).await;
+    let codes: Value = serde_json::from_str(&body).expect("parse codes");
+    assert_eq!(codes[0]["serial"], "SN-0042");
+    assert!(codes[0].get("code").is_none());
+
+    // Codes are bound to the device IDs they allow
+    let (status, _) = send(
+        &app,
+        admin_request(
+            "POST",
+            "/enroll",
+            Some(json!({ "device": "gateway", "serial": "SN-0042", "code": code })),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let enroll = json!({ "device": DEVICE_ID, "serial": "SN-0042", "code": code });
+    let (status, body) = send(&app, admin_request("POST", "/enroll", Some(enroll.clone()))).await;
+    assert_eq!(status, StatusCode::CREATED, "{body}");
+    let enrolled: Value = serde_json::from_str(&body).expect("parse enrollment");
+    assert_eq!(enrolled["serial"], "SN-0042");
+    let token = enrolled["token"].as_str().expect("token").to_string();
+
+    // Codes can only be redeemed once
+    let (status, _) = send(&app, admin_request("POST", "/enroll", Some(enroll.clone()))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = send(&app, version_request(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.lines().next(), Some("1.0.0"));
+
+    let (_, body) = send(&app, admin_request("GET", "/admin/credentials", None)).await;
+    let credentials: Value = serde_json::from_str(&body).expect("parse credentials");
+    assert_eq!(
+        credentials,
+        json!([{
+            "id": "SN-0042",
+            "devices": [DEVICE_ID],
+            "serial": "SN-0042",
+            "enrolled": true,
+        }])
+    );
+
+    // Revoked devices lose their token
+    let (status, _) = send(
+        &app,
+        admin_request("DELETE", "/admin/credentials/SN-0042", None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, version_request(&token)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(
+        &app,
+        admin_request("DELETE", "/admin/credentials/SN-0001", None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_factory_certificate_enrolls_with_csr() {
+    init_tracing();
+    let app = enrollment_app().await;
+
+    let key = KeyPair::generate().expect("generate key");
+    let csr = CertificateParams::new(Vec::<String>::new())
+        .expect("CSR params")
+        .serialize_request(&key)
+        .expect("serialize CSR")
+        .pem()
+        .expect("encode CSR");
+
+    let enroll_as = |serial: &str, devices: &[&str]| {
+        let mut request = admin_request(
+            "POST",
+            "/enroll",
+            Some(json!({ "device": DEVICE_ID, "csr": csr })),
+        );
+        request.extensions_mut().insert(ClientCertificate {
+            common_name: serial.to_string(),
+            devices: devices.iter().map(ToString::to_string).collect(),
+        });
+        request
+    };
+    let enroll = |serial: &str| enroll_as(serial, &[]);
+
+    // Factory certificates name the device IDs they may enroll as, else fall
+    // back to the configured ones
+    let (status, _) = send(&app, enroll_as("SN-0042", &["gateway"])).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = send(&app, enroll_as("SN-0043", &[DEVICE_ID])).await;
+    assert_eq!(status, StatusCode::CREATED, "{body}");
+
+    let (status, body) = send(&app, enroll("SN-0042")).await;
+    assert_eq!(status, StatusCode::CREATED, "{body}");
+    let enrolled: Value = serde_json::from_str(&body).expect("parse enrollment");
+    assert!(enrolled.get("token").is_none());
+    assert!(enrolled["ca_certificate"].is_string());
+
+    // The issued certificate names the serial of the factory certificate and
+    // the device ID it enrolled as
+    let pem = enrolled["certificate"].as_str().expect("certificate");
+    let der = CertificateDer::from_pem_slice(pem.as_bytes()).expect("parse PEM");
+    let certificate = client_certificate(&der).expect("parse certificate");
+    assert_eq!(certificate.common_name, "SN-0042");
+    assert_eq!(certificate.devices, vec![DEVICE_ID.to_string()]);
+
+    // Revoked serials can't enroll again with their certificate
+    let (status, _) = send(
+        &app,
+        admin_request("DELETE", "/admin/credentials/SN-0042", None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, enroll("SN-0042")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (_, body) = send(&app, admin_request("GET", "/admin/enrollments", None)).await;
+    let enrollments: Value = serde_json::from_str(&body).expect("parse enrollments");
+    assert_eq!(enrollments[0]["credential"], "certificate");
+    assert!(enrollments[0]["revoked_at"].is_u64());
+
+    let (status, _) = send(
+        &app,
+        admin_request("POST", "/enroll", Some(json!({ "device": DEVICE_ID }))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_enrollment_cant_replace_configured_credentials() {
+    init_tracing();
+
+    let mut configured = BTreeMap::new();
+    configured.insert(
+        "SN-0042".to_string(),
+        Credential::new(OPERATOR_TOKEN, vec![DEVICE_ID.to_string()], None),
+    );
+    let app = enrollment_app_with_credentials(Some(Arc::new(Credentials::new(configured)))).await;
+
+    let code = create_code(&app, "SN-0042").await;
+    let enroll = json!({ "device": DEVICE_ID, "serial": "SN-0042", "code": code });
+    let (status, body) = send(&app, admin_request("POST", "/enroll", Some(enroll))).await;
+    assert_eq!(status, StatusCode::CREATED, "{body}");
+    let enrolled: Value = serde_json::from_str(&body).expect("parse enrollment");
+    let token = enrolled["token"].as_str().expect("token").to_string();
+
+    // Both credentials authenticate, though they share an ID
+    for token in [OPERATOR_TOKEN, token.as_str()] {
+        let (status, _) = send(&app, version_request(token)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (_, body) = send(&app, admin_request("GET", "/admin/credentials", None)).await;
+    let credentials: Value = serde_json::from_str(&body).expect("parse credentials");
+    assert_eq!(credentials[0]["enrolled"], false);
+    assert_eq!(credentials[1]["enrolled"], true);
+}
+
+#[test]
+fn test_sqlite_store_tracks_enrollments() {
+    let store = SqliteStore::open_in_memory().expect("open database");
+    let enrolled_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+    store
+        .enroll(EnrollmentRecord {
+            serial: "SN-0042".to_string(),
+            model: DEVICE_ID.to_string(),
+            token_sha256: Some("9f86d081".to_string()),
+            certificate_serial: None,
+            enrolled_at,
+            revoked_at: None,
+        })
+        .expect("enroll device");
+
+    assert!(store
+        .revoke("SN-0042", enrolled_at + Duration::from_mins(1))
+        .expect("revoke device"));
+    assert!(!store
+        .revoke("SN-0001", enrolled_at)
+        .expect("revoke unknown device"));
+
+    let enrollments = store.enrollments().expect("list enrollments");
+    assert_eq!(enrollments.len(), 1);
+    assert_eq!(enrollments[0].token_sha256.as_deref(), Some("9f86d081"));
+    assert_eq!(
+        enrollments[0].revoked_at,
+        Some(enrolled_at + Duration::from_mins(1))
+    );
+}
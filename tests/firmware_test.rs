@@ -6,7 +6,10 @@ use axum::{
     body::Body,
     http::{Request, StatusCode},
 };
+use http_body_util::BodyExt;
 use otaflux::api::router::api_router;
+use otaflux::firmware_manager::FirmwareQuery;
+use sha2::{Digest, Sha256};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
@@ -140,8 +143,8 @@ async fn test_thundering_herd_protection() {
 
     for _ in 0..num_concurrent_requests {
         let fm_clone = Arc::clone(&fm);
-        let device_id = firmware.device_id.clone();
-        join_set.spawn(async move { fm_clone.get_firmware(&device_id).await });
+        let query = FirmwareQuery::new(firmware.device_id.clone());
+        join_set.spawn(async move { fm_clone.get_firmware(&query).await });
     }
 
     let mut results = Vec::new();
@@ -169,3 +172,302 @@ async fn test_thundering_herd_protection() {
         "Blob endpoint should be called exactly once, but was called {actual_fetches} times"
     );
 }
+
+#[tokio::test]
+async fn test_firmware_range_request_returns_partial_content() {
+    init_tracing();
+
+    let firmware = TestFirmware::new("device-range", "1.0.0", b"0123456789abcdef");
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/firmware?device=device-range")
+        .method("GET")
+        .header("Range", "bytes=4-9")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        response
+            .headers()
+            .get("content-range")
+            .map(|v| v.to_str().unwrap_or("")),
+        Some("bytes 4-9/16"),
+        "Content-Range should describe the served slice"
+    );
+    assert_eq!(
+        response
+            .headers()
+            .get("accept-ranges")
+            .map(|v| v.to_str().unwrap_or("")),
+        Some("bytes")
+    );
+
+    let body = body_to_bytes(response.into_body()).await;
+    assert_eq!(body, b"456789", "Only the requested bytes should be served");
+}
+
+#[tokio::test]
+async fn test_firmware_if_range_mismatch_returns_full_body() {
+    init_tracing();
+
+    let firmware = TestFirmware::new("device-if-range", "1.0.0", b"0123456789abcdef");
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware.clone())
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    // The ETag from a previous build must not allow resuming the new one
+    let request = Request::builder()
+        .uri("/firmware?device=device-if-range")
+        .method("GET")
+        .header("Range", "bytes=4-")
+        .header("If-Range", "\"sha256:previousbuild\"")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("content-range").is_none());
+
+    let body = body_to_bytes(response.into_body()).await;
+    assert_eq!(body, firmware.bytes, "Full firmware should be served");
+}
+
+#[tokio::test]
+async fn test_firmware_unsatisfiable_range() {
+    init_tracing();
+
+    let firmware = TestFirmware::new("device-416", "1.0.0", b"short");
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/firmware?device=device-416")
+        .method("GET")
+        .header("Range", "bytes=100-200")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        response
+            .headers()
+            .get("content-range")
+            .map(|v| v.to_str().unwrap_or("")),
+        Some("bytes */5")
+    );
+}
+
+#[tokio::test]
+async fn test_firmware_head_request() {
+    init_tracing();
+
+    let firmware = TestFirmware::new("device-head", "1.0.0", b"head request firmware");
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware.clone())
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/firmware?device=device-head")
+        .method("HEAD")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response
+            .headers()
+            .get("content-length")
+            .map(|v| v.to_str().unwrap_or("")),
+        Some(firmware.bytes.len().to_string().as_str()),
+        "HEAD should advertise the firmware size"
+    );
+    assert!(response.headers().contains_key("etag"));
+
+    let body = body_to_bytes(response.into_body()).await;
+    assert!(body.is_empty(), "HEAD response must not carry a body");
+}
+
+#[tokio::test]
+async fn test_firmware_if_none_match_returns_not_modified() {
+    init_tracing();
+
+    let firmware = TestFirmware::new("device-fw-etag", "1.0.0", b"etag firmware binary");
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/firmware?device=device-fw-etag")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.clone().oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response
+        .headers()
+        .get("etag")
+        .expect("ETag header should be present")
+        .clone();
+
+    let request = Request::builder()
+        .uri("/firmware?device=device-fw-etag")
+        .method("GET")
+        .header("If-None-Match", etag)
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    let body = body_to_bytes(response.into_body()).await;
+    assert!(body.is_empty(), "304 response must not carry a body");
+}
+
+/// Cold `/firmware` requests stream from a single registry download.
+#[tokio::test]
+async fn test_concurrent_streamed_downloads_share_registry_fetch() {
+    init_tracing();
+
+    let blob_fetch_count = Arc::new(AtomicUsize::new(0));
+    let content = b"streamed firmware chunk ".repeat(1024);
+    let firmware = TestFirmware::new("device-stream", "1.0.0", &content);
+
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware_delayed(
+            firmware,
+            Duration::from_millis(50),
+            Arc::clone(&blob_fetch_count),
+        )
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = || {
+        Request::builder()
+            .uri("/firmware?device=device-stream")
+            .method("GET")
+            .body(Body::empty())
+            .expect("build request")
+    };
+
+    let (response1, response2) = tokio::join!(
+        app.clone().oneshot(request()),
+        app.clone().oneshot(request())
+    );
+    let response1 = response1.expect("send request");
+    let response2 = response2.expect("send request");
+
+    assert_eq!(response1.status(), StatusCode::OK);
+    assert_eq!(response2.status(), StatusCode::OK);
+    assert_eq!(
+        response1
+            .headers()
+            .get("content-length")
+            .map(|v| v.to_str().unwrap_or("")),
+        Some(content.len().to_string().as_str())
+    );
+
+    let (body1, body2) = tokio::join!(
+        body_to_bytes(response1.into_body()),
+        body_to_bytes(response2.into_body())
+    );
+    assert_eq!(
+        body1, content,
+        "First requester should get the full firmware"
+    );
+    assert_eq!(
+        body2, content,
+        "Attached requester should get the full firmware"
+    );
+
+    assert_eq!(
+        blob_fetch_count.load(Ordering::SeqCst),
+        1,
+        "Blob endpoint should be called exactly once"
+    );
+}
+
+/// Blobs not matching the digest of their layer are rejected, and never fully
+/// streamed to devices.
+#[tokio::test]
+async fn test_firmware_blob_digest_mismatch_rejected() {
+    init_tracing();
+
+    let mut firmware = TestFirmware::new("device-tampered", "1.0.0", b"tampered firmware");
+    // The manifest references the digest of other bytes than the blob served
+    firmware.digest = format!("sha256:{:x}", Sha256::digest(b"genuine firmware"));
+
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let fm = registry.firmware_manager();
+    let error = fm
+        .get_firmware(&FirmwareQuery::new("device-tampered"))
+        .await
+        .expect_err("Tampered firmware should be rejected");
+    assert!(
+        format!("{error:#}").to_lowercase().contains("digest"),
+        "Unexpected error: {error:#}"
+    );
+
+    let response = create_app(fm)
+        .oneshot(
+            Request::builder()
+                .uri("/firmware?device=device-tampered")
+                .method("GET")
+                .body(Body::empty())
+                .expect("build request"),
+        )
+        .await
+        .expect("send request");
+
+    // Streaming may start before the blob is verified, but is then aborted
+    let served =
+        response.status() == StatusCode::OK && response.into_body().collect().await.is_ok();
+    assert!(!served, "Tampered firmware should never be fully served");
+}
@@ -0,0 +1,383 @@
+//! Automatic release halt integration tests.
+
+mod common;
+
+use axum::http::{header, HeaderValue, StatusCode};
+use otaflux::api::auth::API_KEY_HEADER;
+use otaflux::api::router::{api_router_with_config, ApiConfig};
+use otaflux::credential::{Credential, Credentials};
+use otaflux::firmware_manager::{FirmwareManager, FirmwareQuery};
+use otaflux::halt::{
+    FailurePolicy, Halt, Halts, ReleaseHalted, RepositoryPolicy, DEFAULT_MIN_DEVICES,
+};
+use otaflux::inventory::{DeviceStore, SqliteStore, UpdateReport, UpdateStatus};
+use semver::Version;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tower::ServiceExt;
+
+use common::{
+    admin_request, body_to_string, create_app, init_tracing, MockRegistryBuilder, TestFirmware,
+    ADMIN_TOKEN,
+};
+
+fn outcome(serial: usize, status: UpdateStatus, at: SystemTime) -> UpdateReport {
+    UpdateReport {
+        serial: format!("SN-{serial}"),
+        model: "sensor".to_string(),
+        from_version: Some(Version::new(1, 5, 0)),
+        to_version: Version::new(1, 6, 0),
+        status,
+        error_code: None,
+        reported_at: at,
+    }
+}
+
+#[test]
+fn test_failure_policy_parsing() {
+    let policy: RepositoryPolicy = "esp32-sensor=5%@1h".parse().expect("parse");
+    assert_eq!(policy.repository, "esp32-sensor");
+    assert_eq!(
+        policy.policy,
+        FailurePolicy {
+            threshold: 5.0,
+            window: Duration::from_hours(1),
+            min_devices: DEFAULT_MIN_DEVICES,
+        }
+    );
+
+    let policy: FailurePolicy = "2.5%@30m/50".parse().expect("parse");
+    assert_eq!(policy.min_devices, 50);
+
+    for invalid in [
+        "5%@1h",
+        "sensor=5@1h",
+        "sensor=150%@1h",
+        "sensor=5%@1w",
+        "sensor=5%@1h/many",
+    ] {
+        assert!(invalid.parse::<RepositoryPolicy>().is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn test_release_halts_above_failure_rate() {
+    let halts = Halts::new(vec!["sensor=5%@1h".parse().expect("parse")]);
+    let now = SystemTime::now();
+
+    // Failures aren't considered until enough devices installed the release
+    let few = Halts::new(vec!["sensor=5%@1h".parse().expect("parse")]);
+    assert!(few
+        .record(&outcome(0, UpdateStatus::Failed, now), now)
+        .is_none());
+
+    for serial in 1..20 {
+        assert!(halts
+            .record(&outcome(serial, UpdateStatus::Booted, now), now)
+            .is_none());
+    }
+    assert!(halts
+        .record(&outcome(0, UpdateStatus::Failed, now), now)
+        .is_none());
+
+    // 1 out of 20 devices failed, exactly the threshold
+    assert!(halts
+        .record(&outcome(1, UpdateStatus::Installed, now), now)
+        .is_none());
+
+    let halt = halts
+        .record(&outcome(20, UpdateStatus::Failed, now), now)
+        .expect("halt");
+    assert_eq!(halt.version, Version::new(1, 6, 0));
+    assert_eq!((halt.failed, halt.devices), (2, 21));
+
+    // Other repositories have no policy
+    let report = UpdateReport {
+        model: "gateway".to_string(),
+        ..outcome(0, UpdateStatus::Failed, now)
+    };
+    assert!(halts.record(&report, now).is_none());
+}
+
+#[test]
+fn test_failures_expire_with_policy_window() {
+    let halts = Halts::new(vec!["*=10%@1h/2".parse().expect("parse")]);
+    let start = SystemTime::now();
+    let later = start + Duration::from_hours(2);
+
+    assert!(halts
+        .record(&outcome(0, UpdateStatus::Failed, start), start)
+        .is_none());
+    for serial in 1..20 {
+        assert!(halts
+            .record(&outcome(serial, UpdateStatus::Booted, later), later)
+            .is_none());
+    }
+}
+
+#[test]
+fn test_halts_restored_from_device_store() {
+    let store = Arc::new(SqliteStore::open_in_memory().expect("open database"));
+    let halt = Halt {
+        version: Version::new(1, 6, 0),
+        previous: Some(Version::new(1, 5, 0)),
+        devices: 20,
+        failed: 3,
+        halted_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+    };
+    store
+        .save_halt("sensor", Some(halt.clone()))
+        .expect("save halt");
+    store
+        .save_halt("gateway", Some(halt.clone()))
+        .expect("save halt");
+    store.save_halt("gateway", None).expect("lift halt");
+    assert_eq!(
+        store.halts().expect("list halts"),
+        vec![("sensor".to_string(), halt.clone())]
+    );
+
+    let fm = FirmwareManager::new(
+        "localhost:5000".to_string(),
+        "user".to_string(),
+        "pass".to_string(),
+        true,
+        "",
+        None,
+    )
+    .expect("create firmware manager")
+    .with_device_store(store)
+    .expect("restore halts");
+    assert_eq!(fm.halts(), vec![("sensor".to_string(), halt)]);
+}
+
+#[tokio::test]
+#[allow(clippy::too_many_lines)]
+async fn test_halted_release_holds_model_until_resumed() {
+    init_tracing();
+
+    let device_id = "device-halt";
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.5.0", b"firmware 1.5.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.6.0", b"firmware 1.6.0"))
+        .await
+        .build()
+        .await;
+    let fm = FirmwareManager::new(
+        registry.host_port(),
+        "user".to_string(),
+        "pass".to_string(),
+        true,
+        "",
+        None,
+    )
+    .expect("create firmware manager")
+    .with_failure_policies(vec![format!("{device_id}=25%@1h/2")
+        .parse()
+        .expect("parse")]);
+
+    // Devices authenticate with a fleet token, or a token bound to their serial
+    let mut credentials = BTreeMap::new();
+    credentials.insert(
+        "fleet".to_string(),
+        Credential::new("fleet-token", vec![device_id.to_string()], None),
+    );
+    for serial in ["SN-1", "SN-2"] {
+        credentials.insert(
+            serial.to_string(),
+            Credential::new(
+                &format!("{serial}-token"),
+                vec![device_id.to_string()],
+                Some(serial.to_string()),
+            ),
+        );
+    }
+    let app = api_router_with_config(
+        Arc::new(fm),
+        None,
+        ApiConfig {
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            device_credentials: Some(Arc::new(Credentials::new(credentials))),
+            ..ApiConfig::default()
+        },
+    );
+
+    let send_as = |token: &str, method: &str, uri: &str, body: Option<serde_json::Value>| {
+        let mut request = admin_request(method, uri, body);
+        request.headers_mut().remove(header::AUTHORIZATION);
+        request.headers_mut().insert(
+            API_KEY_HEADER,
+            HeaderValue::from_str(token).expect("header value"),
+        );
+        app.clone().oneshot(request)
+    };
+    let send = |method: &str, uri: &str, body: Option<serde_json::Value>| {
+        send_as("fleet-token", method, uri, body)
+    };
+    let admin = |method: &str, uri: &str| app.clone().oneshot(admin_request(method, uri, None));
+    let served_version = |response: axum::response::Response| async move {
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_to_string(response.into_body()).await;
+        body.lines().next().expect("version line").to_string()
+    };
+
+    let report = |token: &str, serial: &str, status: &str| {
+        let report = serde_json::json!({
+            "device": device_id,
+            "serial": serial,
+            "from_version": "1.5.0",
+            "to_version": "1.6.0",
+            "status": status,
+        });
+        let response = send_as(token, "POST", "/report", Some(report));
+        async move {
+            let response = response.await.expect("send request");
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        }
+    };
+
+    let uri = format!("/version?device={device_id}&current=1.5.0");
+    let response = send("GET", &uri, None).await.expect("send request");
+    assert_eq!(served_version(response).await, "1.6.0");
+
+    // Devices that didn't authenticate their serial don't count, even when
+    // they checked in under it
+    for serial in ["SN-8", "SN-9"] {
+        let check_in = format!("{uri}&serial={serial}");
+        let response = send("GET", &check_in, None).await.expect("send request");
+        assert_eq!(served_version(response).await, "1.6.0");
+        report("fleet-token", serial, "failed").await;
+    }
+    let response = send("GET", &uri, None).await.expect("send request");
+    assert_eq!(served_version(response).await, "1.6.0");
+
+    report("SN-1-token", "SN-1", "booted").await;
+    report("SN-2-token", "SN-2", "failed").await;
+
+    // The model is held on the previous release
+    let response = send("GET", &uri, None).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = send("GET", &format!("/version?device={device_id}"), None)
+        .await
+        .expect("send request");
+    assert_eq!(served_version(response).await, "1.5.0");
+
+    let response = admin("GET", "/admin/halts").await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let halts: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    assert_eq!(halts[0]["device"], device_id);
+    assert_eq!(halts[0]["version"], "1.6.0");
+    assert_eq!(halts[0]["previous"], "1.5.0");
+    assert_eq!(halts[0]["failure_rate"], 50.0);
+
+    let resume = format!("/admin/halts/{device_id}/resume");
+    let response = admin("POST", &resume).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = admin("POST", &resume).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = send("GET", &uri, None).await.expect("send request");
+    assert_eq!(served_version(response).await, "1.6.0");
+}
+
+#[tokio::test]
+async fn test_halts_stack_and_hold_each_channel_on_its_preceding_release() {
+    init_tracing();
+
+    let device_id = "device-halts";
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(
+            device_id,
+            "0.9.0-beta.1",
+            b"firmware 0.9.0-beta.1",
+        ))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.0.0", b"firmware 1.0.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.1.0", b"firmware 1.1.0"))
+        .await
+        .build()
+        .await;
+    let fm = Arc::new(
+        FirmwareManager::new(
+            registry.host_port(),
+            "user".to_string(),
+            "pass".to_string(),
+            true,
+            "",
+            None,
+        )
+        .expect("create firmware manager")
+        .with_failure_policies(vec![format!("{device_id}=0%@1h/1").parse().expect("parse")]),
+    );
+    let app = create_app(Arc::clone(&fm));
+
+    let failed = |version: Version| UpdateReport {
+        serial: "SN-1".to_string(),
+        model: device_id.to_string(),
+        from_version: None,
+        to_version: version,
+        status: UpdateStatus::Failed,
+        error_code: None,
+        reported_at: SystemTime::now(),
+    };
+    let served = |uri: String| {
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(common::get(&uri)).await.expect("send request");
+            match response.status() {
+                StatusCode::NO_CONTENT => None,
+                status => {
+                    assert_eq!(status, StatusCode::OK);
+                    let body = body_to_string(response.into_body()).await;
+                    Some(body.lines().next().expect("version line").to_string())
+                }
+            }
+        }
+    };
+
+    let halt = fm
+        .record_outcome(&failed(Version::new(1, 1, 0)))
+        .await
+        .expect("halt");
+    assert_eq!(halt.previous, Some(Version::new(1, 0, 0)));
+
+    // A second release is halted alongside the first one
+    let halt = fm
+        .record_outcome(&failed(Version::new(1, 0, 0)))
+        .await
+        .expect("halt");
+    assert_eq!(halt.previous, None);
+    let halted: Vec<_> = fm.halts().into_iter().map(|(_, h)| h.version).collect();
+    assert_eq!(halted, [Version::new(1, 0, 0), Version::new(1, 1, 0)]);
+
+    // No stable release precedes the halts: nothing is offered
+    assert_eq!(served(format!("/version?device={device_id}")).await, None);
+    assert_eq!(
+        served(format!("/version?device={device_id}&current=1.0.0")).await,
+        None
+    );
+    let error = fm
+        .get_firmware(&FirmwareQuery::new(device_id))
+        .await
+        .expect_err("no release");
+    assert!(error.is::<ReleaseHalted>());
+
+    // Beta devices are held on the release preceding the halts on their channel
+    assert_eq!(
+        served(format!("/version?device={device_id}&channel=beta")).await,
+        Some("0.9.0-beta.1".to_string())
+    );
+
+    let resumed = fm.resume_release(device_id).await;
+    assert_eq!(resumed.len(), 2);
+    assert_eq!(
+        served(format!("/version?device={device_id}")).await,
+        Some("1.1.0".to_string())
+    );
+}
@@ -0,0 +1,163 @@
+//! Device identity integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use otaflux::api::identity::{
+    client_ip, device_serial, ClientCertificate, TrustedProxy, DEVICE_SERIAL_HEADER,
+};
+use otaflux::rollback::RollbackTarget;
+use semver::Version;
+use std::net::{IpAddr, SocketAddr};
+use tower::ServiceExt;
+
+use common::{body_to_string, create_app, init_tracing, MockRegistryBuilder, TestFirmware};
+
+async fn served_version(app: &axum::Router, request: Request<Body>) -> String {
+    let response = app.clone().oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = body_to_string(response.into_body()).await;
+    body.lines().next().expect("version line").to_string()
+}
+
+#[test]
+fn test_device_serial_resolution_order() {
+    let mut headers = axum::http::HeaderMap::new();
+    assert_eq!(device_serial(None, &headers, None), None);
+
+    headers.insert(DEVICE_SERIAL_HEADER, "SN-HEADER".parse().expect("header"));
+    assert_eq!(
+        device_serial(None, &headers, None).as_deref(),
+        Some("SN-HEADER")
+    );
+    assert_eq!(
+        device_serial(Some("SN-QUERY"), &headers, None).as_deref(),
+        Some("SN-QUERY")
+    );
+
+    let certificate = ClientCertificate {
+        common_name: "SN-CERT".to_string(),
+        devices: Vec::new(),
+    };
+    assert_eq!(
+        device_serial(Some("SN-QUERY"), &headers, Some(&certificate)).as_deref(),
+        Some("SN-CERT")
+    );
+
+    // Blank serials identify no device
+    assert_eq!(
+        device_serial(Some(" "), &axum::http::HeaderMap::new(), None),
+        None
+    );
+}
+
+#[test]
+fn test_client_ip_trusts_forwarded_for_from_trusted_proxies() {
+    let trusted: Vec<TrustedProxy> = ["10.0.0.0/8", "2001:db8::1"]
+        .iter()
+        .map(|proxy| proxy.parse().expect("trusted proxy"))
+        .collect();
+    let ip = |ip: &str| ip.parse::<IpAddr>().expect("IP address");
+    let peer = |peer: &str| Some(SocketAddr::new(ip(peer), 40000));
+
+    let mut headers = axum::http::HeaderMap::new();
+    headers.insert(
+        "X-Forwarded-For",
+        "203.0.113.1, 198.51.100.7, 10.0.0.1"
+            .parse()
+            .expect("header"),
+    );
+
+    // Hops are walked back until the first address that isn't a proxy
+    assert_eq!(
+        client_ip(&headers, peer("10.0.0.2"), &trusted),
+        Some(ip("198.51.100.7"))
+    );
+    assert_eq!(
+        client_ip(&headers, peer("::ffff:10.0.0.2"), &trusted),
+        Some(ip("198.51.100.7"))
+    );
+
+    // Clients connecting directly can't forge their address
+    assert_eq!(
+        client_ip(&headers, peer("192.0.2.9"), &trusted),
+        Some(ip("192.0.2.9"))
+    );
+    assert_eq!(
+        client_ip(&headers, peer("10.0.0.2"), &[]),
+        Some(ip("10.0.0.2"))
+    );
+    assert_eq!(client_ip(&headers, None, &trusted), None);
+
+    assert_eq!(
+        "10.0.0.1".parse::<TrustedProxy>(),
+        "10.0.0.1/32".parse::<TrustedProxy>()
+    );
+    for invalid in ["10.0.0.0/33", "2001:db8::/129", "proxy", "10.0.0.0/eight"] {
+        assert!(invalid.parse::<TrustedProxy>().is_err(), "{invalid}");
+    }
+}
+
+#[tokio::test]
+async fn test_serial_identifies_device_within_model() {
+    init_tracing();
+
+    let device_id = "device-identity";
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.0.0", b"firmware 1.0.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.1.0", b"firmware 1.1.0"))
+        .await
+        .build()
+        .await;
+
+    let manager = registry.firmware_manager();
+    manager.set_rollback(RollbackTarget {
+        device: device_id.to_string(),
+        serial: Some("SN-0042".to_string()),
+        version: Version::new(1, 0, 0),
+    });
+    let app = create_app(manager);
+
+    let uri = format!("/version?device={device_id}");
+    let request = |uri: &str| Request::builder().uri(uri).method("GET");
+
+    // Other units of the model are unaffected
+    let anonymous = request(&uri).body(Body::empty()).expect("build request");
+    assert_eq!(served_version(&app, anonymous).await, "1.1.0");
+
+    let by_query = request(&format!("{uri}&serial=SN-0042"))
+        .body(Body::empty())
+        .expect("build request");
+    assert_eq!(served_version(&app, by_query).await, "1.0.0");
+
+    let by_header = request(&uri)
+        .header("X-Device-Serial", "SN-0042")
+        .body(Body::empty())
+        .expect("build request");
+    assert_eq!(served_version(&app, by_header).await, "1.0.0");
+
+    // The client certificate can't be overridden by the claimed serial
+    let by_certificate = request(&format!("{uri}&serial=SN-0042"))
+        .extension(ClientCertificate {
+            common_name: "SN-0001".to_string(),
+            devices: Vec::new(),
+        })
+        .body(Body::empty())
+        .expect("build request");
+    assert_eq!(served_version(&app, by_certificate).await, "1.1.0");
+
+    let by_certificate = request(&uri)
+        .extension(ClientCertificate {
+            common_name: "SN-0042".to_string(),
+            devices: Vec::new(),
+        })
+        .body(Body::empty())
+        .expect("build request");
+    assert_eq!(served_version(&app, by_certificate).await, "1.0.0");
+}
@@ -0,0 +1,264 @@
+//! Device check-in tracking and inventory integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{HeaderValue, Request, StatusCode},
+};
+use otaflux::api::router::{api_router_with_config, ApiConfig};
+use otaflux::inventory::{
+    DeviceFilter, DeviceRecord, DeviceStore, MemoryStore, SqliteStore, UpdateReport, UpdateStatus,
+};
+use semver::Version;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tower::ServiceExt;
+
+use common::{
+    admin_request, body_to_string, get, init_tracing, MockRegistryBuilder, TestFirmware,
+    ADMIN_TOKEN,
+};
+
+fn check_in(serial: &str, model: &str, reported: Option<&str>) -> DeviceRecord {
+    DeviceRecord {
+        serial: serial.to_string(),
+        model: model.to_string(),
+        last_seen: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        reported_version: reported.map(|v| Version::parse(v).expect("version")),
+        served_version: Some(Version::new(1, 1, 0)),
+        ip: Some("192.0.2.10".parse().expect("ip")),
+        user_agent: Some("esp-idf/5.2".to_string()),
+        last_report: None,
+    }
+}
+
+fn assert_store_tracks_devices(store: &dyn DeviceStore) {
+    store
+        .check_in(check_in("SN-2", "sensor", Some("1.0.0")))
+        .expect("check in");
+    store
+        .check_in(check_in("SN-1", "sensor", Some("1.1.0")))
+        .expect("check in");
+    store
+        .check_in(check_in("GW-1", "gateway", Some("1.0.0")))
+        .expect("check in");
+
+    // Check-ins without a reported version keep the last known one
+    let later = DeviceRecord {
+        last_seen: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_060),
+        ..check_in("SN-2", "sensor", None)
+    };
+    store.check_in(later.clone()).expect("check in");
+    assert_eq!(
+        store.get("SN-2").expect("get"),
+        Some(DeviceRecord {
+            reported_version: Some(Version::new(1, 0, 0)),
+            ..later
+        })
+    );
+    assert_eq!(store.get("SN-404").expect("get"), None);
+
+    let serials = |filter: DeviceFilter| -> Vec<String> {
+        store
+            .list(&filter)
+            .expect("list")
+            .into_iter()
+            .map(|d| d.serial)
+            .collect()
+    };
+    assert_eq!(serials(DeviceFilter::default()), ["GW-1", "SN-1", "SN-2"]);
+    assert_eq!(
+        serials(DeviceFilter {
+            model: Some("sensor".to_string()),
+            version: None,
+        }),
+        ["SN-1", "SN-2"]
+    );
+    assert_eq!(
+        serials(DeviceFilter {
+            model: Some("sensor".to_string()),
+            version: Some(Version::new(1, 0, 0)),
+        }),
+        ["SN-2"]
+    );
+}
+
+#[test]
+fn test_memory_store_tracks_devices() {
+    assert_store_tracks_devices(&MemoryStore::new());
+}
+
+#[test]
+fn test_sqlite_store_tracks_devices() {
+    assert_store_tracks_devices(&SqliteStore::open_in_memory().expect("open database"));
+}
+
+#[test]
+fn test_memory_store_forgets_least_recently_seen_devices() {
+    let store = MemoryStore::with_max_devices(NonZeroUsize::new(2).expect("non-zero"));
+    for serial in ["SN-1", "SN-2", "SN-1", "SN-3"] {
+        store
+            .check_in(check_in(serial, "sensor", Some("1.0.0")))
+            .expect("check in");
+    }
+
+    assert_eq!(store.get("SN-2").expect("get"), None);
+    let serials: Vec<String> = store
+        .list(&DeviceFilter::default())
+        .expect("list")
+        .into_iter()
+        .map(|d| d.serial)
+        .collect();
+    assert_eq!(serials, ["SN-1", "SN-3"]);
+}
+
+#[test]
+fn test_sqlite_store_coalesces_polling_check_ins() {
+    let store = SqliteStore::open_in_memory().expect("open database");
+    let at = |secs: u64| DeviceRecord {
+        last_seen: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs),
+        ..check_in("SN-1", "sensor", Some("1.0.0"))
+    };
+    let last_seen =
+        |store: &SqliteStore| store.get("SN-1").expect("get").expect("device").last_seen;
+
+    // Polls within a minute only moving the last seen time aren't written
+    store.check_in(at(0)).expect("check in");
+    store.check_in(at(30)).expect("check in");
+    assert_eq!(last_seen(&store), at(0).last_seen);
+    store.check_in(at(60)).expect("check in");
+    assert_eq!(last_seen(&store), at(60).last_seen);
+
+    // Any other change is written right away
+    let upgraded = DeviceRecord {
+        reported_version: Some(Version::new(1, 1, 0)),
+        ..at(70)
+    };
+    store.check_in(upgraded.clone()).expect("check in");
+    assert_eq!(store.get("SN-1").expect("get"), Some(upgraded));
+
+    // So is the check-in following a report
+    store
+        .report(UpdateReport {
+            serial: "SN-1".to_string(),
+            model: "sensor".to_string(),
+            from_version: Some(Version::new(1, 1, 0)),
+            to_version: Version::new(1, 2, 0),
+            status: UpdateStatus::Downloaded,
+            error_code: None,
+            reported_at: at(75).last_seen,
+        })
+        .expect("report");
+    store
+        .check_in(DeviceRecord {
+            reported_version: Some(Version::new(1, 1, 0)),
+            ..at(80)
+        })
+        .expect("check in");
+    assert_eq!(last_seen(&store), at(80).last_seen);
+}
+
+/// Builds a device request, sent through two trusted proxies.
+fn device_request(uri: &str) -> Request<Body> {
+    let mut request = get(uri);
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 2], 40000))));
+    let headers = request.headers_mut();
+    headers.insert("User-Agent", HeaderValue::from_static("esp-idf/5.2"));
+    headers.insert(
+        "X-Forwarded-For",
+        HeaderValue::from_static("198.51.100.7, 10.0.0.1"),
+    );
+    request
+}
+
+#[tokio::test]
+async fn test_version_check_ins_are_listed_in_inventory() {
+    init_tracing();
+
+    let device_id = "device-inventory";
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.1.0", b"firmware 1.1.0"))
+        .await
+        .build()
+        .await;
+
+    let app = api_router_with_config(
+        registry.firmware_manager(),
+        None,
+        ApiConfig {
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            device_store: Some(Arc::new(
+                SqliteStore::open_in_memory().expect("open database"),
+            )),
+            trusted_proxies: vec!["10.0.0.0/8".parse().expect("trusted proxy")],
+            ..ApiConfig::default()
+        },
+    );
+
+    for uri in [
+        format!("/version?device={device_id}&serial=SN-1&current=1.0.0"),
+        format!("/version?device={device_id}&serial=SN-2&current=1.1.0"),
+        // Devices without serial aren't tracked
+        format!("/version?device={device_id}&current=1.0.0"),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(device_request(&uri))
+            .await
+            .expect("send request");
+        assert!(response.status().is_success(), "{uri}");
+    }
+
+    let response = app
+        .clone()
+        .oneshot(admin_request("GET", "/admin/devices/SN-1", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let device: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    assert_eq!(device["model"], device_id);
+    assert_eq!(device["reported_version"], "1.0.0");
+    assert_eq!(device["served_version"], "1.1.0");
+    assert_eq!(device["ip"], "198.51.100.7");
+    assert_eq!(device["user_agent"], "esp-idf/5.2");
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "GET",
+            &format!("/admin/devices?model={device_id}&version=1.1.0"),
+            None,
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let devices: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    let serials: Vec<&str> = devices
+        .as_array()
+        .expect("devices")
+        .iter()
+        .filter_map(|d| d["serial"].as_str())
+        .collect();
+    assert_eq!(serials, ["SN-2"]);
+
+    for (uri, status) in [
+        ("/admin/devices/SN-404", StatusCode::NOT_FOUND),
+        ("/admin/devices?version=latest", StatusCode::BAD_REQUEST),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(admin_request("GET", uri, None))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), status, "{uri}");
+    }
+}
@@ -0,0 +1,228 @@
+//! JWT device token integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use otaflux::api::router::{api_router_with_config, ApiConfig};
+use otaflux::channel::Channel;
+use otaflux::jwt::{load_jwks, JwtVerifier};
+use otaflux::rollback::RollbackTarget;
+use semver::Version;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tower::ServiceExt;
+
+use common::{body_to_string, init_tracing, MockRegistryBuilder, TestFirmware};
+
+const SECRET: &[u8] = b"otaflux-test-secret-0123456789abcdef";
+const JWKS: &str = r#"{
+    "keys": [
+        {
+            "kty": "oct",
+            "kid": "provisioning-1",
+            "alg": "HS256",
+            "k": "b3RhZmx1eC10ZXN0LXNlY3JldC0wMTIzNDU2Nzg5YWJjZGVm"
+        }
+    ]
+}"#;
+
+fn verifier() -> JwtVerifier {
+    let path = std::env::temp_dir().join(format!("otaflux-jwks-{}.json", std::process::id()));
+    std::fs::write(&path, JWKS).expect("write JWKS file");
+    let keys = load_jwks(&path).expect("load JWKS file");
+    std::fs::remove_file(&path).expect("remove JWKS file");
+
+    JwtVerifier::new(keys).with_issuer(Some("provisioning".to_string()))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time")
+        .as_secs()
+}
+
+fn token(kid: Option<&str>, claims: &Value) -> String {
+    let header = Header {
+        kid: kid.map(str::to_string),
+        ..Header::default()
+    };
+    encode(&header, claims, &EncodingKey::from_secret(SECRET)).expect("sign token")
+}
+
+fn gateway_token(extra: &Value) -> String {
+    let mut claims = json!({
+        "iss": "provisioning",
+        "exp": now() + 300,
+        "sub": "SN-0042",
+        "model": "gateway-jwt",
+        "channel": "beta",
+    });
+    for (key, value) in extra.as_object().expect("claims object") {
+        claims[key] = value.clone();
+    }
+    token(Some("provisioning-1"), &claims)
+}
+
+#[test]
+fn test_verify_device_claims() {
+    let verifier = verifier();
+
+    let claims = verifier
+        .verify(&gateway_token(&json!({})))
+        .expect("valid token");
+    assert_eq!(claims.serial.as_deref(), Some("SN-0042"));
+    assert_eq!(claims.models, vec!["gateway-jwt".to_string()]);
+    assert_eq!(claims.channel, Some(Channel::Beta));
+
+    let claims = verifier
+        .verify(&gateway_token(&json!({ "model": ["gateway-*", "sensor"] })))
+        .expect("valid token");
+    assert_eq!(
+        claims.models,
+        vec!["gateway-*".to_string(), "sensor".to_string()]
+    );
+
+    // Tokens without a key ID are checked against every key
+    let claims = verifier
+        .verify(&token(
+            None,
+            &json!({ "iss": "provisioning", "exp": now() + 300, "model": "sensor" }),
+        ))
+        .expect("valid token");
+    assert_eq!(claims.serial, None);
+    assert_eq!(claims.models, vec!["sensor".to_string()]);
+    assert_eq!(claims.channel, None);
+
+    for invalid in [
+        gateway_token(&json!({ "exp": now() - 3600 })),
+        gateway_token(&json!({ "iss": "someone-else" })),
+        token(
+            Some("provisioning-2"),
+            &json!({ "iss": "provisioning", "exp": now() + 300 }),
+        ),
+        token(Some("provisioning-1"), &json!({ "iss": "provisioning" })),
+        // Tokens must name the device IDs they may request
+        token(
+            Some("provisioning-1"),
+            &json!({ "iss": "provisioning", "exp": now() + 300 }),
+        ),
+        encode(
+            &Header::default(),
+            &json!({ "iss": "provisioning", "exp": now() + 300 }),
+            &EncodingKey::from_secret(b"another secret"),
+        )
+        .expect("sign token"),
+        "not.a.token".to_string(),
+    ] {
+        assert!(verifier.verify(&invalid).is_err(), "{invalid}");
+    }
+}
+
+#[tokio::test]
+async fn test_jwt_claims_restrict_device_and_channel() {
+    init_tracing();
+
+    let device_id = "gateway-jwt";
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.0.0", b"firmware 1.0.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.1.0", b"firmware 1.1.0"))
+        .await
+        .build()
+        .await;
+    let manager = registry.firmware_manager();
+    manager.set_rollback(RollbackTarget {
+        device: device_id.to_string(),
+        serial: Some("SN-0042".to_string()),
+        version: Version::new(1, 0, 0),
+    });
+
+    let app = api_router_with_config(
+        manager,
+        None,
+        ApiConfig {
+            jwt_verifier: Some(Arc::new(verifier())),
+            ..ApiConfig::default()
+        },
+    );
+
+    let request = |uri: &str, token: &str| {
+        Request::builder()
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .expect("build request")
+    };
+    let anonymous = token(
+        Some("provisioning-1"),
+        &json!({
+            "iss": "provisioning",
+            "exp": now() + 300,
+            "model": device_id,
+        }),
+    );
+    let token = gateway_token(&json!({}));
+
+    // The subject identifies the device, whatever serial it claims
+    let response = app
+        .clone()
+        .oneshot(request(
+            &format!("/version?device={device_id}&serial=SN-0001&channel=beta"),
+            &token,
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_to_string(response.into_body()).await;
+    assert_eq!(body.lines().next(), Some("1.0.0"));
+
+    // Tokens without a subject identify no device, nor can they claim one
+    let response = app
+        .clone()
+        .oneshot(request(
+            &format!("/version?device={device_id}&serial=SN-0042"),
+            &anonymous,
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_to_string(response.into_body()).await;
+    assert_eq!(body.lines().next(), Some("1.1.0"));
+
+    for (uri, token, status) in [
+        (
+            format!("/firmware?device={device_id}"),
+            token.clone(),
+            StatusCode::OK,
+        ),
+        (
+            "/version?device=sensor".to_string(),
+            token.clone(),
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            format!("/version?device={device_id}&channel=nightly"),
+            token.clone(),
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            format!("/version?device={device_id}"),
+            gateway_token(&json!({ "exp": now() - 3600 })),
+            StatusCode::UNAUTHORIZED,
+        ),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(request(&uri, &token))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), status, "{uri}");
+    }
+}
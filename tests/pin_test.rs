@@ -0,0 +1,293 @@
+//! Version pinning integration tests.
+
+mod common;
+
+use axum::http::{header, StatusCode};
+use otaflux::firmware_manager::FirmwareManager;
+use otaflux::pin::{load_rules, PinRule, PinTarget, Pins};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use common::{
+    admin_request, body_to_bytes, body_to_string, create_admin_app, create_app, get, init_tracing,
+    MockRegistry, MockRegistryBuilder, TestFirmware, ADMIN_TOKEN,
+};
+
+async fn pin_registry(device_id: &str) -> MockRegistry {
+    let mut builder = MockRegistryBuilder::new().await;
+    for tag in ["1.4.0", "1.4.2", "1.5.0", "2.0.0"] {
+        builder = builder
+            .with_firmware(TestFirmware::new(
+                device_id,
+                tag,
+                format!("firmware {tag}").as_bytes(),
+            ))
+            .await;
+    }
+    builder.build().await
+}
+
+#[test]
+fn test_pin_rules_match_most_specific() {
+    let rules: BTreeMap<String, PinRule> = serde_json::from_value(serde_json::json!({
+        "fleet": { "device": "esp32-*", "version": "<2" },
+        "lab-rack": { "device": "esp32-sensor", "group": "lab", "version": "~1.4" },
+        "bench": { "device": "esp32-sensor", "serial": "SN-1", "tag": "1.4.0" },
+    }))
+    .expect("parse rules");
+    let pins = Pins::new(rules);
+
+    let find = |device: &str, serial: Option<&str>, group: Option<&str>| {
+        pins.find(device, serial, group).map(|(id, _)| id)
+    };
+    assert_eq!(find("esp32-sensor", None, None).as_deref(), Some("fleet"));
+    assert_eq!(
+        find("esp32-gateway", None, Some("lab")).as_deref(),
+        Some("fleet")
+    );
+    assert_eq!(
+        find("esp32-sensor", None, Some("lab")).as_deref(),
+        Some("lab-rack")
+    );
+    assert_eq!(
+        find("esp32-sensor", Some("SN-1"), Some("lab")).as_deref(),
+        Some("bench")
+    );
+    assert_eq!(find("stm32-sensor", Some("SN-1"), None), None);
+
+    // Rules need exactly one target
+    for invalid in [
+        serde_json::json!({ "device": "esp32-*" }),
+        serde_json::json!({ "device": "esp32-*", "tag": "1.0.0", "version": "~1" }),
+        serde_json::json!({ "device": "esp32-*", "version": "not a requirement" }),
+    ] {
+        assert!(
+            serde_json::from_value::<PinRule>(invalid.clone()).is_err(),
+            "{invalid}"
+        );
+    }
+}
+
+#[test]
+fn test_load_pin_file() {
+    let path = std::env::temp_dir().join(format!("otaflux-pins-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"{ "site-a": { "device": "sensor-?", "digest": "sha256:abc" } }"#,
+    )
+    .expect("write pin file");
+
+    let rules = load_rules(&path).expect("load pin file");
+    std::fs::remove_file(&path).expect("remove pin file");
+
+    let rule = &rules["site-a"];
+    assert!(rule.matches("sensor-a", None, None));
+    assert!(!rule.matches("sensor-ab", None, None));
+    assert_eq!(rule.target, PinTarget::Digest("sha256:abc".to_string()));
+}
+
+#[tokio::test]
+async fn test_pinned_devices_get_their_version() {
+    init_tracing();
+
+    let device_id = "device-pin";
+    let registry = pin_registry(device_id).await;
+    let rules = serde_json::from_value(serde_json::json!({
+        "fleet": { "device": "device-pin*", "version": "<2" },
+        "lab-rack": { "device": device_id, "group": "lab", "version": "~1.4" },
+        "bench": { "device": device_id, "serial": "SN-1", "tag": "1.4.0" },
+    }))
+    .expect("parse rules");
+    let fm = FirmwareManager::new(
+        registry.host_port(),
+        "user".to_string(),
+        "pass".to_string(),
+        true,
+        "",
+        None,
+    )
+    .expect("create firmware manager")
+    .with_pins(rules);
+    let app = create_app(Arc::new(fm));
+
+    // Several versions of the same repository are served side by side
+    for (params, version) in [
+        ("", "1.5.0"),
+        ("&group=lab", "1.4.2"),
+        ("&group=lab&serial=SN-1", "1.4.0"),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(get(&format!("/firmware?device={device_id}{params}")))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), StatusCode::OK, "{params}");
+        assert_eq!(
+            body_to_bytes(response.into_body()).await,
+            format!("firmware {version}").as_bytes(),
+            "{params}"
+        );
+    }
+
+    let response = app
+        .oneshot(get(&format!("/version?device={device_id}&group=lab")))
+        .await
+        .expect("send request");
+    let body = body_to_string(response.into_body()).await;
+    assert_eq!(body.lines().next(), Some("1.4.2"));
+}
+
+#[tokio::test]
+async fn test_admin_api_manages_pins() {
+    init_tracing();
+
+    let device_id = "device-pin-admin";
+    let registry = pin_registry(device_id).await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    let response = app
+        .clone()
+        .oneshot(get(&format!("/firmware?device={device_id}")))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let latest_etag = response.headers()[header::ETAG].clone();
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "PUT",
+            "/admin/pins/hold",
+            Some(serde_json::json!({ "device": device_id, "tag": "1.4.2" })),
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(get(&format!("/firmware?device={device_id}")))
+        .await
+        .expect("send request");
+    assert_ne!(response.headers()[header::ETAG], latest_etag);
+    assert_eq!(body_to_bytes(response.into_body()).await, b"firmware 1.4.2");
+
+    let response = app
+        .clone()
+        .oneshot(admin_request("GET", "/admin/pins", None))
+        .await
+        .expect("send request");
+    let pins: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    assert_eq!(
+        pins,
+        serde_json::json!([{ "id": "hold", "device": device_id, "tag": "1.4.2" }])
+    );
+
+    let response = app
+        .clone()
+        .oneshot(admin_request("DELETE", "/admin/pins/hold", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "PUT",
+            "/admin/pins/invalid",
+            Some(serde_json::json!({ "device": device_id })),
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_pins_skip_yanked_targets() {
+    init_tracing();
+
+    let device_id = "device-pin-yank";
+    let registry = pin_registry(device_id).await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+    let pin = |target: serde_json::Value| {
+        let mut rule = serde_json::json!({ "device": device_id });
+        rule.as_object_mut()
+            .expect("rule object")
+            .extend(target.as_object().expect("target object").clone());
+        admin_request("PUT", "/admin/pins/hold", Some(rule))
+    };
+    let served = |app: axum::Router| async move {
+        let response = app
+            .oneshot(get(&format!("/firmware?device={device_id}")))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .expect("ETag")
+            .trim_matches('"')
+            .to_string();
+        (etag, body_to_bytes(response.into_body()).await)
+    };
+
+    let response = app
+        .clone()
+        .oneshot(pin(serde_json::json!({ "tag": "1.4.2" })))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let (digest, body) = served(app.clone()).await;
+    assert_eq!(body, b"firmware 1.4.2");
+
+    // Digests of the tags are resolved once, then only the served one is checked
+    let response = app
+        .clone()
+        .oneshot(pin(serde_json::json!({ "digest": digest })))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(served(app.clone()).await.1, b"firmware 1.4.2");
+    let resolved = registry.manifest_requests(device_id).await;
+    assert_eq!(served(app.clone()).await.1, b"firmware 1.4.2");
+    assert_eq!(registry.manifest_requests(device_id).await, resolved + 1);
+
+    // Devices pinned on a release yanked afterwards get the preceding one
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "PUT",
+            &format!("/admin/yanks/{device_id}/1.4.2"),
+            None,
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(served(app.clone()).await.1, b"firmware 1.4.0");
+
+    // Yanked releases can't be pinned
+    for target in [
+        serde_json::json!({ "tag": "1.4.2" }),
+        serde_json::json!({ "digest": "sha256:0000" }),
+    ] {
+        if let Some(digest) = target.get("digest").and_then(serde_json::Value::as_str) {
+            let response = app
+                .clone()
+                .oneshot(admin_request(
+                    "PUT",
+                    &format!("/admin/yanks/{device_id}/{digest}"),
+                    None,
+                ))
+                .await
+                .expect("send request");
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+        let response = app
+            .clone()
+            .oneshot(pin(target.clone()))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), StatusCode::CONFLICT, "{target}");
+    }
+}
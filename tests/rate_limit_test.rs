@@ -0,0 +1,240 @@
+//! Rate limiting and download concurrency integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header, Request, StatusCode},
+};
+use otaflux::api::router::{api_router_with_config, ApiConfig};
+use otaflux::api::throttle::Throttle;
+use otaflux::credential::{Credential, Credentials};
+use otaflux::rate_limit::{RateLimit, RateLimiter};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tower::ServiceExt;
+
+use common::{body_to_bytes, init_tracing, MockRegistryBuilder, TestFirmware};
+
+const DEVICE_ID: &str = "sensor-throttle";
+const FLEET_TOKEN: &str = "fleet-token";
+const SN_0042_TOKEN: &str = "sn-0042-token";
+
+#[test]
+fn test_parse_rate_limit() {
+    assert_eq!(
+        "60/1m".parse::<RateLimit>(),
+        Ok(RateLimit {
+            requests: 60,
+            period: Duration::from_mins(1),
+        })
+    );
+    for invalid in ["60", "0/1m", "-1/1m", "60/1", "60/0s"] {
+        assert!(invalid.parse::<RateLimit>().is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn test_rate_limiter_replenishes_allowance() {
+    let limiter = RateLimiter::new("3/30s".parse().expect("rate limit"));
+    let start = Instant::now();
+
+    for _ in 0..3 {
+        assert_eq!(limiter.check("SN-0042", start), Ok(()));
+    }
+    assert_eq!(
+        limiter.check("SN-0042", start),
+        Err(Duration::from_secs(10))
+    );
+    assert_eq!(limiter.check("SN-0001", start), Ok(()));
+
+    // One request is regained every 10 seconds
+    let later = start + Duration::from_secs(12);
+    assert_eq!(limiter.check("SN-0042", later), Ok(()));
+    assert_eq!(limiter.check("SN-0042", later), Err(Duration::from_secs(8)));
+
+    // Clients that regained their allowance are forgotten
+    assert_eq!(limiter.len(), 2);
+    assert_eq!(
+        limiter.check("SN-0002", start + Duration::from_mins(1)),
+        Ok(())
+    );
+    assert_eq!(limiter.len(), 1);
+}
+
+async fn throttled_app(config: ApiConfig) -> axum::Router {
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(DEVICE_ID, "1.0.0", b"firmware 1.0.0"))
+        .await
+        .build()
+        .await;
+    api_router_with_config(registry.firmware_manager(), None, config)
+}
+
+fn credentials() -> Arc<Credentials> {
+    let mut credentials = BTreeMap::new();
+    credentials.insert(
+        "fleet".to_string(),
+        Credential::new(FLEET_TOKEN, vec!["sensor-*".to_string()], None),
+    );
+    credentials.insert(
+        "sn-0042".to_string(),
+        Credential::new(
+            SN_0042_TOKEN,
+            vec![DEVICE_ID.to_string()],
+            Some("SN-0042".to_string()),
+        ),
+    );
+    Arc::new(Credentials::new(credentials))
+}
+
+/// Builds a request sent straight from `ip`, with the bearer `token` if any.
+fn get(uri: &str, ip: &str, token: Option<&str>) -> Request<Body> {
+    let ip: IpAddr = ip.parse().expect("IP address");
+    let mut request = Request::builder()
+        .uri(uri)
+        .extension(ConnectInfo(SocketAddr::new(ip, 40000)));
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+    request.body(Body::empty()).expect("build request")
+}
+
+#[tokio::test]
+async fn test_device_and_ip_rate_limits() {
+    init_tracing();
+
+    let app = throttled_app(ApiConfig {
+        throttle: Throttle {
+            devices: Some(Arc::new(RateLimiter::new(
+                "2/1m".parse().expect("rate limit"),
+            ))),
+            ips: Some(Arc::new(RateLimiter::new(
+                "5/1m".parse().expect("rate limit"),
+            ))),
+        },
+        device_credentials: Some(credentials()),
+        ..ApiConfig::default()
+    })
+    .await;
+
+    let version = |serial: &str| format!("/version?device={DEVICE_ID}&serial={serial}");
+    let mut requests = vec![
+        (SN_0042_TOKEN, "SN-0042", "198.51.100.7", StatusCode::OK),
+        (SN_0042_TOKEN, "SN-0042", "198.51.100.8", StatusCode::OK),
+        // Authenticated serials are limited whatever address they connect from
+        (
+            SN_0042_TOKEN,
+            "SN-0042",
+            "198.51.100.9",
+            StatusCode::TOO_MANY_REQUESTS,
+        ),
+        (FLEET_TOKEN, "SN-0001", "198.51.100.10", StatusCode::OK),
+        (FLEET_TOKEN, "SN-0001", "198.51.100.10", StatusCode::OK),
+        (
+            FLEET_TOKEN,
+            "SN-0001",
+            "198.51.100.10",
+            StatusCode::TOO_MANY_REQUESTS,
+        ),
+        // Claimed serials are limited apart, so that devices behind one
+        // address don't share a limit, while the address limit caps rotation
+        (FLEET_TOKEN, "SN-0002", "198.51.100.10", StatusCode::OK),
+    ];
+    // Addresses are limited before authentication, whatever token they send
+    requests.extend(std::iter::repeat_n(
+        (
+            "invalid-token",
+            "SN-0004",
+            "198.51.100.11",
+            StatusCode::UNAUTHORIZED,
+        ),
+        5,
+    ));
+    requests.push((
+        FLEET_TOKEN,
+        "SN-0004",
+        "198.51.100.11",
+        StatusCode::TOO_MANY_REQUESTS,
+    ));
+
+    for (token, serial, ip, status) in requests {
+        let response = app
+            .clone()
+            .oneshot(get(&version(serial), ip, Some(token)))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), status, "{serial} from {ip}");
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+                .to_str()
+                .expect("Retry-After header")
+                .parse()
+                .expect("Retry-After seconds");
+            assert!((1..=60).contains(&retry_after), "{retry_after}");
+        }
+    }
+
+    // The health check isn't limited
+    let response = app
+        .oneshot(get("/health", "198.51.100.11", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_concurrent_downloads_are_capped() {
+    init_tracing();
+
+    let app = throttled_app(ApiConfig {
+        max_concurrent_downloads: Some(1),
+        ..ApiConfig::default()
+    })
+    .await;
+    let firmware = format!("/firmware?device={DEVICE_ID}");
+
+    // The slot is held while the body of the first download is being sent
+    let download = app
+        .clone()
+        .oneshot(get(&firmware, "198.51.100.7", None))
+        .await
+        .expect("send request");
+    assert_eq!(download.status(), StatusCode::OK);
+    assert_eq!(
+        download.headers()[header::CONTENT_LENGTH],
+        b"firmware 1.0.0".len().to_string().as_str()
+    );
+
+    let response = app
+        .clone()
+        .oneshot(get(&firmware, "198.51.100.8", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+    // Version checks don't take a slot
+    let response = app
+        .clone()
+        .oneshot(get(
+            &format!("/version?device={DEVICE_ID}"),
+            "198.51.100.8",
+            None,
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_eq!(body_to_bytes(download.into_body()).await, b"firmware 1.0.0");
+    let response = app
+        .oneshot(get(&firmware, "198.51.100.8", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+}
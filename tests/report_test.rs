@@ -0,0 +1,171 @@
+//! Update outcome reporting integration tests.
+
+mod common;
+
+use axum::http::StatusCode;
+use otaflux::api::router::{api_router_with_config, ApiConfig};
+use otaflux::inventory::{DeviceStore, MemoryStore, SqliteStore, UpdateReport, UpdateStatus};
+use semver::Version;
+use std::time::{Duration, SystemTime};
+use tower::ServiceExt;
+
+use common::{admin_request, body_to_string, init_tracing, MockRegistryBuilder, ADMIN_TOKEN};
+
+fn report(status: UpdateStatus, seconds: u64) -> UpdateReport {
+    UpdateReport {
+        serial: "SN-1".to_string(),
+        model: "sensor".to_string(),
+        from_version: Some(Version::new(1, 0, 0)),
+        to_version: Version::new(1, 1, 0),
+        status,
+        error_code: (status == UpdateStatus::Failed).then(|| "0x1105".to_string()),
+        reported_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds),
+    }
+}
+
+fn assert_store_records_reports(store: &dyn DeviceStore) {
+    store
+        .report(report(UpdateStatus::Installed, 0))
+        .expect("report");
+
+    // Devices are known from their first report, without running version yet
+    let device = store.get("SN-1").expect("get").expect("device");
+    assert_eq!(device.model, "sensor");
+    assert_eq!(device.reported_version, None);
+    assert_eq!(device.last_report, Some(report(UpdateStatus::Installed, 0)));
+
+    store
+        .report(report(UpdateStatus::Failed, 60))
+        .expect("report");
+    let device = store.get("SN-1").expect("get").expect("device");
+    assert_eq!(device.reported_version, Some(Version::new(1, 0, 0)));
+    assert_eq!(
+        device.last_seen,
+        report(UpdateStatus::Failed, 60).reported_at
+    );
+
+    store
+        .report(report(UpdateStatus::Booted, 120))
+        .expect("report");
+    let device = store.get("SN-1").expect("get").expect("device");
+    assert_eq!(device.reported_version, Some(Version::new(1, 1, 0)));
+
+    let statuses: Vec<UpdateStatus> = store
+        .reports("SN-1")
+        .expect("reports")
+        .into_iter()
+        .map(|r| r.status)
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            UpdateStatus::Booted,
+            UpdateStatus::Failed,
+            UpdateStatus::Installed
+        ]
+    );
+    assert!(store.reports("SN-404").expect("reports").is_empty());
+}
+
+#[test]
+fn test_memory_store_records_reports() {
+    assert_store_records_reports(&MemoryStore::new());
+}
+
+#[test]
+fn test_sqlite_store_records_reports() {
+    assert_store_records_reports(&SqliteStore::open_in_memory().expect("open database"));
+}
+
+#[tokio::test]
+async fn test_reported_outcomes_are_shown_in_inventory() {
+    init_tracing();
+
+    let registry = MockRegistryBuilder::new().await.build().await;
+    let app = api_router_with_config(
+        registry.firmware_manager(),
+        None,
+        ApiConfig {
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            ..ApiConfig::default()
+        },
+    );
+
+    for body in [
+        serde_json::json!({
+            "device": "sensor",
+            "serial": "SN-1",
+            "from_version": "1.0.0",
+            "to_version": "1.1.0",
+            "status": "installed"
+        }),
+        serde_json::json!({
+            "device": "sensor",
+            "serial": "SN-1",
+            "from_version": "1.0.0",
+            "to_version": "v1.1.0",
+            "status": "failed",
+            "error_code": 4357
+        }),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(admin_request("POST", "/report", Some(body)))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    let response = app
+        .clone()
+        .oneshot(admin_request("GET", "/admin/devices/SN-1", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let device: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    assert_eq!(device["reported_version"], "1.0.0");
+    assert_eq!(device["last_report"]["status"], "failed");
+    assert_eq!(device["last_report"]["to_version"], "1.1.0");
+    assert_eq!(device["last_report"]["error_code"], "4357");
+
+    let response = app
+        .clone()
+        .oneshot(admin_request("GET", "/admin/devices/SN-1/reports", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let reports: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    assert_eq!(reports.as_array().map(Vec::len), Some(2));
+    assert_eq!(reports[1]["status"], "installed");
+}
+
+#[tokio::test]
+async fn test_invalid_reports_are_rejected() {
+    init_tracing();
+
+    let registry = MockRegistryBuilder::new().await.build().await;
+    let app = api_router_with_config(registry.firmware_manager(), None, ApiConfig::default());
+
+    for body in [
+        // Missing serial
+        serde_json::json!({ "device": "sensor", "to_version": "1.1.0", "status": "booted" }),
+        serde_json::json!({
+            "device": "sensor", "serial": "SN-1", "to_version": "1.1.0", "status": "exploded"
+        }),
+        serde_json::json!({
+            "device": "sensor", "serial": "SN-1", "to_version": "latest", "status": "booted"
+        }),
+        serde_json::json!({
+            "device": "", "serial": "SN-1", "to_version": "1.1.0", "status": "booted"
+        }),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(admin_request("POST", "/report", Some(body.clone())))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{body}");
+    }
+}
@@ -0,0 +1,220 @@
+//! Downgrade protection and rollback target integration tests.
+
+mod common;
+
+use axum::http::StatusCode;
+use tower::ServiceExt;
+
+use common::{
+    admin_request, body_to_bytes, body_to_string, create_admin_app, get, init_tracing,
+    MockRegistry, MockRegistryBuilder, TestFirmware, ADMIN_TOKEN,
+};
+
+async fn rollback_registry(device_id: &str) -> MockRegistry {
+    MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.0.0", b"firmware 1.0.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.1.0", b"firmware 1.1.0"))
+        .await
+        .build()
+        .await
+}
+
+#[tokio::test]
+async fn test_newer_device_is_not_downgraded() {
+    init_tracing();
+
+    let device_id = "device-no-downgrade";
+    let registry = rollback_registry(device_id).await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    // A device running an unreleased build doesn't get the latest release
+    for uri in [
+        format!("/version?device={device_id}&current=1.2.0"),
+        format!("/firmware?device={device_id}&current=1.2.0"),
+    ] {
+        let response = app.clone().oneshot(get(&uri)).await.expect("send request");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn test_model_rollback_target_is_served_and_flagged() {
+    init_tracing();
+
+    let device_id = "device-rollback";
+    let registry = rollback_registry(device_id).await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "PUT",
+            &format!("/admin/rollbacks/{device_id}"),
+            Some(serde_json::json!({ "version": "1.0.0" })),
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(get(&format!(
+            "/version?device={device_id}&current=1.1.0&format=json"
+        )))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-firmware-rollback"], "true");
+    let version: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    assert_eq!(version["version"], "1.0.0");
+    assert_eq!(version["rollback"], true);
+
+    let response = app
+        .clone()
+        .oneshot(get(&format!("/firmware?device={device_id}&current=1.1.0")))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_to_bytes(response.into_body()).await, b"firmware 1.0.0");
+
+    // Devices already rolled back are up to date
+    let response = app
+        .clone()
+        .oneshot(get(&format!("/version?device={device_id}&current=1.0.0")))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "DELETE",
+            &format!("/admin/rollbacks/{device_id}"),
+            None,
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = app
+        .oneshot(get(&format!("/version?device={device_id}&current=1.1.0")))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_serial_rollback_target_only_applies_to_device() {
+    init_tracing();
+
+    let device_id = "device-serial-rollback";
+    let registry = rollback_registry(device_id).await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "PUT",
+            &format!("/admin/rollbacks/{device_id}/SN-LAB-1"),
+            Some(serde_json::json!({ "version": "1.0.0" })),
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let version = |serial: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(get(&format!(
+                    "/version?device={device_id}&serial={serial}&current=1.1.0&format=json"
+                )))
+                .await
+                .expect("send request");
+            response.status()
+        }
+    };
+    assert_eq!(version("SN-LAB-1").await, StatusCode::OK);
+    assert_eq!(version("SN-FIELD-1").await, StatusCode::NO_CONTENT);
+
+    let response = app
+        .oneshot(admin_request("GET", "/admin/rollbacks", None))
+        .await
+        .expect("send request");
+    let rollbacks: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    assert_eq!(
+        rollbacks,
+        serde_json::json!([{ "device": device_id, "serial": "SN-LAB-1", "version": "1.0.0" }])
+    );
+}
+
+#[tokio::test]
+async fn test_yanked_rollback_target_is_not_served() {
+    init_tracing();
+
+    let device_id = "device-yanked-rollback";
+    let registry = rollback_registry(device_id).await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    for (method, uri, body) in [
+        (
+            "PUT",
+            format!("/admin/rollbacks/{device_id}"),
+            Some(serde_json::json!({ "version": "1.0.0" })),
+        ),
+        ("PUT", format!("/admin/yanks/{device_id}/1.0.0"), None),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(admin_request(method, &uri, body))
+            .await
+            .expect("send request");
+        assert!(response.status().is_success(), "{uri}");
+    }
+
+    // Devices keep the latest release rather than rolling back to a yanked one
+    let response = app
+        .clone()
+        .oneshot(get(&format!("/version?device={device_id}&current=1.1.0")))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = app
+        .oneshot(get(&format!("/version?device={device_id}")))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("x-firmware-rollback").is_none());
+    let body = body_to_string(response.into_body()).await;
+    assert_eq!(body.lines().next(), Some("1.1.0"));
+}
+
+#[tokio::test]
+async fn test_admin_api_rejects_invalid_rollback() {
+    init_tracing();
+
+    let registry = MockRegistryBuilder::new().await.build().await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "PUT",
+            "/admin/rollbacks/device",
+            Some(serde_json::json!({ "version": "previous" })),
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .oneshot(admin_request("DELETE", "/admin/rollbacks/device", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,416 @@
+//! Staged rollout integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use otaflux::inventory::{DeviceStore, RolloutRecord, SqliteStore};
+use otaflux::rollout::{bucket, RepositorySchedule, Rollout, RolloutSchedule, Rollouts};
+use semver::Version;
+use std::time::{Duration, SystemTime};
+use tower::ServiceExt;
+
+use common::{
+    admin_request, body_to_string, create_admin_app, create_app, get, init_tracing,
+    MockRegistryBuilder, TestFirmware, ADMIN_TOKEN,
+};
+
+/// Returns a serial whose rollout bucket is below (or not) the given percentage.
+fn serial_in_rollout(device_id: &str, percentage: u8, included: bool) -> String {
+    (0..100_000)
+        .map(|i| format!("SN{i:05}"))
+        .find(|serial| (bucket(device_id, serial) < percentage) == included)
+        .expect("find serial")
+}
+
+async fn served_version(app: &axum::Router, uri: &str) -> String {
+    let response = app.clone().oneshot(get(uri)).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK, "{uri}");
+
+    let body = body_to_string(response.into_body()).await;
+    body.lines().next().expect("version line").to_string()
+}
+
+#[test]
+fn test_rollout_buckets_are_stable_and_proportional() {
+    let rollout = Rollout::new(Version::new(1, 5, 0), 25);
+
+    let serials: Vec<String> = (0..1000).map(|i| format!("SN{i:05}")).collect();
+    let included = serials
+        .iter()
+        .filter(|serial| rollout.includes("sensor", Some(serial)))
+        .count();
+
+    assert!(
+        (150..=350).contains(&included),
+        "{included} devices included"
+    );
+    assert_eq!(bucket("sensor", "SN00042"), bucket("sensor", "SN00042"));
+
+    // Devices included at a lower percentage stay included when ramping up
+    let ramped = Rollout {
+        percentage: 50,
+        ..rollout.clone()
+    };
+    assert!(serials
+        .iter()
+        .filter(|serial| rollout.includes("sensor", Some(serial)))
+        .all(|serial| ramped.includes("sensor", Some(serial))));
+
+    // Devices without serial only get fully rolled out versions
+    assert!(!rollout.includes("sensor", None));
+    assert!(Rollout {
+        percentage: 100,
+        ..rollout
+    }
+    .includes("sensor", None));
+}
+
+#[test]
+fn test_rollout_holds_back_newer_stable_versions_until_complete() {
+    let rollout = Rollout::new(Version::new(1, 5, 0), 5);
+    let excluded = serial_in_rollout("sensor", 5, false);
+    let allows = |rollout: &Rollout, version: &str| {
+        rollout.allows(
+            &Version::parse(version).expect("version"),
+            "sensor",
+            Some(&excluded),
+        )
+    };
+
+    assert!(allows(&rollout, "1.4.0"));
+    assert!(!allows(&rollout, "1.5.0"));
+    assert!(!allows(&rollout, "1.6.0"));
+    // Pre-releases aren't staged by rollouts of stable releases
+    assert!(allows(&rollout, "1.6.0-beta.1"));
+    assert!(allows(&rollout, "1.6.0-nightly.20240101"));
+
+    let complete = Rollout::new(Version::new(1, 5, 0), 100);
+    assert!(allows(&complete, "1.5.0"));
+    assert!(allows(&complete, "1.6.0"));
+}
+
+#[tokio::test]
+async fn test_rollout_serves_new_version_to_bucketed_devices() {
+    init_tracing();
+
+    let device_id = "device-rollout";
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.4.0", b"previous firmware"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.5.0", b"new firmware"))
+        .await
+        .build()
+        .await;
+
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "PUT",
+            &format!("/admin/rollouts/{device_id}"),
+            Some(serde_json::json!({ "version": "1.5.0", "percentage": 5 })),
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let included = serial_in_rollout(device_id, 5, true);
+    let excluded = serial_in_rollout(device_id, 5, false);
+
+    let uri = |serial: &str| format!("/version?device={device_id}&serial={serial}");
+    assert_eq!(served_version(&app, &uri(&included)).await, "1.5.0");
+    assert_eq!(served_version(&app, &uri(&excluded)).await, "1.4.0");
+    assert_eq!(
+        served_version(&app, &format!("/version?device={device_id}")).await,
+        "1.4.0"
+    );
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "GET",
+            &format!("/admin/rollouts/{device_id}"),
+            None,
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let rollout: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    assert_eq!(rollout["version"], "1.5.0");
+    assert_eq!(rollout["percentage"], 5);
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "DELETE",
+            &format!("/admin/rollouts/{device_id}"),
+            None,
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    assert_eq!(served_version(&app, &uri(&excluded)).await, "1.5.0");
+}
+
+#[tokio::test]
+async fn test_admin_api_requires_token() {
+    init_tracing();
+
+    let registry = MockRegistryBuilder::new().await.build().await;
+
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+    let request = Request::builder()
+        .uri("/admin/rollouts")
+        .method("GET")
+        .header("Authorization", "Bearer wrong-token")
+        .body(Body::empty())
+        .expect("build request");
+    let response = app.oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // The admin API is not exposed without a configured token
+    let app = create_app(registry.firmware_manager());
+    let response = app
+        .oneshot(admin_request("GET", "/admin/rollouts", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_admin_api_rejects_invalid_rollout() {
+    init_tracing();
+
+    let registry = MockRegistryBuilder::new().await.build().await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    for body in [
+        serde_json::json!({ "version": "not-a-version", "percentage": 5 }),
+        serde_json::json!({ "version": "1.0.0", "percentage": 150 }),
+        serde_json::json!({ "version": "1.1.0-beta.1", "percentage": 5 }),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(admin_request(
+                "PUT",
+                "/admin/rollouts/device",
+                Some(body.clone()),
+            ))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{body}");
+    }
+}
+
+#[test]
+fn test_rollout_schedule_parsing() {
+    let schedule: RepositorySchedule = "esp32-sensor=1,10,50,100@6h".parse().expect("parse");
+    assert_eq!(schedule.repository, "esp32-sensor");
+    assert_eq!(schedule.schedule.steps, vec![1, 10, 50, 100]);
+    assert_eq!(schedule.schedule.interval, Duration::from_hours(6));
+    assert_eq!(schedule.schedule.to_string(), "1,10,50,100@6h");
+
+    for invalid in [
+        "1,10,50,100@6h",
+        "sensor=1,10,50,100",
+        "sensor=10,5,100@6h",
+        "sensor=1,150@6h",
+        "sensor=1,100@6",
+        "sensor=1,100@0h",
+        "sensor=1,100@6w",
+    ] {
+        assert!(invalid.parse::<RepositorySchedule>().is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn test_scheduled_rollout_progression() {
+    let schedule: RolloutSchedule = "1,10,50,100@6h".parse().expect("parse");
+    let hours = |h: u64| Duration::from_secs(h * 60 * 60);
+    let start = SystemTime::UNIX_EPOCH + hours(1000);
+
+    let mut rollout = Rollout::scheduled(Version::new(2, 0, 0), schedule, start);
+    assert_eq!(rollout.percentage, 1);
+
+    assert!(!rollout.progress(start + hours(5)));
+    assert_eq!(rollout.percentage, 1);
+
+    assert!(rollout.progress(start + hours(6)));
+    assert_eq!(rollout.percentage, 10);
+
+    // Halted rollouts keep their percentage
+    rollout.halt();
+    assert!(!rollout.progress(start + hours(30)));
+    assert_eq!(rollout.percentage, 10);
+
+    // Resumed rollouts wait a full interval before the next step
+    rollout.resume(start + hours(30));
+    assert!(!rollout.progress(start + hours(35)));
+    assert!(rollout.progress(start + hours(36)));
+    assert_eq!(rollout.percentage, 50);
+
+    // Steps missed while no progression happened are caught up, up to the last one
+    assert!(rollout.progress(start + hours(100)));
+    assert_eq!(rollout.percentage, 100);
+    assert!(rollout.is_complete());
+    assert_eq!(rollout.progression.map(|p| p.step), Some(3));
+}
+
+#[test]
+fn test_scheduled_rollout_starts_for_new_versions() {
+    let rollouts = Rollouts::new(vec![
+        "sensor=1,10,50,100@6h".parse().expect("parse"),
+        "*=50,100@1d".parse().expect("parse"),
+    ]);
+    let now = SystemTime::now();
+    let versions = |v: &[&str]| -> Vec<Version> {
+        v.iter()
+            .map(|v| Version::parse(v).expect("version"))
+            .collect()
+    };
+
+    // The latest version when first observed is rolled out like any new version
+    let rollout = rollouts
+        .observe("sensor", &versions(&["1.0.0", "1.1.0"]), now)
+        .expect("rollout started");
+    assert_eq!(rollout.version, Version::new(1, 1, 0));
+    assert_eq!(rollout.percentage, 1);
+    assert_eq!(rollouts.get("sensor"), Some(rollout));
+
+    // Removed rollouts aren't started again
+    rollouts.remove("sensor");
+    assert!(rollouts
+        .observe("sensor", &versions(&["1.0.0", "1.1.0"]), now)
+        .is_none());
+
+    // Pre-releases don't start rollouts
+    rollouts.observe("sensor", &versions(&["1.1.0", "1.2.0-beta.1"]), now);
+    assert!(rollouts.get("sensor").is_none());
+
+    rollouts.observe("sensor", &versions(&["1.1.0", "1.2.0"]), now);
+    let rollout = rollouts.get("sensor").expect("rollout started");
+    assert_eq!(rollout.version, Version::new(1, 2, 0));
+    assert_eq!(rollout.percentage, 1);
+
+    // Newer versions wait for the current rollout to complete
+    rollouts.observe("sensor", &versions(&["1.2.0", "1.3.0"]), now);
+    assert_eq!(
+        rollouts.get("sensor").expect("rollout").version,
+        Version::new(1, 2, 0)
+    );
+
+    rollouts.progress(now + Duration::from_hours(18));
+    rollouts.observe("sensor", &versions(&["1.2.0", "1.3.0"]), now);
+    assert_eq!(
+        rollouts.get("sensor").expect("rollout").version,
+        Version::new(1, 3, 0)
+    );
+
+    // Other repositories use the wildcard schedule
+    rollouts.observe("gateway", &versions(&["3.0.0"]), now);
+    assert_eq!(rollouts.get("gateway").expect("rollout").percentage, 50);
+}
+
+#[test]
+fn test_rollouts_restored_from_device_store() {
+    let schedules = || vec!["*=1,10,50,100@6h".parse().expect("parse")];
+    let store = SqliteStore::open_in_memory().expect("open database");
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let hours = |h: u64| Duration::from_secs(h * 60 * 60);
+    let versions = |v: &[&str]| -> Vec<Version> {
+        v.iter()
+            .map(|v| Version::parse(v).expect("version"))
+            .collect()
+    };
+
+    let rollouts = Rollouts::new(schedules());
+    rollouts.observe("sensor", &versions(&["1.0.0"]), start);
+    rollouts.remove("sensor");
+    rollouts.observe("sensor", &versions(&["1.0.0", "1.1.0"]), start);
+    rollouts.set("gateway", Rollout::new(Version::new(2, 0, 0), 25));
+    for model in ["sensor", "gateway"] {
+        store
+            .save_rollout(rollouts.record(model))
+            .expect("save rollout");
+    }
+
+    // Restored rollouts catch up with the steps elapsed while stopped
+    let restored = Rollouts::new(schedules());
+    restored.load(store.rollouts().expect("list rollouts"), start + hours(7));
+    let rollout = restored.get("sensor").expect("sensor rollout");
+    assert_eq!(rollout.version, Version::new(1, 1, 0));
+    assert_eq!(rollout.percentage, 10);
+    assert_eq!(
+        restored.get("gateway"),
+        Some(Rollout::new(Version::new(2, 0, 0), 25))
+    );
+
+    // Versions rolled out before the restart aren't rolled out again
+    restored.remove("sensor");
+    assert!(restored
+        .observe("sensor", &versions(&["1.0.0", "1.1.0"]), start)
+        .is_none());
+
+    // Records without rollout nor baseline are deleted
+    store
+        .save_rollout(RolloutRecord {
+            model: "gateway".to_string(),
+            rollout: None,
+            baseline: None,
+        })
+        .expect("delete rollout");
+    let records = store.rollouts().expect("list rollouts");
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].baseline, Some(Version::new(1, 0, 0)));
+}
+
+#[tokio::test]
+async fn test_admin_api_halts_and_resumes_rollout() {
+    init_tracing();
+
+    let registry = MockRegistryBuilder::new().await.build().await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    let response = app
+        .clone()
+        .oneshot(admin_request("POST", "/admin/rollouts/device/halt", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "PUT",
+            "/admin/rollouts/device",
+            Some(serde_json::json!({ "version": "1.0.0", "percentage": 10 })),
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for (action, halted) in [("halt", true), ("resume", false)] {
+        let response = app
+            .clone()
+            .oneshot(admin_request(
+                "POST",
+                &format!("/admin/rollouts/device/{action}"),
+                None,
+            ))
+            .await
+            .expect("send request");
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let rollout: serde_json::Value =
+            serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+        assert_eq!(rollout["halted"], halted, "{action}");
+        assert_eq!(rollout["percentage"], 10);
+    }
+}
@@ -0,0 +1,296 @@
+//! TLS and mutual TLS integration tests.
+
+mod common;
+
+use otaflux::api::router::{api_router_with_config, ApiConfig};
+use otaflux::rollback::RollbackTarget;
+use otaflux::tls::{client_certificate, serve, ServerTls, TLS_HANDSHAKE_TIMEOUT};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use rustls::pki_types::{PrivatePkcs8KeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore};
+use semver::Version;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsConnector;
+use tokio_util::sync::CancellationToken;
+
+use common::{init_tracing, MockRegistryBuilder, TestFirmware};
+
+struct Pki {
+    ca: Certificate,
+    ca_key: KeyPair,
+}
+
+impl Pki {
+    fn new() -> Self {
+        let ca_key = KeyPair::generate().expect("generate key");
+        let mut params = CertificateParams::new(Vec::<String>::new()).expect("CA params");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "OtaFlux Device CA");
+        let ca = params.self_signed(&ca_key).expect("sign CA");
+        Self { ca, ca_key }
+    }
+
+    fn issue(
+        &self,
+        common_name: Option<&str>,
+        alt_names: &[&str],
+        usage: ExtendedKeyUsagePurpose,
+    ) -> (Certificate, KeyPair) {
+        let key = KeyPair::generate().expect("generate key");
+        let alt_names: Vec<String> = alt_names.iter().map(ToString::to_string).collect();
+        let mut params = CertificateParams::new(alt_names).expect("certificate params");
+        params.distinguished_name = DistinguishedName::new();
+        if let Some(common_name) = common_name {
+            params
+                .distinguished_name
+                .push(DnType::CommonName, common_name);
+        }
+        params.extended_key_usages = vec![usage];
+        let cert = params
+            .signed_by(&key, &self.ca, &self.ca_key)
+            .expect("sign certificate");
+        (cert, key)
+    }
+}
+
+fn write_pem(name: &str, pem: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("otaflux-{}-{name}.pem", std::process::id()));
+    std::fs::write(&path, pem).expect("write PEM file");
+    path
+}
+
+async fn get(
+    address: std::net::SocketAddr,
+    config: ClientConfig,
+    path: &str,
+) -> std::io::Result<String> {
+    let stream = TcpStream::connect(address).await?;
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(
+            ServerName::try_from("localhost").expect("server name"),
+            stream,
+        )
+        .await?;
+
+    stream
+        .write_all(
+            format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .as_bytes(),
+        )
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok(response)
+}
+
+#[test]
+fn test_client_certificate_identity() {
+    let pki = Pki::new();
+
+    let (cert, _) = pki.issue(
+        Some("SN-0042"),
+        &["sn-0042.devices.example.com"],
+        ExtendedKeyUsagePurpose::ClientAuth,
+    );
+    let certificate = client_certificate(cert.der()).expect("parse certificate");
+    assert_eq!(certificate.common_name, "SN-0042");
+    assert!(certificate.devices.is_empty());
+
+    // Organizational units name the device IDs of the device
+    let key = KeyPair::generate().expect("generate key");
+    let mut params = CertificateParams::new(Vec::<String>::new()).expect("certificate params");
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "SN-0044");
+    params
+        .distinguished_name
+        .push(DnType::OrganizationalUnitName, "esp32-sensor");
+    let cert = params
+        .signed_by(&key, &pki.ca, &pki.ca_key)
+        .expect("sign certificate");
+    let certificate = client_certificate(cert.der()).expect("parse certificate");
+    assert_eq!(certificate.devices, vec!["esp32-sensor".to_string()]);
+
+    // Certificates without a common name fall back to their alternative name
+    let (cert, _) = pki.issue(
+        None,
+        &["sn-0043.devices.example.com"],
+        ExtendedKeyUsagePurpose::ClientAuth,
+    );
+    let certificate = client_certificate(cert.der()).expect("parse certificate");
+    assert_eq!(certificate.common_name, "sn-0043.devices.example.com");
+
+    let (cert, _) = pki.issue(None, &[], ExtendedKeyUsagePurpose::ClientAuth);
+    assert!(client_certificate(cert.der()).is_err());
+    assert!(client_certificate(b"not a certificate").is_err());
+}
+
+#[test]
+fn test_server_tls_rejects_missing_files() {
+    let tls = ServerTls {
+        cert_path: PathBuf::from("/nonexistent/server.pem"),
+        key_path: PathBuf::from("/nonexistent/server.key"),
+        client_ca_path: None,
+    };
+    assert!(tls.server_config().is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_stalled_tls_handshake_times_out() {
+    init_tracing();
+
+    let pki = Pki::new();
+    let (server_cert, server_key) =
+        pki.issue(None, &["localhost"], ExtendedKeyUsagePurpose::ServerAuth);
+    let tls = ServerTls {
+        cert_path: write_pem("stalled-server-cert", &server_cert.pem()),
+        key_path: write_pem("stalled-server-key", &server_key.serialize_pem()),
+        client_ca_path: None,
+    };
+    let config = tls.server_config().expect("server TLS config");
+    for path in [&tls.cert_path, &tls.key_path] {
+        std::fs::remove_file(path).expect("remove PEM file");
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let address = listener.local_addr().expect("local address");
+    let cancel_token = CancellationToken::new();
+    let server = tokio::spawn(serve(
+        listener,
+        config,
+        axum::Router::new(),
+        cancel_token.clone(),
+    ));
+
+    // The client connects but never starts the handshake
+    let mut stream = TcpStream::connect(address).await.expect("connect");
+    let started = tokio::time::Instant::now();
+    let mut buffer = [0; 1];
+    let read = stream.read(&mut buffer).await.expect("read");
+    assert_eq!(read, 0);
+    assert!(started.elapsed() >= TLS_HANDSHAKE_TIMEOUT);
+
+    cancel_token.cancel();
+    server.await.expect("server task");
+}
+
+#[tokio::test]
+async fn test_mutual_tls_identifies_device() {
+    init_tracing();
+
+    let device_id = "device-mtls";
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.0.0", b"firmware 1.0.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.1.0", b"firmware 1.1.0"))
+        .await
+        .build()
+        .await;
+    let manager = registry.firmware_manager();
+    manager.set_rollback(RollbackTarget {
+        device: device_id.to_string(),
+        serial: Some("SN-0042".to_string()),
+        version: Version::new(1, 0, 0),
+    });
+
+    let pki = Pki::new();
+    let (server_cert, server_key) =
+        pki.issue(None, &["localhost"], ExtendedKeyUsagePurpose::ServerAuth);
+    let tls = ServerTls {
+        cert_path: write_pem("server-cert", &server_cert.pem()),
+        key_path: write_pem("server-key", &server_key.serialize_pem()),
+        client_ca_path: Some(write_pem("client-ca", &pki.ca.pem())),
+    };
+    let config = tls.server_config().expect("server TLS config");
+    for path in [&tls.cert_path, &tls.key_path] {
+        std::fs::remove_file(path).expect("remove PEM file");
+    }
+    std::fs::remove_file(tls.client_ca_path.as_ref().unwrap()).expect("remove PEM file");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let address = listener.local_addr().expect("local address");
+    let cancel_token = CancellationToken::new();
+    let router = api_router_with_config(
+        manager,
+        None,
+        ApiConfig {
+            certificate_devices: vec![device_id.to_string()],
+            require_client_certificate: true,
+            ..ApiConfig::default()
+        },
+    );
+    let server = tokio::spawn(serve(listener, config, router, cancel_token.clone()));
+
+    let mut roots = RootCertStore::empty();
+    roots.add(pki.ca.der().clone()).expect("add CA");
+    let client_config = |identity: Option<(&Certificate, &KeyPair)>| {
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .expect("protocol versions")
+                .with_root_certificates(roots.clone());
+        match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(
+                    vec![cert.der().clone()],
+                    PrivatePkcs8KeyDer::from(key.serialize_der()).into(),
+                )
+                .expect("client certificate"),
+            None => builder.with_no_client_auth(),
+        }
+    };
+
+    // The certificate identifies the device, whatever serial it claims
+    let (cert, key) = pki.issue(Some("SN-0042"), &[], ExtendedKeyUsagePurpose::ClientAuth);
+    let response = get(
+        address,
+        client_config(Some((&cert, &key))),
+        &format!("/version?device={device_id}&serial=SN-0001"),
+    )
+    .await
+    .expect("send request");
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains("\r\n\r\n1.0.0\n"), "{response}");
+
+    let (cert, key) = pki.issue(Some("SN-0001"), &[], ExtendedKeyUsagePurpose::ClientAuth);
+    let response = get(
+        address,
+        client_config(Some((&cert, &key))),
+        &format!("/version?device={device_id}&serial=SN-0042"),
+    )
+    .await
+    .expect("send request");
+    assert!(response.contains("\r\n\r\n1.1.0\n"), "{response}");
+
+    // Clients without a certificate only reach the routes not requiring one
+    let response = get(address, client_config(None), "/health")
+        .await
+        .expect("send request");
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    let response = get(
+        address,
+        client_config(None),
+        &format!("/version?device={device_id}"),
+    )
+    .await
+    .expect("send request");
+    assert!(response.starts_with("HTTP/1.1 401"), "{response}");
+
+    // Certificates of another CA are rejected during the handshake
+    let untrusted = Pki::new();
+    let (cert, key) = untrusted.issue(Some("SN-0042"), &[], ExtendedKeyUsagePurpose::ClientAuth);
+    let response = get(address, client_config(Some((&cert, &key))), "/health").await;
+    assert!(!response.is_ok_and(|response| !response.is_empty()));
+
+    cancel_token.cancel();
+    server.await.expect("server task");
+}
@@ -0,0 +1,239 @@
+//! Upgrade path (stepping stone) integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use otaflux::firmware_manager::FirmwareManager;
+use otaflux::upgrade_path::{min_version, SteppingStone, UpgradePaths, MIN_VERSION_ANNOTATION};
+use semver::Version;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use common::{
+    body_to_bytes, body_to_string, create_app, init_tracing, MockRegistry, MockRegistryBuilder,
+    TestFirmware,
+};
+
+async fn upgrade_path_registry(device_id: &str) -> MockRegistry {
+    MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.0.0", b"firmware 1.0.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.5.0", b"firmware 1.5.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "2.0.0", b"firmware 2.0.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "2.3.0", b"firmware 2.3.0"))
+        .await
+        .with_firmware(
+            TestFirmware::new(device_id, "3.0.0", b"firmware 3.0.0")
+                .with_annotation(MIN_VERSION_ANNOTATION, "2.3.0"),
+        )
+        .await
+        .build()
+        .await
+}
+
+fn firmware_manager(registry: &MockRegistry, stepping_stones: &[&str]) -> Arc<FirmwareManager> {
+    let fm = FirmwareManager::new(
+        registry.host_port(),
+        "user".to_string(),
+        "pass".to_string(),
+        true,
+        "",
+        None,
+    )
+    .expect("create firmware manager")
+    .with_stepping_stones(
+        stepping_stones
+            .iter()
+            .map(|s| s.parse().expect("parse stepping stone"))
+            .collect(),
+    );
+    Arc::new(fm)
+}
+
+async fn served_version(app: &axum::Router, uri: &str) -> String {
+    let request = Request::builder()
+        .uri(uri)
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.clone().oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK, "{uri}");
+
+    let body = body_to_string(response.into_body()).await;
+    body.lines().next().expect("version line").to_string()
+}
+
+#[test]
+fn test_required_version() {
+    let stone: SteppingStone = "sensor=v2.0.0".parse().expect("parse");
+    assert_eq!(stone.version, Version::new(2, 0, 0));
+    assert!("2.0.0".parse::<SteppingStone>().is_err());
+
+    let paths = UpgradePaths::new(vec![stone, "sensor=1.0.0".parse().expect("parse")]);
+    let no_annotations = BTreeMap::new();
+
+    assert_eq!(
+        paths.required_version("sensor", &Version::new(2, 3, 0), &no_annotations),
+        Some(Version::new(2, 0, 0))
+    );
+    assert_eq!(
+        paths.required_version("sensor", &Version::new(2, 0, 0), &no_annotations),
+        Some(Version::new(1, 0, 0))
+    );
+    assert_eq!(
+        paths.required_version("other", &Version::new(2, 3, 0), &no_annotations),
+        None
+    );
+
+    // The highest of the stepping stone and the annotation applies
+    let annotations = BTreeMap::from([(MIN_VERSION_ANNOTATION.to_string(), "2.1.0".to_string())]);
+    let required = min_version("sensor", &Version::new(2, 3, 0), &annotations);
+    assert_eq!(required, Some(Version::new(2, 1, 0)));
+    let annotated = BTreeMap::from([(Version::new(2, 3, 0), Version::new(2, 1, 0))]);
+    assert_eq!(
+        paths.required_version("sensor", &Version::new(2, 3, 0), &annotated),
+        Some(Version::new(2, 1, 0))
+    );
+
+    // Versions required by a release are stepping stones of every later release
+    let annotated = BTreeMap::from([(Version::new(3, 0, 0), Version::new(2, 1, 0))]);
+    assert_eq!(
+        paths.required_version("other", &Version::new(2, 3, 0), &annotated),
+        Some(Version::new(2, 1, 0))
+    );
+    assert_eq!(
+        paths.required_version("other", &Version::new(2, 1, 0), &annotated),
+        None
+    );
+
+    let invalid = BTreeMap::from([(MIN_VERSION_ANNOTATION.to_string(), "latest".to_string())]);
+    assert_eq!(
+        min_version("sensor", &Version::new(2, 3, 0), &invalid),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_version_returns_next_hop_of_stepping_stone() {
+    init_tracing();
+
+    let device_id = "device-stepping-stone";
+    let registry = upgrade_path_registry(device_id).await;
+    let app = create_app(firmware_manager(
+        &registry,
+        &[&format!("{device_id}=2.0.0")],
+    ));
+
+    let uri = |current: &str| format!("/version?device={device_id}&current={current}");
+    assert_eq!(served_version(&app, &uri("1.0.0")).await, "2.0.0");
+    assert_eq!(served_version(&app, &uri("2.0.0")).await, "2.3.0");
+    assert_eq!(served_version(&app, &uri("2.3.0")).await, "3.0.0");
+
+    // Devices that don't report their version get the latest release
+    assert_eq!(
+        served_version(&app, &format!("/version?device={device_id}")).await,
+        "3.0.0"
+    );
+}
+
+#[tokio::test]
+async fn test_version_honours_min_version_annotation() {
+    init_tracing();
+
+    let device_id = "device-min-version";
+    let registry = upgrade_path_registry(device_id).await;
+    let app = create_app(firmware_manager(&registry, &[]));
+
+    let uri = |current: &str| format!("/version?device={device_id}&current={current}");
+    assert_eq!(served_version(&app, &uri("1.0.0")).await, "2.3.0");
+    assert_eq!(served_version(&app, &uri("2.3.0")).await, "3.0.0");
+}
+
+#[tokio::test]
+async fn test_min_version_annotation_applies_to_older_releases() {
+    init_tracing();
+
+    let device_id = "device-annotated-stepping-stone";
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.0.0", b"firmware 1.0.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "2.0.0", b"firmware 2.0.0"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "2.3.0", b"firmware 2.3.0"))
+        .await
+        .with_firmware(
+            TestFirmware::new(device_id, "3.0.0", b"firmware 3.0.0")
+                .with_annotation(MIN_VERSION_ANNOTATION, "2.0.0"),
+        )
+        .await
+        .build()
+        .await;
+    let app = create_app(firmware_manager(&registry, &[]));
+
+    // 2.0.0 is required by 3.0.0, and so by 2.3.0 released after it
+    let uri = |current: &str| format!("/version?device={device_id}&current={current}");
+    assert_eq!(served_version(&app, &uri("1.0.0")).await, "2.0.0");
+    assert_eq!(served_version(&app, &uri("2.0.0")).await, "3.0.0");
+}
+
+#[tokio::test]
+async fn test_manifest_annotations_are_cached() {
+    init_tracing();
+
+    let device_id = "device-cached-annotations";
+    let registry = upgrade_path_registry(device_id).await;
+    let app = create_app(firmware_manager(&registry, &[]));
+
+    let uri = format!("/version?device={device_id}&current=1.0.0");
+    assert_eq!(served_version(&app, &uri).await, "2.3.0");
+    let resolved = registry.manifest_requests(device_id).await;
+
+    // Later polls only check the manifest of the served release
+    assert_eq!(served_version(&app, &uri).await, "2.3.0");
+    assert_eq!(registry.manifest_requests(device_id).await, resolved + 1);
+}
+
+#[tokio::test]
+async fn test_firmware_download_follows_upgrade_path() {
+    init_tracing();
+
+    let device_id = "device-stepping-stone-download";
+    let registry = upgrade_path_registry(device_id).await;
+    let app = create_app(firmware_manager(
+        &registry,
+        &[&format!("{device_id}=2.0.0")],
+    ));
+
+    let request = Request::builder()
+        .uri(format!(
+            "/version?device={device_id}&current=1.5.0&format=json"
+        ))
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+    let response = app.clone().oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let version: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    assert_eq!(version["version"], "2.0.0");
+    let url = version["url"].as_str().expect("url").to_string();
+    assert!(url.contains("current=1.5.0"), "{url}");
+
+    let request = Request::builder()
+        .uri(url)
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+    let response = app.oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_to_bytes(response.into_body()).await, b"firmware 2.0.0");
+}
@@ -0,0 +1,110 @@
+//! Hardware variant selection integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use tower::ServiceExt;
+
+use common::{body_to_bytes, body_to_string, create_app, init_tracing, MockRegistryBuilder};
+
+async fn variant_app(device_id: &str) -> axum::Router {
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware_index(
+            device_id,
+            "1.0.0",
+            vec![
+                (
+                    serde_json::json!({
+                        "platform": {
+                            "architecture": "xtensa",
+                            "os": "none",
+                            "variant": "esp32-revA"
+                        }
+                    }),
+                    b"esp32 revA firmware".to_vec(),
+                ),
+                (
+                    serde_json::json!({
+                        "platform": {
+                            "architecture": "xtensa",
+                            "os": "none",
+                            "os.features": ["esp32s3-revB"]
+                        }
+                    }),
+                    b"esp32s3 revB firmware".to_vec(),
+                ),
+                (
+                    serde_json::json!({
+                        "annotations": {
+                            "dev.otaflux.hardware": "nrf52840"
+                        }
+                    }),
+                    b"nrf52840 firmware".to_vec(),
+                ),
+            ],
+        )
+        .await
+        .build()
+        .await;
+
+    create_app(registry.firmware_manager())
+}
+
+#[tokio::test]
+async fn test_firmware_hardware_variant_selection() {
+    init_tracing();
+
+    let app = variant_app("device-variants").await;
+
+    let cases: [(&str, &[u8]); 4] = [
+        ("/firmware?device=device-variants", b"esp32 revA firmware"),
+        (
+            "/firmware?device=device-variants&hw=esp32-revA",
+            b"esp32 revA firmware",
+        ),
+        (
+            "/firmware?device=device-variants&hw=esp32s3-revB",
+            b"esp32s3 revB firmware",
+        ),
+        (
+            "/firmware?device=device-variants&hw=nrf52840",
+            b"nrf52840 firmware",
+        ),
+    ];
+
+    for (uri, expected) in cases {
+        let request = Request::builder()
+            .uri(uri)
+            .method("GET")
+            .body(Body::empty())
+            .expect("build request");
+
+        let response = app.clone().oneshot(request).await.expect("send request");
+
+        assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        assert_eq!(body_to_bytes(response.into_body()).await, expected, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn test_version_unknown_hardware_variant_returns_404() {
+    init_tracing();
+
+    let app = variant_app("device-variants-404").await;
+
+    let request = Request::builder()
+        .uri("/version?device=device-variants-404&hw=stm32f4")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = body_to_string(response.into_body()).await;
+    assert!(body.contains("hardware variant 'stm32f4'"), "{body}");
+}
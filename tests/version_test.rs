@@ -173,3 +173,165 @@ async fn test_version_endpoint_missing_device_param() {
         "Error should mention missing device parameter"
     );
 }
+
+#[tokio::test]
+async fn test_version_endpoint_if_none_match_returns_not_modified() {
+    init_tracing();
+
+    let firmware = TestFirmware::new("device-etag", "1.0.0", b"etag firmware");
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/version?device=device-etag")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.clone().oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let etag = response
+        .headers()
+        .get("etag")
+        .expect("ETag header should be present")
+        .clone();
+    assert!(
+        etag.to_str().unwrap_or("").starts_with("\"sha256:"),
+        "ETag should be derived from the manifest digest"
+    );
+
+    let request = Request::builder()
+        .uri("/version?device=device-etag")
+        .method("GET")
+        .header("If-None-Match", etag.clone())
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers().get("etag"), Some(&etag));
+
+    let body = body_to_string(response.into_body()).await;
+    assert!(body.is_empty(), "304 response must not carry a body");
+}
+
+#[tokio::test]
+async fn test_version_endpoint_up_to_date_device_gets_no_content() {
+    init_tracing();
+
+    let firmware = TestFirmware::new("device-current", "1.2.3", b"current firmware");
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware)
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    // Device already runs the latest version (query parameter)
+    let request = Request::builder()
+        .uri("/version?device=device-current&current=1.2.3")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.clone().oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // Device runs an older version (header)
+    let request = Request::builder()
+        .uri("/version?device=device-current")
+        .method("GET")
+        .header("X-Firmware-Version", "1.2.3-rc.1")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = body_to_string(response.into_body()).await;
+    assert_eq!(body.lines().next(), Some("1.2.3"));
+}
+
+#[tokio::test]
+async fn test_version_endpoint_invalid_current_version() {
+    init_tracing();
+
+    let registry = MockRegistryBuilder::new().await.build().await;
+    let app = create_app(registry.firmware_manager());
+
+    let request = Request::builder()
+        .uri("/version?device=device-001&current=not-a-version")
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+
+    let response = app.oneshot(request).await.expect("send request");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_version_endpoint_json_format() {
+    init_tracing();
+
+    let firmware = TestFirmware::new("device-json", "1.4.0", b"json firmware content");
+    let registry = MockRegistryBuilder::new()
+        .await
+        .with_firmware(firmware.clone())
+        .await
+        .build()
+        .await;
+
+    let app = create_app(registry.firmware_manager());
+
+    for request in [
+        Request::builder()
+            .uri("/version?device=device-json&format=json")
+            .method("GET")
+            .body(Body::empty())
+            .expect("build request"),
+        Request::builder()
+            .uri("/version?device=device-json")
+            .method("GET")
+            .header("Accept", "application/json")
+            .body(Body::empty())
+            .expect("build request"),
+    ] {
+        let response = app.clone().oneshot(request).await.expect("send request");
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get("content-type")
+                .map(|v| v.to_str().unwrap_or("")),
+            Some("application/json")
+        );
+
+        let body = body_to_string(response.into_body()).await;
+        let json: serde_json::Value = serde_json::from_str(&body).expect("valid JSON");
+
+        assert_eq!(json["version"], "1.4.0");
+        assert_eq!(json["crc32"], crc32fast::hash(&firmware.bytes));
+        assert_eq!(json["size"], firmware.bytes.len());
+        assert_eq!(
+            json["sha256"],
+            firmware.digest.trim_start_matches("sha256:"),
+            "SHA-256 should match the firmware binary"
+        );
+        assert!(json["manifest_digest"]
+            .as_str()
+            .is_some_and(|d| d.starts_with("sha256:")));
+        assert_eq!(json["url"], "/firmware?device=device-json");
+    }
+}
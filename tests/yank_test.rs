@@ -0,0 +1,174 @@
+//! Yanked release integration tests.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use otaflux::firmware_manager::FirmwareManager;
+use otaflux::yank::{Yank, YankedRelease};
+use semver::Version;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use common::{
+    admin_request, body_to_string, create_admin_app, create_app, get, init_tracing, MockRegistry,
+    MockRegistryBuilder, TestFirmware, ADMIN_TOKEN,
+};
+
+async fn yank_registry(device_id: &str) -> MockRegistry {
+    MockRegistryBuilder::new()
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.0.0", b"good firmware"))
+        .await
+        .with_firmware(TestFirmware::new(device_id, "1.1.0", b"bricking firmware"))
+        .await
+        .build()
+        .await
+}
+
+async fn served_version(app: &axum::Router, device_id: &str) -> String {
+    let response = app
+        .clone()
+        .oneshot(get(&format!("/version?device={device_id}")))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = body_to_string(response.into_body()).await;
+    body.lines().next().expect("version line").to_string()
+}
+
+#[test]
+fn test_yank_parsing() {
+    let yank: Yank = "esp32-sensor=v1.2.0".parse().expect("parse");
+    assert_eq!(yank.device, "esp32-sensor");
+    assert_eq!(yank.release, YankedRelease::Version(Version::new(1, 2, 0)));
+
+    let yank: Yank = "esp32-sensor=sha256:abc123".parse().expect("parse");
+    assert_eq!(
+        yank.release,
+        YankedRelease::Digest("sha256:abc123".to_string())
+    );
+
+    assert!("1.2.0".parse::<Yank>().is_err());
+    assert!("esp32-sensor=latest".parse::<Yank>().is_err());
+}
+
+#[tokio::test]
+async fn test_configured_yank_is_skipped() {
+    init_tracing();
+
+    let device_id = "device-yank-config";
+    let registry = yank_registry(device_id).await;
+    let fm = FirmwareManager::new(
+        registry.host_port(),
+        "user".to_string(),
+        "pass".to_string(),
+        true,
+        "",
+        None,
+    )
+    .expect("create firmware manager")
+    .with_yanks(vec![format!("{device_id}=1.1.0").parse().expect("parse")]);
+
+    let app = create_app(Arc::new(fm));
+    assert_eq!(served_version(&app, device_id).await, "1.0.0");
+}
+
+#[tokio::test]
+async fn test_admin_api_yanks_and_restores_version() {
+    init_tracing();
+
+    let device_id = "device-yank-version";
+    let registry = yank_registry(device_id).await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    // Cache the release before yanking it
+    assert_eq!(served_version(&app, device_id).await, "1.1.0");
+
+    let uri = format!("/admin/yanks/{device_id}/1.1.0");
+    let response = app
+        .clone()
+        .oneshot(admin_request("PUT", &uri, None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(served_version(&app, device_id).await, "1.0.0");
+
+    let response = app
+        .clone()
+        .oneshot(admin_request("GET", "/admin/yanks", None))
+        .await
+        .expect("send request");
+    let yanks: serde_json::Value =
+        serde_json::from_str(&body_to_string(response.into_body()).await).expect("parse JSON");
+    assert_eq!(
+        yanks,
+        serde_json::json!([{ "device": device_id, "release": "1.1.0" }])
+    );
+
+    let response = app
+        .clone()
+        .oneshot(admin_request("DELETE", &uri, None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(served_version(&app, device_id).await, "1.1.0");
+
+    let response = app
+        .clone()
+        .oneshot(admin_request("DELETE", &uri, None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_admin_api_yanks_manifest_digest() {
+    init_tracing();
+
+    let device_id = "device-yank-digest";
+    let registry = yank_registry(device_id).await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    let request = Request::builder()
+        .uri(format!("/firmware?device={device_id}"))
+        .method("GET")
+        .body(Body::empty())
+        .expect("build request");
+    let response = app.clone().oneshot(request).await.expect("send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let digest = response.headers()[header::ETAG]
+        .to_str()
+        .expect("ETag header")
+        .trim_matches('"')
+        .to_string();
+
+    let response = app
+        .clone()
+        .oneshot(admin_request(
+            "PUT",
+            &format!("/admin/yanks/{device_id}/{digest}"),
+            None,
+        ))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(served_version(&app, device_id).await, "1.0.0");
+}
+
+#[tokio::test]
+async fn test_admin_api_rejects_invalid_yank() {
+    init_tracing();
+
+    let registry = MockRegistryBuilder::new().await.build().await;
+    let app = create_admin_app(registry.firmware_manager(), ADMIN_TOKEN);
+
+    let response = app
+        .oneshot(admin_request("PUT", "/admin/yanks/device/latest", None))
+        .await
+        .expect("send request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}